@@ -0,0 +1,9 @@
+; Adds two numbers and halts
+        LDA x
+        ADD y
+        OUT
+        HLT
+
+.org 14
+x:      .byte 14
+y:      .byte 28
@@ -0,0 +1,19 @@
+; Multiplies two numbers by repeated addition and halts, for the extended
+; machine (asm --extended, or run with --extended)
+loop:   LDA product
+        ADD x
+        STA product
+        LDA y
+        SUB one
+        STA y
+        JZ done
+        JMP loop
+done:   LDA product
+        OUT
+        HLT
+
+.org 0x80
+x:       .byte 7
+y:       .byte 6
+one:     .byte 1
+product: .byte 0
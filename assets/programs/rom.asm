@@ -0,0 +1,26 @@
+; Multiplies two numbers by repeated addition and halts, from the ROM of
+; assets/machines/rom.toml: code and constants live below 0x80, variables in
+; RAM above it
+        LDI 6
+        STA y
+        LDI 0
+        STA product
+loop:   LDA product
+        ADD x
+        STA product
+        LDA y
+        SUB one
+        STA y
+        JZ done
+        JMP loop
+done:   LDA product
+        OUT
+        HLT
+
+.org 0x70
+x:       .byte 7
+one:     .byte 1
+
+.org 0x80
+y:       .byte 0
+product: .byte 0
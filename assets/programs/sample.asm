@@ -0,0 +1,12 @@
+; Increments A to 255 then decrements it down to 0 and repeats
+up:     OUT
+        ADD one
+        JC down
+        JMP up
+down:   SUB one
+        OUT
+        JZ up
+        JMP down
+
+.org 15
+one:    .byte 1
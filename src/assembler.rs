@@ -0,0 +1,297 @@
+// Assembler for the instruction set defined in `isa`.
+//
+// Syntax, one statement per line:
+//
+//     ; comments start with a semicolon
+//     start:  LDA x       ; labels end with a colon
+//             ADD 0xf     ; operands are decimal, 0x hex or 0b binary numbers,
+//             JC end      ; or labels
+//             JMP start
+//     end:    HLT
+//     .org 14             ; move the location counter
+//     x:      .byte 14, 28
+//
+// Programs for the extended machine are assembled the same way, each
+// instruction with an operand taking two bytes.
+use crate::isa::{self, Encoding, Operand};
+use crate::modules::RAM_SIZE;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnknownMnemonic(String),
+    UnknownDirective(String),
+    InvalidNumber(String),
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    MissingOperand(&'static str),
+    UnexpectedOperand(&'static str),
+    OperandOutOfRange {
+        value: i64,
+        min: i64,
+        max: i64,
+    },
+    /// Address past the end of RAM, and the size of RAM
+    Overflow {
+        address: usize,
+        size: usize,
+    },
+    Overlap(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    /// 1-based line number in the source
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use ErrorKind::*;
+        match self {
+            UnknownMnemonic(m) => write!(f, "unknown mnemonic `{}`", m),
+            UnknownDirective(d) => write!(f, "unknown directive `{}`", d),
+            InvalidNumber(n) => write!(f, "invalid number `{}`", n),
+            InvalidLabel(l) => write!(f, "invalid label name `{}`", l),
+            DuplicateLabel(l) => write!(f, "label `{}` is already defined", l),
+            UndefinedLabel(l) => write!(f, "undefined label `{}`", l),
+            MissingOperand(m) => write!(f, "{} expects an operand", m),
+            UnexpectedOperand(m) => write!(f, "{} does not take an operand", m),
+            OperandOutOfRange { value, min, max } => write!(
+                f,
+                "operand {} is out of range (expected {} to {})",
+                value, min, max
+            ),
+            Overflow { address, size } => write!(
+                f,
+                "address {:#x} is outside of the {} bytes of RAM",
+                address, size
+            ),
+            Overlap(address) => write!(f, "address {:#x} is written twice", address),
+        }
+    }
+}
+
+impl Display for AssemblyError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+enum Statement<'a> {
+    Instruction(&'static isa::Instruction, Option<&'a str>),
+    Bytes(Vec<&'a str>),
+    Org(&'a str),
+}
+
+struct Line<'a> {
+    number: usize,
+    label: Option<&'a str>,
+    statement: Option<Statement<'a>>,
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub(crate) fn parse_number(s: &str) -> Option<i64> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let lower = s.to_ascii_lowercase();
+    let value = if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        lower.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_line(number: usize, line: &str) -> Result<Line<'_>, AssemblyError> {
+    let error = |kind| AssemblyError { line: number, kind };
+    let mut rest = line.split(';').next().unwrap_or("").trim();
+    let mut label = None;
+    if let Some(colon) = rest.find(':') {
+        let name = rest[..colon].trim();
+        if !is_identifier(name) {
+            return Err(error(ErrorKind::InvalidLabel(name.to_string())));
+        }
+        label = Some(name);
+        rest = rest[colon + 1..].trim();
+    }
+    if rest.is_empty() {
+        return Ok(Line {
+            number,
+            label,
+            statement: None,
+        });
+    }
+    let (word, operands) = match rest.find(char::is_whitespace) {
+        Some(i) => (&rest[..i], rest[i..].trim()),
+        None => (rest, ""),
+    };
+    let statement = if let Some(directive) = word.strip_prefix('.') {
+        match directive.to_ascii_lowercase().as_str() {
+            "byte" if !operands.is_empty() => {
+                Statement::Bytes(operands.split(',').map(str::trim).collect())
+            }
+            "byte" => return Err(error(ErrorKind::MissingOperand(".byte"))),
+            "org" if !operands.is_empty() => Statement::Org(operands),
+            "org" => return Err(error(ErrorKind::MissingOperand(".org"))),
+            _ => return Err(error(ErrorKind::UnknownDirective(word.to_string()))),
+        }
+    } else {
+        let instruction = isa::by_mnemonic(word)
+            .ok_or_else(|| error(ErrorKind::UnknownMnemonic(word.to_string())))?;
+        match (instruction.operand, operands.is_empty()) {
+            (Operand::None, true) => Statement::Instruction(instruction, None),
+            (Operand::None, false) => {
+                return Err(error(ErrorKind::UnexpectedOperand(instruction.mnemonic)))
+            }
+            (_, true) => return Err(error(ErrorKind::MissingOperand(instruction.mnemonic))),
+            (_, false) => Statement::Instruction(instruction, Some(operands)),
+        }
+    };
+    Ok(Line {
+        number,
+        label,
+        statement: Some(statement),
+    })
+}
+
+struct Assembler {
+    labels: HashMap<String, usize>,
+}
+
+impl Assembler {
+    fn value(&self, line: usize, operand: &str, min: i64, max: i64) -> Result<i64, AssemblyError> {
+        let error = |kind| AssemblyError { line, kind };
+        let value = if is_identifier(operand) {
+            *self
+                .labels
+                .get(operand)
+                .ok_or_else(|| error(ErrorKind::UndefinedLabel(operand.to_string())))?
+                as i64
+        } else {
+            parse_number(operand)
+                .ok_or_else(|| error(ErrorKind::InvalidNumber(operand.to_string())))?
+        };
+        if value < min || value > max {
+            return Err(error(ErrorKind::OperandOutOfRange { value, min, max }));
+        }
+        Ok(value)
+    }
+}
+
+/// Assembles a program into a RAM image. Bytes that are not explicitly
+/// written by the program are left at 0.
+pub fn assemble(source: &str) -> Result<[u8; RAM_SIZE], AssemblyError> {
+    let image = assemble_with(source, Encoding::Compact)?;
+    let mut ram = [0; RAM_SIZE];
+    ram.copy_from_slice(&image);
+    Ok(ram)
+}
+
+/// Assembles a program into an image of the whole RAM of a machine using
+/// `encoding`
+pub fn assemble_with(source: &str, encoding: Encoding) -> Result<Vec<u8>, AssemblyError> {
+    assemble_image(source, encoding).map(|(image, _)| image)
+}
+
+/// Assembles a program like `assemble_with`, also returning the address past
+/// the last byte the program writes: the rest of the image is padding
+pub fn assemble_image(source: &str, encoding: Encoding) -> Result<(Vec<u8>, usize), AssemblyError> {
+    let size = encoding.ram_size();
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| parse_line(i + 1, line))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // First pass: compute the address of every label
+    let mut assembler = Assembler {
+        labels: HashMap::new(),
+    };
+    let mut address = 0;
+    for line in &lines {
+        if let Some(label) = line.label {
+            if assembler
+                .labels
+                .insert(label.to_string(), address)
+                .is_some()
+            {
+                return Err(AssemblyError {
+                    line: line.number,
+                    kind: ErrorKind::DuplicateLabel(label.to_string()),
+                });
+            }
+        }
+        match line.statement {
+            Some(Statement::Instruction(instruction, _)) => address += instruction.size(encoding),
+            Some(Statement::Bytes(ref bytes)) => address += bytes.len(),
+            Some(Statement::Org(operand)) => {
+                address = assembler.value(line.number, operand, 0, size as i64 - 1)? as usize;
+            }
+            None => (),
+        }
+    }
+
+    // Second pass: emit the bytes
+    let mut ram = vec![0; size];
+    let mut written = vec![false; size];
+    let mut address = 0;
+    for line in &lines {
+        let bytes = match line.statement {
+            Some(Statement::Instruction(instruction, operand)) => {
+                let max = encoding.max_operand() as i64;
+                let operand = match operand {
+                    Some(operand) => assembler.value(line.number, operand, 0, max)? as u8,
+                    None => 0,
+                };
+                instruction.encode_with(encoding, operand)
+            }
+            Some(Statement::Bytes(ref values)) => values
+                .iter()
+                .map(|value| Ok(assembler.value(line.number, value, -128, 0xff)? as u8))
+                .collect::<Result<_, _>>()?,
+            Some(Statement::Org(operand)) => {
+                address = assembler.value(line.number, operand, 0, size as i64 - 1)? as usize;
+                continue;
+            }
+            None => continue,
+        };
+        for byte in bytes {
+            let error = |kind| AssemblyError {
+                line: line.number,
+                kind,
+            };
+            if address >= size {
+                return Err(error(ErrorKind::Overflow { address, size }));
+            }
+            if written[address] {
+                return Err(error(ErrorKind::Overlap(address)));
+            }
+            ram[address] = byte;
+            written[address] = true;
+            address += 1;
+        }
+    }
+    let end = written
+        .iter()
+        .rposition(|&written| written)
+        .map_or(0, |last| last + 1);
+    Ok((ram, end))
+}
@@ -0,0 +1,63 @@
+use breadboard_8bit::assembler::assemble_with;
+use breadboard_8bit::isa::Encoding;
+use clap::{App, Arg};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+fn main() {
+    let matches = App::new("8bit computer assembler")
+        .version("0.1.1")
+        .author("Aymeric Beringer <aymeric@beringer.cf>")
+        .arg(
+            Arg::with_name("input")
+                .value_name("SOURCE")
+                .help("Assembly source file")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .help(concat!(
+                    "Write the RAM image to FILE (defaults to the source file ",
+                    "with a .bin extension, - for stdout)"
+                ))
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("extended").long("extended").help(concat!(
+            "Assemble for the extended machine, with 256 bytes of RAM and ",
+            "instructions followed by their operand"
+        )))
+        .get_matches();
+    let input = matches.value_of("input").unwrap();
+    let source = match fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", input, e);
+            std::process::exit(1);
+        }
+    };
+    let encoding = if matches.is_present("extended") {
+        Encoding::Extended
+    } else {
+        Encoding::Compact
+    };
+    let ram = match assemble_with(&source, encoding) {
+        Ok(ram) => ram,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            std::process::exit(1);
+        }
+    };
+    let result = match matches.value_of("output") {
+        Some("-") => io::stdout().write_all(&ram),
+        Some(output) => fs::write(output, &ram),
+        None => fs::write(Path::new(input).with_extension("bin"), &ram),
+    };
+    if let Err(e) = result {
+        eprintln!("Could not write RAM image: {}", e);
+        std::process::exit(1);
+    }
+}
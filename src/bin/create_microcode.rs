@@ -1,91 +1,272 @@
-use breadboard_8bit::modules::{ControlFlag, ControlWord};
-use std::io::{self, BufWriter, Write};
+use breadboard_8bit::eeprom::{parse_active_low, ChipLayout};
+use breadboard_8bit::microcode_compiler::compile;
+use breadboard_8bit::modules::{
+    AddressLayout, ControlFlag, ControlNames, ControlWord, MicrocodeAddress, MicrocodeFile,
+    DEFAULT_STEPS, MICROCODE_SIZE,
+};
+use clap::{App, Arg, ArgMatches};
+use std::fs;
 
-// Argument to decoder is in the form
+// Control word of every address of the layout
 // F F F F    I I I I   S S S S
 // ^ ^ ^ ^    ^ ^ ^ ^   ^ ^ ^ ^
 // flags    instruction  step
-fn write_microcode<F: Fn(u16) -> u32, W: Write>(writer: &mut W, decoder: F) -> io::Result<()> {
-    for i in 0..(1 << 12) {
-        let cw = decoder(i);
-        writer.write_all(&cw.to_le_bytes())?;
+fn build_microcode<F: Fn(MicrocodeAddress) -> u32>(
+    layout: AddressLayout,
+    names: ControlNames,
+    steps: usize,
+    decoder: F,
+) -> Result<MicrocodeFile, String> {
+    if steps > layout.max_steps() {
+        return Err(format!(
+            "The microcode has {} steps, but the step lines of layout {} can only count {}",
+            steps,
+            layout,
+            layout.max_steps()
+        ));
     }
-    Ok(())
-}
-
-struct MicrocodeAddress {
-    flags: u8,
-    instruction: u8,
-    step: u8,
+    Ok(MicrocodeFile {
+        layout,
+        microcode: (0..layout.size()).map(|address| decoder(layout.unpack(address))).collect(),
+        names,
+        steps: steps as u8,
+    })
 }
 
-fn unpack_microcode_address(address: u16) -> MicrocodeAddress {
-    let flags = ((address >> 8) & 0b1111) as u8;
-    let instruction = ((address >> 4) & 0b1111) as u8;
-    let step = (address & 0b1111) as u8;
-    MicrocodeAddress {
-        flags,
-        instruction,
-        step,
-    }
-}
-
-fn sample_decoder(address: u16) -> u32 {
+fn sample_decoder(address: MicrocodeAddress) -> u32 {
+    use breadboard_8bit::isa::*;
     use ControlFlag::*;
 
     let MicrocodeAddress {
         flags,
         instruction,
         step,
-    } = unpack_microcode_address(address);
+    } = address;
     let carry = flags & 0b10 > 0;
     let zero = flags & 0b01 > 0;
     match (instruction, step) {
         (_, 0) => CounterOut | MemoryAddressIn,
         (_, 1) => RamOut | InstructionRegisterIn | CounterEnable,
 
-        // LDA
-        (0x1, 2) => InstructionRegisterOut | MemoryAddressIn,
-        (0x1, 3) => RamOut | ARegisterIn | NextInstruction,
+        (LDA, 2) => InstructionRegisterOut | MemoryAddressIn,
+        (LDA, 3) => RamOut | ARegisterIn | NextInstruction,
 
-        // ADD
-        (0x2, 2) => InstructionRegisterOut | MemoryAddressIn,
-        (0x2, 3) => RamOut | BRegisterIn,
-        (0x2, 4) => SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
+        (ADD, 2) => InstructionRegisterOut | MemoryAddressIn,
+        (ADD, 3) => RamOut | BRegisterIn,
+        (ADD, 4) => SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
 
-        // SUB
-        (0x3, 2) => InstructionRegisterOut | MemoryAddressIn,
-        (0x3, 3) => RamOut | BRegisterIn,
-        (0x3, 4) => Subtract | SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
+        (SUB, 2) => InstructionRegisterOut | MemoryAddressIn,
+        (SUB, 3) => RamOut | BRegisterIn,
+        (SUB, 4) => Subtract | SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
 
-        // STA
-        (0x4, 2) => InstructionRegisterOut | MemoryAddressIn,
-        (0x4, 3) => ARegisterOut | RamIn | NextInstruction,
+        (STA, 2) => InstructionRegisterOut | MemoryAddressIn,
+        (STA, 3) => ARegisterOut | RamIn | NextInstruction,
 
-        // LDI
-        (0x5, 2) => InstructionRegisterOut | ARegisterIn | NextInstruction,
+        (LDI, 2) => InstructionRegisterOut | ARegisterIn | NextInstruction,
 
-        //JMP
-        (0x6, 2) => InstructionRegisterOut | Jump | NextInstruction,
+        (JMP, 2) => InstructionRegisterOut | Jump | NextInstruction,
 
-        // JC
-        (0x7, 2) if carry => InstructionRegisterOut | Jump | NextInstruction,
-        (0x7, 2) => Empty | NextInstruction,
+        (JC, 2) if carry => InstructionRegisterOut | Jump | NextInstruction,
+        (JC, 2) => Empty | NextInstruction,
 
-        // JZ
-        (0x8, 2) if zero => InstructionRegisterOut | Jump | NextInstruction,
-        (0x8, 2) => Empty | NextInstruction,
+        (JZ, 2) if zero => InstructionRegisterOut | Jump | NextInstruction,
+        (JZ, 2) => Empty | NextInstruction,
 
-        // OUT
-        (0xe, 2) => ARegisterOut | OutputRegisterIn | NextInstruction,
+        (OUT, 2) => ARegisterOut | OutputRegisterIn | NextInstruction,
 
-        // Hlt
-        (0xf, 2) => ControlWord(Hlt as u32),
+        (HLT, 2) => ControlWord(Hlt as u32),
         _ => ControlWord(0),
     }.0
 }
 
-fn main() -> io::Result<()> {
-    let file = std::fs::File::create("microcode")?;
-    write_microcode(&mut BufWriter::new(file), sample_decoder)
+fn main() {
+    let matches = App::new("8bit computer microcode compiler")
+        .version("0.1.1")
+        .author("Aymeric Beringer <aymeric@beringer.cf>")
+        .arg(
+            Arg::with_name("input")
+                .value_name("FILE")
+                .help("Microcode description, the built-in decoder is used if omitted"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("FILE")
+                .default_value("microcode")
+                .help("Where to write the microcode image"),
+        )
+        .arg(
+            Arg::with_name("raw")
+                .long("raw")
+                .conflicts_with("chips")
+                .help("Write the bare control words, as older versions did, instead of a microcode file"),
+        )
+        .arg(
+            Arg::with_name("chips")
+                .long("chips")
+                .value_name("N")
+                .help(concat!("Split the control word across N 8-bit EEPROMs, writing ",
+                              "one image per chip to OUTPUT.0, OUTPUT.1... (chip 0 holds ",
+                              "the least significant byte)")),
+        )
+        .arg(
+            Arg::with_name("layout")
+                .long("layout")
+                .value_name("FIELDS")
+                .default_value("flags:4,instruction:4,step:4")
+                .help(concat!("Address lines of the microcode, most significant first, with ",
+                              "their width, e.g. flags:2,select:1,instruction:4,step:3")),
+        )
+        .arg(
+            Arg::with_name("active-low")
+                .long("active-low")
+                .value_name("FLAGS")
+                .requires("chips")
+                .help("Control lines to invert: comma-separated flags, none or all [default: none]"),
+        )
+        .arg(
+            Arg::with_name("combined")
+                .long("combined")
+                .requires("chips")
+                .help(concat!("Write a single image for every chip instead, the chip ",
+                              "number being the select lines of the layout, or the upper ",
+                              "address lines if it has none")),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .requires("chips")
+                .help("Compare the chip images at OUTPUT with the microcode instead of writing them"),
+        )
+        .get_matches();
+    let (decoder, names, steps): (Box<dyn Fn(MicrocodeAddress) -> u32>, _, _) = match matches.value_of("input") {
+        Some(filename) => {
+            let source = fs::read_to_string(filename).unwrap_or_else(|e| {
+                eprintln!("Could not read {}: {}", filename, e);
+                std::process::exit(1);
+            });
+            let compiled = compile(&source).unwrap_or_else(|errors| {
+                for error in errors {
+                    eprintln!("{}: {}", filename, error);
+                }
+                std::process::exit(1);
+            });
+            let microcode = compiled.microcode;
+            (
+                Box::new(move |address| microcode[AddressLayout::default().pack(address)]),
+                compiled.names,
+                compiled.steps,
+            )
+        }
+        None => (Box::new(sample_decoder), ControlNames::default(), DEFAULT_STEPS),
+    };
+    let output = matches.value_of("output").unwrap();
+    let layout: AddressLayout = match matches.value_of("layout").unwrap().parse() {
+        Ok(layout) => layout,
+        Err(e) => {
+            eprintln!("Invalid layout: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let result = match matches.value_of("chips") {
+        Some(chips) => chip_layout(chips, layout, &matches).and_then(|layout| {
+            let microcode: Vec<u32> = (0..MICROCODE_SIZE)
+                .map(|address| decoder(AddressLayout::default().unpack(address)))
+                .collect();
+            if steps != DEFAULT_STEPS {
+                println!("The chips do not record the step count, run them with --steps {}", steps);
+            }
+            if matches.is_present("verify") {
+                verify_chips(&layout, &microcode, output, matches.is_present("combined"))
+            } else {
+                write_chips(&layout, &microcode, output, matches.is_present("combined"))
+            }
+        }),
+        None => {
+            let bytes = build_microcode(layout, names, steps, decoder).and_then(|file| {
+                if matches.is_present("raw") {
+                    file.to_raw_bytes()
+                } else {
+                    file.to_bytes()
+                }
+            });
+            bytes.and_then(|bytes| {
+                fs::write(output, bytes).map_err(|e| format!("Could not write {}: {}", output, e))
+            })
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn chip_layout(chips: &str, layout: AddressLayout, matches: &ArgMatches) -> Result<ChipLayout, String> {
+    let chips = match chips.parse() {
+        Ok(chips) if (1..=4).contains(&chips) => chips,
+        _ => return Err(format!("Invalid chip count {}, expected 1 to 4", chips)),
+    };
+    Ok(ChipLayout {
+        chips,
+        layout,
+        active_low: parse_active_low(matches.value_of("active-low").unwrap_or("none"))?,
+    })
+}
+
+fn chip_paths(layout: &ChipLayout, output: &str, combined: bool) -> Vec<String> {
+    if combined {
+        vec![output.to_string()]
+    } else {
+        (0..layout.chips).map(|chip| format!("{}.{}", output, chip)).collect()
+    }
+}
+
+fn write_chips(layout: &ChipLayout, microcode: &[u32], output: &str, combined: bool) -> Result<(), String> {
+    if layout.chips < 4 && microcode.iter().any(|word| word >> (8 * layout.chips) != 0) {
+        eprintln!(
+            "Warning: {} chips only hold {} control bits, the other ones are dropped",
+            layout.chips,
+            8 * layout.chips
+        );
+    }
+    let images = if combined {
+        vec![layout.export_combined(microcode)?]
+    } else {
+        layout.export(microcode)?
+    };
+    for (path, image) in chip_paths(layout, output, combined).iter().zip(images) {
+        fs::write(path, image).map_err(|e| format!("Could not write {}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn verify_chips(layout: &ChipLayout, microcode: &[u32], output: &str, combined: bool) -> Result<(), String> {
+    let mut images = Vec::new();
+    for path in chip_paths(layout, output, combined) {
+        images.push(fs::read(&path).map_err(|e| format!("Could not read {}: {}", path, e))?);
+    }
+    if combined {
+        images = layout.split_combined(&images[0])?;
+    }
+    let dumped = layout.import(&images)?;
+    // What the chips can hold of the microcode
+    let expected = layout.import(&layout.export(microcode)?)?;
+    let mismatches: Vec<usize> = (0..MICROCODE_SIZE)
+        .filter(|&address| dumped[address] != expected[address])
+        .collect();
+    for &address in mismatches.iter().take(16) {
+        println!(
+            "{:#05x}: expected {:#010x}, found {:#010x}",
+            address,
+            expected[address],
+            dumped[address]
+        );
+    }
+    if mismatches.is_empty() {
+        println!("The chips match the microcode");
+        Ok(())
+    } else {
+        Err(format!("{} addresses differ", mismatches.len()))
+    }
 }
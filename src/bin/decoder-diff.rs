@@ -0,0 +1,204 @@
+use breadboard_8bit::breadboard_builder::BreadboardBuilder;
+use breadboard_8bit::decoder_diff::{compare, lockstep, LockstepOutcome, Probe};
+use breadboard_8bit::microcode_compiler::compile;
+use breadboard_8bit::modules::{
+    AddressLayout, AnyDecoder, BranchingInstructionDecoder, ExtendedInstructionDecoder,
+    MicrocodeDecoder, MicrocodeFile, SimpleInstructionDecoder,
+};
+use breadboard_8bit::program::{load_program, ProgramFormat};
+use breadboard_8bit::shareable::Shared;
+use breadboard_8bit::trace::Tracer;
+use clap::{App, Arg};
+use std::fs;
+
+const EXIT_ERROR: i32 = 2;
+
+/// A decoder given on the command line
+enum Spec {
+    Simple,
+    Branching,
+    Extended,
+    Microcode(MicrocodeFile),
+}
+
+impl Spec {
+    /// `simple`, `branching`, `extended`, a microcode source ending in `.mc` or a
+    /// microcode image
+    fn load(s: &str) -> Result<Self, String> {
+        match s {
+            "simple" => Ok(Spec::Simple),
+            "branching" => Ok(Spec::Branching),
+            "extended" => Ok(Spec::Extended),
+            _ if s.ends_with(".mc") => {
+                let source = fs::read_to_string(s).map_err(|e| format!("{}: {}", s, e))?;
+                let compiled = compile(&source).map_err(|errors| {
+                    let errors: Vec<String> =
+                        errors.iter().map(|e| format!("{}: {}", s, e)).collect();
+                    errors.join("\n")
+                })?;
+                Ok(Spec::Microcode(MicrocodeFile {
+                    layout: AddressLayout::default(),
+                    microcode: compiled.microcode.to_vec(),
+                    names: compiled.names,
+                    steps: compiled.steps as u8,
+                }))
+            }
+            _ => MicrocodeFile::load(s).map(Spec::Microcode),
+        }
+    }
+
+    fn decoder(&self, instruction: Shared<u8>, flags: Shared<u8>) -> Result<AnyDecoder, String> {
+        Ok(match self {
+            Spec::Simple => AnyDecoder::Simple(SimpleInstructionDecoder::new(instruction)),
+            Spec::Branching => {
+                AnyDecoder::Branching(BranchingInstructionDecoder::new(instruction, flags))
+            }
+            Spec::Extended => {
+                AnyDecoder::Extended(ExtendedInstructionDecoder::new(instruction, flags))
+            }
+            Spec::Microcode(file) => AnyDecoder::Microcode(MicrocodeDecoder::with_file(
+                instruction,
+                flags,
+                file.clone(),
+            )?),
+        })
+    }
+}
+
+fn main() {
+    let matches = App::new("8bit computer decoder comparison")
+        .version("0.1.1")
+        .author("Aymeric Beringer <aymeric@beringer.cf>")
+        .arg(
+            Arg::with_name("left")
+                .value_name("LEFT")
+                .help("simple, branching, extended, a microcode source (.mc) or a microcode image")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("right")
+                .value_name("RIGHT")
+                .help("Decoder to compare with, given like LEFT")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("lockstep")
+                .long("lockstep")
+                .requires("program")
+                .help(
+                    "Run a program on both decoders and report the first cycle where they differ",
+                ),
+        )
+        .arg(
+            Arg::with_name("program")
+                .short("p")
+                .long("program")
+                .value_name("FILE")
+                .help("Program to run with --lockstep"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["bin", "hex", "asm"])
+                .help("Format of the program, detected from the file if omitted"),
+        )
+        .arg(
+            Arg::with_name("max-cycles")
+                .long("max-cycles")
+                .value_name("N")
+                .help("Give up on --lockstep after N cycles (10000 by default)"),
+        )
+        .arg(
+            Arg::with_name("extended")
+                .long("extended")
+                .help("Run --lockstep on the extended machine, with 256 bytes of RAM"),
+        )
+        .get_matches();
+    let spec = |name| {
+        Spec::load(matches.value_of(name).unwrap()).unwrap_or_else(|e| {
+            eprintln!("Could not load decoder: {}", e);
+            std::process::exit(EXIT_ERROR);
+        })
+    };
+    let (left, right) = (spec("left"), spec("right"));
+    let code = if matches.is_present("lockstep") {
+        let filename = matches.value_of("program").unwrap();
+        let format = matches
+            .value_of("format")
+            .map(|f| f.parse::<ProgramFormat>().unwrap());
+        let machine = if matches.is_present("extended") {
+            BreadboardBuilder::extended()
+        } else {
+            BreadboardBuilder::sap1()
+        };
+        let size = machine.ram_size().unwrap_or(0);
+        let image =
+            load_program(filename, format, machine.get_encoding(), size).unwrap_or_else(|e| {
+                eprintln!("Could not load program: {}", e);
+                std::process::exit(EXIT_ERROR);
+            });
+        let max_cycles = matches.value_of("max-cycles").unwrap_or("10000");
+        let max_cycles = max_cycles.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid cycle count {}", max_cycles);
+            std::process::exit(EXIT_ERROR);
+        });
+        let build = |spec: &Spec| {
+            let ram_init = |ram: &mut [u8]| ram[..image.len()].copy_from_slice(&image);
+            machine
+                .build(ram_init, |instruction, flags| {
+                    spec.decoder(instruction, flags)
+                })
+                .map(|state| state.with_tracer(Tracer::none()))
+                .unwrap_or_else(|e| {
+                    eprintln!("Could not build the machine: {}", e);
+                    std::process::exit(EXIT_ERROR);
+                })
+        };
+        match lockstep(&mut build(&left), &mut build(&right), max_cycles) {
+            LockstepOutcome::Halted { cycles } => {
+                println!(
+                    "Both machines halted after {} cycles without differing",
+                    cycles
+                );
+                0
+            }
+            LockstepOutcome::CycleLimit { cycles } => {
+                println!("No difference in {} cycles", cycles);
+                0
+            }
+            LockstepOutcome::Diverged(divergence) => {
+                println!("Diverged at {}", divergence);
+                1
+            }
+        }
+    } else {
+        let probe = |spec: &Spec| {
+            Probe::new(|instruction, flags| spec.decoder(instruction, flags)).unwrap_or_else(|e| {
+                eprintln!("Could not build the decoder: {}", e);
+                std::process::exit(EXIT_ERROR);
+            })
+        };
+        let (mut l, mut r) = (probe(&left), probe(&right));
+        if l.steps() != r.steps() {
+            println!(
+                "Note: the decoders wrap after {} and {} steps, only the first {} are compared",
+                l.steps(),
+                r.steps(),
+                l.steps().min(r.steps())
+            );
+        }
+        let mismatches = compare(&mut l, &mut r);
+        for mismatch in mismatches.iter() {
+            println!("{}", mismatch);
+        }
+        println!("{} mismatches", mismatches.len());
+        if mismatches.is_empty() {
+            0
+        } else {
+            1
+        }
+    };
+    std::process::exit(code);
+}
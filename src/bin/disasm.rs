@@ -0,0 +1,53 @@
+use breadboard_8bit::disassembler::disassemble_with;
+use breadboard_8bit::isa::Encoding;
+use breadboard_8bit::program::{load_program, ProgramFormat};
+use clap::{App, Arg};
+
+fn main() {
+    let matches = App::new("8bit computer disassembler")
+        .version("0.1.1")
+        .author("Aymeric Beringer <aymeric@beringer.cf>")
+        .arg(
+            Arg::with_name("input")
+                .value_name("FILE")
+                .help("RAM image to disassemble")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["bin", "hex", "asm"])
+                .help("Format of the RAM image, detected from the file if omitted"),
+        )
+        .arg(
+            Arg::with_name("extended")
+                .long("extended")
+                .help("Disassemble a program for the extended machine, with 256 bytes of RAM"),
+        )
+        .get_matches();
+    let format = matches
+        .value_of("format")
+        .map(|f| f.parse::<ProgramFormat>().unwrap());
+    let encoding = if matches.is_present("extended") {
+        Encoding::Extended
+    } else {
+        Encoding::Compact
+    };
+    let image = match load_program(
+        matches.value_of("input").unwrap(),
+        format,
+        encoding,
+        encoding.ram_size(),
+    ) {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Could not load RAM image: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for line in disassemble_with(&image, encoding) {
+        println!("{}", line);
+    }
+}
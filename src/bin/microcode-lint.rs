@@ -0,0 +1,42 @@
+use breadboard_8bit::microcode_lint::{lint_file, Severity};
+use breadboard_8bit::modules::MicrocodeFile;
+use clap::{App, Arg};
+
+fn main() {
+    let matches = App::new("8bit computer microcode linter")
+        .version("0.1.1")
+        .author("Aymeric Beringer <aymeric@beringer.cf>")
+        .arg(
+            Arg::with_name("input")
+                .value_name("FILE")
+                .help("Microcode image to check")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("deny-warnings")
+                .long("deny-warnings")
+                .help("Exit with an error on warnings too"),
+        )
+        .get_matches();
+    let filename = matches.value_of("input").unwrap();
+    let file = match MicrocodeFile::load(filename) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Could not read microcode: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let diagnostics = lint_file(&file);
+    for diagnostic in diagnostics.iter() {
+        println!("{}", diagnostic);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity() == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    println!("{} errors, {} warnings", errors, warnings);
+    if errors > 0 || (warnings > 0 && matches.is_present("deny-warnings")) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,683 @@
+// Assembling machines from a list of modules, either in code or from a TOML
+// or RON description, instead of wiring them by hand
+use crate::graphics::GraphicalModule;
+use crate::isa::Encoding;
+use crate::modules::*;
+use crate::program::load_program;
+use crate::shareable::{Share, Shared};
+use crate::state::{BreadboardState, Modules};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::convert::AsRef;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A module of the machine and the modules whose values it observes, which
+/// are given by name
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ModuleSpec {
+    /// Latches the bus on `read` and drives it on `write`
+    Register {
+        name: String,
+        #[serde(deserialize_with = "flag")]
+        read: ControlFlag,
+        #[serde(default, deserialize_with = "optional_flag")]
+        write: Option<ControlFlag>,
+    },
+    /// Adds or subtracts two registers, its value being the flags
+    Alu {
+        a: String,
+        b: String,
+    },
+    /// Shows the flags of an ALU
+    Flags {
+        alu: String,
+    },
+    /// `size` bytes (16 by default) addressed by the low bits of a register
+    Ram {
+        address: String,
+        #[serde(default = "default_ram_size")]
+        size: usize,
+    },
+    /// A fixed program at `start`, which RAM observing the same register
+    /// leaves to it. Descriptions give a `program` file laid out like RAM,
+    /// of which the ROM holds `size` bytes from `start` on (the rest of the
+    /// file by default). It is named after `start`, e.g. `ROM@0x00`, unless
+    /// given a `name`.
+    Rom {
+        #[serde(default)]
+        name: Option<String>,
+        address: String,
+        #[serde(default)]
+        start: usize,
+        #[serde(default)]
+        size: Option<usize>,
+        #[serde(default)]
+        program: Option<PathBuf>,
+        #[serde(skip)]
+        contents: Vec<u8>,
+    },
+    InstructionRegister,
+    ProgramCounter {
+        #[serde(default = "default_counter_bits")]
+        bits: u8,
+    },
+    Output,
+    /// Shows the step of the decoder
+    DecoderStep,
+    /// Leaves a slot empty on screen
+    Empty,
+}
+
+fn default_counter_bits() -> u8 {
+    4
+}
+
+fn default_ram_size() -> usize {
+    RAM_SIZE
+}
+
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ControlFlag, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+fn optional_flag<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ControlFlag>, D::Error> {
+    flag(deserializer).map(Some)
+}
+
+/// A module built from its description, with its observable value
+type BuiltModule = (Box<dyn GraphicalModule>, Option<Shared<u8>>);
+
+impl ModuleSpec {
+    /// Name of the module on screen and in saved states, which keep one
+    /// snapshot per name
+    fn shown_name(&self) -> Option<String> {
+        match self {
+            ModuleSpec::Rom {
+                name: Some(name), ..
+            } => Some(name.clone()),
+            ModuleSpec::Rom { start, .. } => Some(format!("ROM@{:#04x}", start)),
+            ModuleSpec::Ram { .. } => Some("Memory Contents".to_string()),
+            ModuleSpec::Flags { .. } => Some("Flags".to_string()),
+            ModuleSpec::ProgramCounter { .. } => Some("Program Counter".to_string()),
+            ModuleSpec::Output => Some("Output".to_string()),
+            ModuleSpec::DecoderStep => Some("Decoder Step".to_string()),
+            _ => BreadboardBuilder::name(self).map(str::to_string),
+        }
+    }
+
+    /// Modules whose values this module observes
+    fn inputs(&self) -> Vec<&str> {
+        match self {
+            ModuleSpec::Alu { a, b } => vec![a, b],
+            ModuleSpec::Flags { alu } => vec![alu],
+            ModuleSpec::Ram { address, .. } | ModuleSpec::Rom { address, .. } => vec![address],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The module and the value other modules can observe, if it has one.
+    /// ROMs decode the `space` addresses of the RAM they share a register with.
+    fn build<R: FnOnce(&mut [u8])>(
+        &self,
+        shared: &HashMap<String, Shared<u8>>,
+        decoder: Option<(Shared<u8>, usize)>,
+        ram_init: &mut Option<R>,
+        map: MemoryMap,
+        space: usize,
+    ) -> Result<BuiltModule, String> {
+        let input = |name: &String| shared[name].share();
+        Ok(match self {
+            ModuleSpec::Register { name, read, write } => {
+                let register = Register::new(name, *read, write.unwrap_or(ControlFlag::Empty));
+                let value = register.share();
+                (Box::new(register), Some(value))
+            }
+            ModuleSpec::Alu { a, b } => {
+                let alu = Alu::new(input(a), input(b));
+                let flags = alu.share();
+                (Box::new(alu), Some(flags))
+            }
+            ModuleSpec::Flags { alu } => {
+                (Box::new(FlagsRegister::new(input(alu), FLAG_LABELS)), None)
+            }
+            ModuleSpec::Ram { address, size } => {
+                let mut ram = Ram::with_size(input(address), *size)?.with_map(map);
+                if let Some(init) = ram_init.take() {
+                    init(&mut ram.memory);
+                }
+                (Box::new(ram), None)
+            }
+            ModuleSpec::Rom {
+                address,
+                start,
+                contents,
+                ..
+            } => {
+                let name = self.shown_name().unwrap_or_default();
+                let rom =
+                    Rom::new(&name, input(address), *start, contents.clone()).with_size(space)?;
+                (Box::new(rom), None)
+            }
+            ModuleSpec::InstructionRegister => {
+                let register = InstructionRegister::default();
+                let value = register.share();
+                (Box::new(register), Some(value))
+            }
+            ModuleSpec::ProgramCounter { bits } => (Box::new(ProgramCounter::new(*bits)), None),
+            ModuleSpec::Output => (Box::new(OutputRegister(0)), None),
+            ModuleSpec::DecoderStep => {
+                let (step, steps) = decoder.expect("the decoder is built before its step");
+                (Box::new(DecoderStep::new(step, steps)), None)
+            }
+            ModuleSpec::Empty => (Box::new(EmptyModule), None),
+        })
+    }
+}
+
+/// Where the decoder reads the instruction and the flags from
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DecoderInputs {
+    #[serde(default = "default_instruction")]
+    pub instruction: String,
+    #[serde(default = "default_flags")]
+    pub flags: String,
+}
+
+fn default_instruction() -> String {
+    "Instruction Register".to_string()
+}
+
+fn default_flags() -> String {
+    "Sum Register".to_string()
+}
+
+impl Default for DecoderInputs {
+    fn default() -> Self {
+        DecoderInputs {
+            instruction: default_instruction(),
+            flags: default_flags(),
+        }
+    }
+}
+
+/// Builds a machine from its modules, in the order they are shown on screen:
+///
+/// ```no_run
+/// # use breadboard_8bit::breadboard_builder::BreadboardBuilder;
+/// # use breadboard_8bit::modules::{BranchingInstructionDecoder, ControlFlag};
+/// let state = BreadboardBuilder::sap1()
+///     .register("C Register", ControlFlag::OutputRegisterIn, None)
+///     .build(|_| {}, |instruction, flags| {
+///         Ok(BranchingInstructionDecoder::new(instruction, flags))
+///     });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BreadboardBuilder {
+    modules: Vec<ModuleSpec>,
+    decoder: DecoderInputs,
+    encoding: Encoding,
+}
+
+impl BreadboardBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The machine of the breadboard build
+    pub fn sap1() -> Self {
+        Self::breadboard(4, RAM_SIZE, Encoding::Compact)
+    }
+
+    /// The breadboard build with 256 bytes of RAM, an 8-bit program counter
+    /// and instructions followed by their operand
+    pub fn extended() -> Self {
+        Self::breadboard(8, EXTENDED_RAM_SIZE, Encoding::Extended)
+    }
+
+    fn breadboard(counter_bits: u8, ram_size: usize, encoding: Encoding) -> Self {
+        Self::new()
+            .program_counter(counter_bits)
+            .register("Memory Address", ControlFlag::MemoryAddressIn, None)
+            .ram_with_size("Memory Address", ram_size)
+            .module(ModuleSpec::InstructionRegister)
+            .module(ModuleSpec::DecoderStep)
+            .register(
+                "A Register",
+                ControlFlag::ARegisterIn,
+                Some(ControlFlag::ARegisterOut),
+            )
+            .alu("A Register", "B Register")
+            .flags("Sum Register")
+            .register("B Register", ControlFlag::BRegisterIn, None)
+            .module(ModuleSpec::Output)
+            .encoding(encoding)
+    }
+
+    pub fn module(mut self, module: ModuleSpec) -> Self {
+        self.modules.push(module);
+        self
+    }
+
+    pub fn register(self, name: &str, read: ControlFlag, write: Option<ControlFlag>) -> Self {
+        self.module(ModuleSpec::Register {
+            name: name.to_string(),
+            read,
+            write,
+        })
+    }
+
+    pub fn program_counter(self, bits: u8) -> Self {
+        self.module(ModuleSpec::ProgramCounter { bits })
+    }
+
+    pub fn alu(self, a: &str, b: &str) -> Self {
+        self.module(ModuleSpec::Alu {
+            a: a.to_string(),
+            b: b.to_string(),
+        })
+    }
+
+    pub fn flags(self, alu: &str) -> Self {
+        self.module(ModuleSpec::Flags {
+            alu: alu.to_string(),
+        })
+    }
+
+    pub fn ram(self, address: &str) -> Self {
+        self.ram_with_size(address, RAM_SIZE)
+    }
+
+    pub fn ram_with_size(self, address: &str, size: usize) -> Self {
+        self.module(ModuleSpec::Ram {
+            address: address.to_string(),
+            size,
+        })
+    }
+
+    /// A ROM holding `contents` from `start` on, named after `start`
+    pub fn rom(self, address: &str, start: usize, contents: Vec<u8>) -> Self {
+        self.module(ModuleSpec::Rom {
+            name: None,
+            address: address.to_string(),
+            start,
+            size: None,
+            program: None,
+            contents,
+        })
+    }
+
+    /// Modules the decoder reads the instruction and the flags from
+    pub fn decoder_inputs(mut self, instruction: &str, flags: &str) -> Self {
+        self.decoder = DecoderInputs {
+            instruction: instruction.to_string(),
+            flags: flags.to_string(),
+        };
+        self
+    }
+
+    /// How programs for the machine are laid out in memory
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn get_encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn modules(&self) -> &[ModuleSpec] {
+        &self.modules
+    }
+
+    /// Bytes of RAM programs are loaded into, if the machine has RAM
+    pub fn ram_size(&self) -> Option<usize> {
+        self.modules.iter().find_map(|module| match module {
+            ModuleSpec::Ram { size, .. } => Some(*size),
+            _ => None,
+        })
+    }
+
+    /// Addresses of the RAM observing `address`, or every address it can
+    /// hold if no RAM does
+    fn address_space(&self, address: &str) -> usize {
+        self.modules
+            .iter()
+            .find_map(|module| match module {
+                ModuleSpec::Ram { address: ram, size } if ram == address => Some(*size),
+                _ => None,
+            })
+            .unwrap_or(EXTENDED_RAM_SIZE)
+    }
+
+    /// Regions of the ROMs that observe the same register as `module`
+    fn memory_map(&self, module: &ModuleSpec) -> MemoryMap {
+        let ram_address = match module {
+            ModuleSpec::Ram { address, .. } => address,
+            _ => return MemoryMap::default(),
+        };
+        self.modules
+            .iter()
+            .fold(MemoryMap::new(), |map, rom| match rom {
+                ModuleSpec::Rom {
+                    address,
+                    start,
+                    contents,
+                    ..
+                } if address == ram_address => map.read_only(*start..*start + contents.len()),
+                _ => map,
+            })
+    }
+
+    /// Name of the module, as other modules refer to it
+    fn name(module: &ModuleSpec) -> Option<&str> {
+        match module {
+            ModuleSpec::Register { name, .. } => Some(name),
+            ModuleSpec::Alu { .. } => Some("Sum Register"),
+            ModuleSpec::InstructionRegister => Some("Instruction Register"),
+            _ => None,
+        }
+    }
+
+    /// Checks that every module observes a module that exists and has a
+    /// value, and that names are unique
+    pub fn check(&self) -> Result<(), String> {
+        let mut shown: Vec<String> = Vec::new();
+        for name in self.modules.iter().filter_map(ModuleSpec::shown_name) {
+            if shown.contains(&name) {
+                return Err(format!("two modules are named {}", name));
+            }
+            shown.push(name);
+        }
+        let names: Vec<&str> = self.modules.iter().filter_map(Self::name).collect();
+        for module in self.modules.iter() {
+            match module {
+                ModuleSpec::ProgramCounter { bits } if !(1..=8).contains(bits) => {
+                    return Err(format!("a program counter cannot have {} bits", bits));
+                }
+                ModuleSpec::Ram { size, .. }
+                    if !size.is_power_of_two() || *size > EXTENDED_RAM_SIZE =>
+                {
+                    return Err(format!(
+                        "RAM cannot hold {} bytes, only powers of two up to 256",
+                        size
+                    ));
+                }
+                ModuleSpec::Rom {
+                    start, contents, ..
+                } if contents.is_empty() => {
+                    return Err(format!("the ROM at {:#04x} is empty", start));
+                }
+                ModuleSpec::Rom {
+                    address,
+                    start,
+                    contents,
+                    ..
+                } if start + contents.len() > self.address_space(address) => {
+                    return Err(format!(
+                        "the ROM at {:#04x} holds {} bytes, past the end of the address \
+                         space of {} ({} bytes)",
+                        start,
+                        contents.len(),
+                        address,
+                        self.address_space(address)
+                    ));
+                }
+                _ => (),
+            }
+        }
+        let roms: Vec<(&String, Range<usize>)> = self
+            .modules
+            .iter()
+            .filter_map(|module| match module {
+                ModuleSpec::Rom {
+                    address,
+                    start,
+                    contents,
+                    ..
+                } => Some((address, *start..*start + contents.len())),
+                _ => None,
+            })
+            .collect();
+        for (i, (address, region)) in roms.iter().enumerate() {
+            let overlap = roms[i + 1..].iter().find(|(other, r)| {
+                other == address && r.start < region.end && region.start < r.end
+            });
+            if let Some((_, r)) = overlap {
+                return Err(format!(
+                    "two ROMs answer to address {:#04x}",
+                    region.start.max(r.start)
+                ));
+            }
+        }
+        let inputs = self.modules.iter().flat_map(ModuleSpec::inputs);
+        let decoder_inputs = [
+            self.decoder.instruction.as_str(),
+            self.decoder.flags.as_str(),
+        ];
+        for input in inputs.chain(decoder_inputs.iter().copied()) {
+            if !names.contains(&input) {
+                return Err(format!(
+                    "no register, ALU or instruction register named {} to observe",
+                    input
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the modules, modules that others observe first, and shows them
+    /// in the order they were added. `ram_init` fills the first RAM.
+    pub fn build<R, D, I>(&self, ram_init: R, get_decoder: D) -> Result<BreadboardState<I>, String>
+    where
+        R: FnOnce(&mut [u8]),
+        D: FnOnce(Shared<u8>, Shared<u8>) -> Result<I, String>,
+        I: InstructionDecoder + Share<u8>,
+    {
+        self.check()?;
+        let mut built: Vec<Option<Box<dyn GraphicalModule>>> =
+            self.modules.iter().map(|_| None).collect();
+        let mut shared: HashMap<String, Shared<u8>> = HashMap::new();
+        let mut ram_init = Some(ram_init);
+        let mut get_decoder = Some(get_decoder);
+        let mut decoder: Option<I> = None;
+        loop {
+            let mut progress = false;
+            if decoder.is_none()
+                && shared.contains_key(&self.decoder.instruction)
+                && shared.contains_key(&self.decoder.flags)
+            {
+                let get_decoder = get_decoder.take().unwrap();
+                decoder = Some(get_decoder(
+                    shared[&self.decoder.instruction].share(),
+                    shared[&self.decoder.flags].share(),
+                )?);
+                progress = true;
+            }
+            for (spec, slot) in self.modules.iter().zip(built.iter_mut()) {
+                let ready = slot.is_none()
+                    && spec
+                        .inputs()
+                        .iter()
+                        .all(|input| shared.contains_key(*input))
+                    && (decoder.is_some() || *spec != ModuleSpec::DecoderStep);
+                if !ready {
+                    continue;
+                }
+                let decoder = decoder.as_ref().map(|d| (d.share(), d.steps()));
+                let space = match spec {
+                    ModuleSpec::Rom { address, .. } => self.address_space(address),
+                    _ => EXTENDED_RAM_SIZE,
+                };
+                let (module, value) = spec.build(
+                    &shared,
+                    decoder,
+                    &mut ram_init,
+                    self.memory_map(spec),
+                    space,
+                )?;
+                if let (Some(name), Some(value)) = (Self::name(spec), value) {
+                    shared.insert(name.to_string(), value);
+                }
+                *slot = Some(module);
+                progress = true;
+            }
+            if decoder.is_some() && built.iter().all(Option::is_some) {
+                break;
+            }
+            if !progress {
+                return Err("modules observe each other in a loop".to_string());
+            }
+        }
+        let modules: Modules = built.into_iter().map(Option::unwrap).collect();
+        Ok(BreadboardState::new(modules, decoder.unwrap()).with_encoding(self.encoding))
+    }
+}
+
+/// The decoder of a machine description
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum DecoderSpec {
+    Simple,
+    #[default]
+    Branching,
+    /// A microcode image, relative to the description
+    Microcode {
+        file: PathBuf,
+    },
+    /// The decoder of the extended machine
+    Extended,
+}
+
+/// A machine described in TOML or RON:
+///
+/// ```toml
+/// [decoder]
+/// kind = "microcode"
+/// file = "microcode"
+///
+/// [[module]]
+/// kind = "register"
+/// name = "A Register"
+/// read = "AI"
+/// write = "AO"
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MachineDescription {
+    #[serde(default)]
+    pub decoder: DecoderSpec,
+    /// Where the decoder reads the instruction and flags from
+    #[serde(default)]
+    pub decoder_inputs: DecoderInputs,
+    /// How programs are laid out in memory, `compact` or `extended`
+    #[serde(default, deserialize_with = "encoding")]
+    pub encoding: Encoding,
+    #[serde(rename = "module")]
+    pub modules: Vec<ModuleSpec>,
+}
+
+fn encoding<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Encoding, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+impl MachineDescription {
+    pub fn from_toml(s: &str) -> Result<Self, String> {
+        toml::from_str(s).map_err(|e| e.to_string())
+    }
+
+    pub fn from_ron(s: &str) -> Result<Self, String> {
+        ron::from_str(s).map_err(|e| e.to_string())
+    }
+
+    /// Reads a `.ron` file as RON and anything else as TOML. Microcode and
+    /// ROM program paths are made relative to the directory of the
+    /// description.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut description = match path.extension() {
+            Some(extension) if extension == "ron" => Self::from_ron(&s),
+            _ => Self::from_toml(&s),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        if let Some(dir) = path.parent() {
+            if let DecoderSpec::Microcode { file } = &mut description.decoder {
+                *file = dir.join(&file);
+            }
+            for module in description.modules.iter_mut() {
+                if let ModuleSpec::Rom {
+                    program: Some(program),
+                    ..
+                } = module
+                {
+                    *program = dir.join(&program);
+                }
+            }
+        }
+        Ok(description)
+    }
+
+    pub fn builder(&self) -> BreadboardBuilder {
+        BreadboardBuilder {
+            modules: self.modules.clone(),
+            decoder: self.decoder_inputs.clone(),
+            encoding: self.encoding,
+        }
+    }
+
+    /// The builder of the machine, with the programs of its ROMs read
+    pub fn load_roms(&self) -> Result<BreadboardBuilder, String> {
+        let mut builder = self.builder();
+        for module in builder.modules.iter_mut() {
+            if let ModuleSpec::Rom {
+                start,
+                size,
+                program: Some(program),
+                contents,
+                ..
+            } = module
+            {
+                let image = load_program(&program, None, self.encoding, self.encoding.ram_size())?;
+                let size = size.unwrap_or_else(|| image.len().saturating_sub(*start));
+                *contents = vec![0; size];
+                let end = image.len().min(*start + size);
+                if *start < end {
+                    contents[..end - *start].copy_from_slice(&image[*start..end]);
+                }
+            }
+        }
+        Ok(builder)
+    }
+
+    pub fn build<R>(&self, ram_init: R) -> Result<BreadboardState<AnyDecoder>, String>
+    where
+        R: FnOnce(&mut [u8]),
+    {
+        self.load_roms()?
+            .build(ram_init, |instruction, flags| match &self.decoder {
+                DecoderSpec::Simple => Ok(AnyDecoder::Simple(SimpleInstructionDecoder::new(
+                    instruction,
+                ))),
+                DecoderSpec::Branching => Ok(AnyDecoder::Branching(
+                    BranchingInstructionDecoder::new(instruction, flags),
+                )),
+                DecoderSpec::Microcode { file } => {
+                    MicrocodeDecoder::from_file(instruction, flags, file).map(AnyDecoder::Microcode)
+                }
+                DecoderSpec::Extended => Ok(AnyDecoder::Extended(ExtendedInstructionDecoder::new(
+                    instruction,
+                    flags,
+                ))),
+            })
+    }
+}
@@ -0,0 +1,214 @@
+// Breakpoints and watchpoints, checked every time the control word is decoded
+use crate::isa;
+use crate::modules::ControlFlag;
+use crate::trace::CycleTrace;
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// The program counter holds this address when an instruction is fetched
+    Address(u8),
+    /// An instruction with this opcode starts executing
+    Opcode(u8),
+    /// The control flag is asserted
+    Flag(ControlFlag),
+    /// The memory cell answering to this address changes
+    Memory(usize),
+    /// The value of the module with this name changes
+    Watch(String),
+}
+
+/// Parses `pc=ADDRESS`, `op=MNEMONIC`, `flag=FLAG`, `ram=ADDRESS` or
+/// `watch=MODULE`. Numbers can be given in decimal or hexadecimal (`0x`).
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parse_number = |n: &str| {
+            match n.strip_prefix("0x") {
+                Some(hex) => usize::from_str_radix(hex, 16),
+                None => n.parse(),
+            }
+            .map_err(|_| format!("invalid number {}", n))
+        };
+        let (kind, argument) = match s.find('=') {
+            Some(i) => (&s[..i], s[i + 1..].trim()),
+            None => return Err(format!("invalid breakpoint {}, expected KIND=VALUE", s)),
+        };
+        match kind.trim() {
+            "pc" => u8::try_from(parse_number(argument)?)
+                .map(Breakpoint::Address)
+                .map_err(|_| format!("address {} does not fit in the program counter", argument)),
+            "op" => match isa::by_mnemonic(argument) {
+                Some(instruction) => Ok(Breakpoint::Opcode(instruction.opcode)),
+                None => match parse_number(argument)? {
+                    opcode if opcode > 0xf => {
+                        Err(format!("opcode {} is wider than 4 bits", argument))
+                    }
+                    opcode => Ok(Breakpoint::Opcode(opcode as u8)),
+                },
+            },
+            "flag" => Ok(Breakpoint::Flag(argument.parse()?)),
+            "ram" => Ok(Breakpoint::Memory(parse_number(argument)?)),
+            "watch" => Ok(Breakpoint::Watch(argument.to_string())),
+            _ => Err(format!("unknown breakpoint kind {}", kind)),
+        }
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Address(address) => write!(f, "pc={:#x}", address),
+            Breakpoint::Opcode(opcode) => match isa::by_opcode(*opcode) {
+                Some(instruction) => write!(f, "op={}", instruction.mnemonic),
+                None => write!(f, "op={:#x}", opcode),
+            },
+            Breakpoint::Flag(flag) => write!(f, "flag={:?}", flag),
+            Breakpoint::Memory(address) => write!(f, "ram={:#x}", address),
+            Breakpoint::Watch(name) => write!(f, "watch={}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Hit {
+    pub breakpoint: Breakpoint,
+    /// Name of the module that triggered the breakpoint, if any
+    pub module: Option<String>,
+    pub reason: String,
+}
+
+impl Display for Hit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.breakpoint, self.reason)
+    }
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    breakpoints: Vec<Breakpoint>,
+    /// Values seen by each watchpoint on the previous check
+    previous: Vec<Option<u8>>,
+}
+
+impl Breakpoints {
+    pub fn new(breakpoints: Vec<Breakpoint>) -> Self {
+        let previous = vec![None; breakpoints.len()];
+        Breakpoints {
+            breakpoints,
+            previous,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+        self.previous.push(None);
+    }
+
+    /// Values seen by each watchpoint on the last check
+    pub fn watched(&self) -> &[Option<u8>] {
+        &self.previous
+    }
+
+    /// Puts back values returned by `watched`. Watchpoints added since then
+    /// have not seen any value yet.
+    pub fn set_watched(&mut self, watched: &[Option<u8>]) {
+        for (i, previous) in self.previous.iter_mut().enumerate() {
+            *previous = watched.get(i).copied().flatten();
+        }
+    }
+
+    /// Forgets the values seen by the watchpoints, which start over from
+    /// the next check
+    pub fn reset(&mut self) {
+        self.previous.fill(None);
+    }
+
+    /// Returns the first breakpoint triggered by this cycle. Watchpoints
+    /// compare against the values seen on the previous call.
+    pub fn check(&mut self, cycle: &CycleTrace) -> Option<Hit> {
+        let mut hit = None;
+        for (breakpoint, previous) in self.breakpoints.iter().zip(self.previous.iter_mut()) {
+            let triggered = match breakpoint {
+                Breakpoint::Address(address) => cycle
+                    .modules
+                    .iter()
+                    .find(|m| m.bus_write_flag() == ControlFlag::CounterOut)
+                    .filter(|m| cycle.step == 0 && m.value() == Some(*address))
+                    .map(|m| {
+                        (
+                            Some(m.get_name().to_string()),
+                            format!("program counter reached {:#x}", address),
+                        )
+                    }),
+                // The instruction is latched at the end of the fetch cycle
+                Breakpoint::Opcode(opcode)
+                    if cycle.step == 2 && cycle.instruction >> 4 == *opcode =>
+                {
+                    let module = cycle
+                        .modules
+                        .iter()
+                        .find(|m| m.bus_read_flag() == ControlFlag::InstructionRegisterIn)
+                        .map(|m| m.get_name().to_string());
+                    Some((module, format!("executing {:#04x}", cycle.instruction)))
+                }
+                Breakpoint::Opcode(_) => None,
+                Breakpoint::Flag(flag) if cycle.cw.has(*flag) => {
+                    let module = cycle
+                        .modules
+                        .iter()
+                        .find(|m| m.bus_read_flag() == *flag || m.bus_write_flag() == *flag)
+                        .map(|m| m.get_name().to_string());
+                    Some((module, format!("{:?} is asserted", flag)))
+                }
+                Breakpoint::Flag(_) => None,
+                Breakpoint::Memory(address) => cycle
+                    .modules
+                    .iter()
+                    .find_map(|m| Some((m, m.read_address(*address)?)))
+                    .and_then(|(m, value)| {
+                        let changed = previous.is_some_and(|p| p != value);
+                        *previous = Some(value);
+                        if changed {
+                            Some((
+                                Some(m.get_name().to_string()),
+                                format!("RAM at {:#x} changed to {}", address, value),
+                            ))
+                        } else {
+                            None
+                        }
+                    }),
+                Breakpoint::Watch(name) => cycle
+                    .modules
+                    .iter()
+                    .find(|m| m.get_name() == name)
+                    .and_then(|m| {
+                        let value = m.value()?;
+                        let changed = previous.is_some_and(|p| p != value);
+                        *previous = Some(value);
+                        if changed {
+                            Some((Some(name.clone()), format!("{} changed to {}", name, value)))
+                        } else {
+                            None
+                        }
+                    }),
+            };
+            // Keep going to update the other watchpoints
+            if let (None, Some((module, reason))) = (&hit, triggered) {
+                hit = Some(Hit {
+                    breakpoint: breakpoint.clone(),
+                    module,
+                    reason,
+                });
+            }
+        }
+        hit
+    }
+}
@@ -0,0 +1,116 @@
+// What ends up on the bus when several modules, or none, drive it
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ContentionPolicy {
+    /// Report the collision and halt the clock
+    Halt,
+    /// Report the collision, the first module in the list wins
+    #[default]
+    Warn,
+    /// The bus reads as the AND of every value driven onto it
+    WiredAnd,
+    /// The bus reads as the OR of every value driven onto it
+    WiredOr,
+}
+
+impl FromStr for ContentionPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "halt" => Ok(ContentionPolicy::Halt),
+            "warn" => Ok(ContentionPolicy::Warn),
+            "and" => Ok(ContentionPolicy::WiredAnd),
+            "or" => Ok(ContentionPolicy::WiredOr),
+            _ => Err(format!("unknown bus contention policy {}", s)),
+        }
+    }
+}
+
+impl ContentionPolicy {
+    pub fn resolve(self, contention: &Contention) -> u8 {
+        let values = contention.drivers.iter().map(|(_, value)| *value);
+        match self {
+            ContentionPolicy::Halt | ContentionPolicy::Warn => contention.drivers[0].1,
+            ContentionPolicy::WiredAnd => values.fold(0xff, |bus, value| bus & value),
+            ContentionPolicy::WiredOr => values.fold(0, |bus, value| bus | value),
+        }
+    }
+}
+
+/// Several modules writing to the bus during the same cycle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Contention {
+    /// Name of each module driving the bus and the value it drives
+    pub drivers: Vec<(String, u8)>,
+}
+
+impl Display for Contention {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let drivers: Vec<String> = self
+            .drivers
+            .iter()
+            .map(|(name, value)| format!("{} ({:08b})", name, value))
+            .collect();
+        write!(f, "{} all write to the bus", drivers.join(", "))
+    }
+}
+
+/// What the bus reads as when no module drives it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FloatingPolicy {
+    #[default]
+    Zero,
+    /// Pull-up resistors on every line
+    PullUp,
+    /// The bus keeps the last value driven onto it
+    Previous,
+    Random,
+}
+
+impl FromStr for FloatingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "zero" => Ok(FloatingPolicy::Zero),
+            "pull-up" => Ok(FloatingPolicy::PullUp),
+            "previous" => Ok(FloatingPolicy::Previous),
+            "random" => Ok(FloatingPolicy::Random),
+            _ => Err(format!("unknown floating bus policy {}", s)),
+        }
+    }
+}
+
+impl FloatingPolicy {
+    pub fn resolve(self, previous: u8, noise: &mut Noise) -> u8 {
+        match self {
+            FloatingPolicy::Zero => 0,
+            FloatingPolicy::PullUp => 0xff,
+            FloatingPolicy::Previous => previous,
+            FloatingPolicy::Random => noise.byte(),
+        }
+    }
+}
+
+/// Xorshift generator for random floating values. It always starts from the
+/// same seed so that runs can be reproduced.
+#[derive(Clone, Debug)]
+pub struct Noise(u32);
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise(0x2545_f491)
+    }
+}
+
+impl Noise {
+    pub fn byte(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 24) as u8
+    }
+}
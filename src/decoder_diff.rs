@@ -0,0 +1,349 @@
+// Differential testing of decoders: comparing the control words two decoders
+// produce for every address, or running two machines side by side
+use crate::disassembler::disassemble_instruction;
+use crate::isa;
+use crate::modules::{ControlFlag, ControlWord, InstructionDecoder, MicrocodeAddress};
+use crate::shareable::{Share, Shareable, Shared};
+use crate::state::BreadboardState;
+use std::fmt::{self, Display, Formatter};
+
+/// A decoder with registers of its own, so that it can be asked for the
+/// control word of any address
+pub struct Probe<D> {
+    decoder: D,
+    instruction: Shareable<u8>,
+    flags: Shareable<u8>,
+}
+
+impl<D: InstructionDecoder> Probe<D> {
+    /// Builds the decoder like `BreadboardState::default_with_decoder` does
+    pub fn new<F>(get_decoder: F) -> Result<Self, String>
+    where
+        F: FnOnce(Shared<u8>, Shared<u8>) -> Result<D, String>,
+    {
+        let instruction = Shareable::new(0);
+        let flags = Shareable::new(0);
+        let decoder = get_decoder(instruction.share(), flags.share())?;
+        Ok(Probe {
+            decoder,
+            instruction,
+            flags,
+        })
+    }
+
+    pub fn steps(&self) -> usize {
+        self.decoder.steps()
+    }
+
+    pub fn decode(&mut self, address: MicrocodeAddress) -> ControlWord {
+        self.instruction.set(address.instruction << 4);
+        self.flags.set(address.flags);
+        self.decoder.set_counter(address.step as usize);
+        self.decoder.decode()
+    }
+}
+
+/// Addresses where two decoders disagree, for one instruction and step
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub instruction: u8,
+    pub step: u8,
+    /// Values of the flags nibble for which the decoders disagree this way
+    pub flags: Vec<u8>,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match isa::by_opcode(self.instruction) {
+            Some(instruction) => write!(f, "{} step {}", instruction.mnemonic, self.step)?,
+            None => write!(f, "opcode {:#x} step {}", self.instruction, self.step)?,
+        }
+        if self.flags.len() < 16 {
+            let flags: Vec<String> = self
+                .flags
+                .iter()
+                .map(|flags| format!("{:04b}", flags))
+                .collect();
+            write!(f, " (flags {})", flags.join(", "))?;
+        }
+        write!(
+            f,
+            ": {} / {}",
+            ControlWord(self.left),
+            ControlWord(self.right)
+        )
+    }
+}
+
+fn ends(cw: ControlWord) -> bool {
+    cw.has(ControlFlag::NextInstruction) || cw.has(ControlFlag::Hlt)
+}
+
+/// Whether the steps after `address` up to the end of the counter are empty
+fn idle_after<D: InstructionDecoder>(probe: &mut Probe<D>, address: MicrocodeAddress) -> bool {
+    (address.step as usize + 1..probe.steps()).all(|step| {
+        probe
+            .decode(MicrocodeAddress {
+                step: step as u8,
+                ..address
+            })
+            .0
+            == 0
+    })
+}
+
+/// Compares the control words of two decoders for every flags, instruction
+/// and step. Steps after both decoders have ended the instruction are never
+/// reached, so they are not compared. Ending an instruction early with
+/// `NextInstruction` matches leaving the steps after it empty, which is how
+/// the simple decoder ends them.
+pub fn compare<A, B>(left: &mut Probe<A>, right: &mut Probe<B>) -> Vec<Mismatch>
+where
+    A: InstructionDecoder,
+    B: InstructionDecoder,
+{
+    let next = ControlFlag::NextInstruction as u32;
+    let steps = left.steps().min(right.steps()) as u8;
+    let mut mismatches: Vec<Mismatch> = Vec::new();
+    for instruction in 0..16 {
+        for flags in 0..16 {
+            for step in 0..steps {
+                let address = MicrocodeAddress {
+                    flags,
+                    instruction,
+                    step,
+                };
+                let (l, r) = (left.decode(address), right.decode(address));
+                // Only one of them ends the instruction here
+                let early_end = l.0 != r.0 && l.0 & !next == r.0 & !next;
+                let same = match (early_end, l.has(ControlFlag::NextInstruction)) {
+                    (false, _) => l.0 == r.0,
+                    (true, true) => idle_after(right, address),
+                    (true, false) => idle_after(left, address),
+                };
+                if !same {
+                    let existing = mismatches.iter_mut().find(|m| {
+                        m.instruction == instruction
+                            && m.step == step
+                            && m.left == l.0
+                            && m.right == r.0
+                    });
+                    match existing {
+                        Some(mismatch) => mismatch.flags.push(flags),
+                        None => mismatches.push(Mismatch {
+                            instruction,
+                            step,
+                            flags: vec![flags],
+                            left: l.0,
+                            right: r.0,
+                        }),
+                    }
+                }
+                if early_end || ends(l) && ends(r) {
+                    break;
+                }
+            }
+        }
+    }
+    mismatches
+}
+
+/// Something that differs between two machines
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference {
+    ControlWord {
+        left: u32,
+        right: u32,
+    },
+    Bus {
+        left: u8,
+        right: u8,
+    },
+    Halted {
+        left: bool,
+        right: bool,
+    },
+    /// Value held by a module, e.g. a register
+    Value {
+        module: String,
+        left: Option<u8>,
+        right: Option<u8>,
+    },
+    /// First differing byte of a memory-like module
+    Memory {
+        module: String,
+        address: usize,
+        left: u8,
+        right: u8,
+    },
+    /// The module only exists in one of the machines
+    MissingModule(String),
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let value = |v: &Option<u8>| v.map_or("nothing".to_string(), |v| format!("{:#04x}", v));
+        match self {
+            Difference::ControlWord { left, right } => write!(
+                f,
+                "control word {} / {}",
+                ControlWord(*left),
+                ControlWord(*right)
+            ),
+            Difference::Bus { left, right } => write!(f, "bus {:08b} / {:08b}", left, right),
+            Difference::Halted { left, right } => write!(f, "halted {} / {}", left, right),
+            Difference::Value {
+                module,
+                left,
+                right,
+            } => write!(f, "{} {} / {}", module, value(left), value(right)),
+            Difference::Memory {
+                module,
+                address,
+                left,
+                right,
+            } => write!(
+                f,
+                "{} at {:#04x}: {:#04x} / {:#04x}",
+                module, address, left, right
+            ),
+            Difference::MissingModule(module) => {
+                write!(f, "{} is missing from one machine", module)
+            }
+        }
+    }
+}
+
+/// The first cycle where two machines differ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub cycle: u64,
+    /// Instruction register and decoder step of each machine
+    pub left: (u8, usize),
+    pub right: (u8, usize),
+    pub differences: Vec<Difference>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let at = |(instruction, step): (u8, usize)| {
+            format!("{} T{}", disassemble_instruction(instruction), step)
+        };
+        write!(f, "cycle {}, {}", self.cycle, at(self.left))?;
+        if self.right != self.left {
+            write!(f, " / {}", at(self.right))?;
+        }
+        let differences: Vec<String> = self.differences.iter().map(|d| d.to_string()).collect();
+        write!(f, ": {}", differences.join(", "))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockstepOutcome {
+    /// Both machines halted without differing
+    Halted {
+        cycles: u64,
+    },
+    /// No difference within the cycle limit
+    CycleLimit {
+        cycles: u64,
+    },
+    Diverged(Divergence),
+}
+
+fn differences<A, B>(left: &BreadboardState<A>, right: &BreadboardState<B>) -> Vec<Difference>
+where
+    A: InstructionDecoder,
+    B: InstructionDecoder,
+{
+    let mut differences = Vec::new();
+    if left.cw().0 != right.cw().0 {
+        differences.push(Difference::ControlWord {
+            left: left.cw().0,
+            right: right.cw().0,
+        });
+    }
+    if left.bus() != right.bus() {
+        differences.push(Difference::Bus {
+            left: left.bus(),
+            right: right.bus(),
+        });
+    }
+    if left.halted() != right.halted() {
+        differences.push(Difference::Halted {
+            left: left.halted(),
+            right: right.halted(),
+        });
+    }
+    for l in left.modules().iter().filter(|m| !m.get_name().is_empty()) {
+        let name = l.get_name().to_string();
+        let r = match right.module(&name) {
+            Some(r) => r,
+            None => {
+                differences.push(Difference::MissingModule(name));
+                continue;
+            }
+        };
+        if l.value() != r.value() {
+            differences.push(Difference::Value {
+                module: name.clone(),
+                left: l.value(),
+                right: r.value(),
+            });
+        }
+        if let (Some(lm), Some(rm)) = (l.memory(), r.memory()) {
+            if let Some(address) = (0..lm.len().min(rm.len())).find(|&a| lm[a] != rm[a]) {
+                differences.push(Difference::Memory {
+                    module: name,
+                    address,
+                    left: lm[address],
+                    right: rm[address],
+                });
+            }
+        }
+    }
+    for r in right.modules().iter().filter(|m| !m.get_name().is_empty()) {
+        if left.module(r.get_name()).is_none() {
+            differences.push(Difference::MissingModule(r.get_name().to_string()));
+        }
+    }
+    differences
+}
+
+/// Runs two machines one cycle at a time, comparing them after each cycle is
+/// decoded, until they differ, both halt or `max_cycles` cycles have run
+pub fn lockstep<A, B>(
+    left: &mut BreadboardState<A>,
+    right: &mut BreadboardState<B>,
+    max_cycles: u64,
+) -> LockstepOutcome
+where
+    A: InstructionDecoder,
+    B: InstructionDecoder,
+{
+    let mut cycles = 0;
+    loop {
+        left.settle();
+        right.settle();
+        let differences = differences(left, right);
+        if !differences.is_empty() {
+            return LockstepOutcome::Diverged(Divergence {
+                cycle: left.cycles(),
+                left: (left.instruction(), left.step()),
+                right: (right.instruction(), right.step()),
+                differences,
+            });
+        }
+        if left.halted() && right.halted() {
+            return LockstepOutcome::Halted { cycles };
+        }
+        if cycles >= max_cycles {
+            return LockstepOutcome::CycleLimit { cycles };
+        }
+        left.step_cycle();
+        right.step_cycle();
+        cycles += 1;
+    }
+}
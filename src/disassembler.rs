@@ -0,0 +1,124 @@
+// Turns RAM images back into assembly
+use crate::isa::{self, Encoding, Operand};
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ByteKind {
+    /// Reachable from address 0 by following the program's control flow
+    Code,
+    /// Operand of a memory instruction (LDA, ADD, SUB, STA)
+    Data,
+    /// Second byte of an extended instruction
+    Operand,
+    /// Neither executed nor referenced
+    Unreachable,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DisassembledByte {
+    pub address: usize,
+    pub byte: u8,
+    pub kind: ByteKind,
+    /// Operand in the next byte, for extended instructions that take one
+    pub operand: Option<u8>,
+}
+
+/// Formats a single instruction, e.g. `JC 4`
+pub fn disassemble_instruction(byte: u8) -> String {
+    match isa::by_opcode(byte >> 4) {
+        Some(instruction) if instruction.operand == Operand::None => {
+            instruction.mnemonic.to_string()
+        }
+        Some(instruction) => format!("{} {}", instruction.mnemonic, byte & 0xf),
+        None => format!("??? ({:#04x})", byte),
+    }
+}
+
+/// Formats the contents of the instruction register. Extended instructions
+/// keep their operand in memory, so only the mnemonic is shown for them.
+pub fn disassemble_register(byte: u8, encoding: Encoding) -> String {
+    match (encoding, isa::by_opcode(byte >> 4)) {
+        (Encoding::Extended, Some(instruction)) => instruction.mnemonic.to_string(),
+        _ => disassemble_instruction(byte),
+    }
+}
+
+/// Follows the control flow from address 0 to separate code from data
+pub fn disassemble(memory: &[u8]) -> Vec<DisassembledByte> {
+    disassemble_with(memory, Encoding::Compact)
+}
+
+pub fn disassemble_with(memory: &[u8], encoding: Encoding) -> Vec<DisassembledByte> {
+    let mut code = vec![false; memory.len()];
+    let mut data = vec![false; memory.len()];
+    // Second bytes of extended instructions, by address of the instruction
+    let mut operands = vec![None; memory.len()];
+    let mut operand_bytes = vec![false; memory.len()];
+    let mut pending = vec![0];
+    while let Some(mut address) = pending.pop() {
+        while address < memory.len() && !code[address] {
+            code[address] = true;
+            let byte = memory[address];
+            let instruction = match isa::by_opcode(byte >> 4) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+            let operand = if instruction.size(encoding) == 2 {
+                let next = (address + 1) % memory.len();
+                operands[address] = Some(memory[next]);
+                operand_bytes[next] = true;
+                address = next;
+                memory[next] as usize
+            } else {
+                (byte & 0xf) as usize
+            };
+            match instruction.operand {
+                Operand::Memory if operand < memory.len() => data[operand] = true,
+                Operand::Jump => pending.push(operand),
+                _ => (),
+            }
+            if instruction.opcode == isa::JMP || instruction.opcode == isa::HLT {
+                break;
+            }
+            // The program counter wraps around
+            address = (address + 1) % memory.len();
+        }
+    }
+    memory
+        .iter()
+        .enumerate()
+        .map(|(address, &byte)| DisassembledByte {
+            address,
+            byte,
+            kind: if code[address] {
+                ByteKind::Code
+            } else if operand_bytes[address] {
+                ByteKind::Operand
+            } else if data[address] {
+                ByteKind::Data
+            } else {
+                ByteKind::Unreachable
+            },
+            operand: operands[address],
+        })
+        .collect()
+}
+
+impl Display for DisassembledByte {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:#04x}: {:02x}  ", self.address, self.byte)?;
+        match self.kind {
+            ByteKind::Code => match (self.operand, isa::by_opcode(self.byte >> 4)) {
+                (Some(operand), Some(instruction)) => {
+                    write!(f, "{} {}", instruction.mnemonic, operand)
+                }
+                _ => write!(f, "{}", disassemble_instruction(self.byte)),
+            },
+            ByteKind::Operand => write!(f, "{:<8} ; operand", ""),
+            ByteKind::Data => write!(f, "{:<8} ; data", format!(".byte {}", self.byte)),
+            ByteKind::Unreachable => {
+                write!(f, "{:<8} ; unreachable", disassemble_instruction(self.byte))
+            }
+        }
+    }
+}
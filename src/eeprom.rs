@@ -0,0 +1,180 @@
+// Splitting microcode across 8-bit EEPROMs, like the breadboard build does,
+// and putting it back together from chip dumps
+use crate::modules::{AddressLayout, ControlFlag, MICROCODE_SIZE};
+
+/// Parses a list of control flags separated by commas, `none` or `all`, for
+/// the lines that are active low
+pub fn parse_active_low(s: &str) -> Result<u32, String> {
+    match s {
+        "none" => Ok(0),
+        "all" => Ok(ControlFlag::ALL
+            .iter()
+            .fold(0, |mask, &flag| mask | flag as u32)),
+        _ => s.split(',').try_fold(0, |mask, flag| {
+            let flag: ControlFlag = flag.trim().parse()?;
+            Ok(mask | flag as u32)
+        }),
+    }
+}
+
+/// How the control word is spread across chips. Chip 0 holds the least
+/// significant byte of the control word. Microcode given to and returned by
+/// these functions uses the default layout, whatever the layout of the chips.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChipLayout {
+    pub chips: usize,
+    /// How the chips' address lines are wired
+    pub layout: AddressLayout,
+    /// Control lines that are asserted when low
+    pub active_low: u32,
+}
+
+impl Default for ChipLayout {
+    fn default() -> Self {
+        ChipLayout {
+            chips: 3,
+            layout: AddressLayout::default(),
+            active_low: 0,
+        }
+    }
+}
+
+impl ChipLayout {
+    /// Bytes in the image of a single chip
+    pub fn chip_size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Bits of the control word stored on the chips
+    fn mask(&self) -> u32 {
+        if self.chips >= 4 {
+            u32::MAX
+        } else {
+            (1 << (8 * self.chips)) - 1
+        }
+    }
+
+    /// One image per chip
+    pub fn export(&self, microcode: &[u32]) -> Result<Vec<Vec<u8>>, String> {
+        self.check_chips()?;
+        let words = AddressLayout::default().convert(microcode, &self.layout);
+        Ok((0..self.chips)
+            .map(|chip| {
+                words
+                    .iter()
+                    .map(|word| ((word ^ self.active_low) >> (8 * chip)) as u8)
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// A single image for every chip, the chip number being given on the byte
+    /// select lines of the layout, or on the lines above it if it has none
+    pub fn export_combined(&self, microcode: &[u32]) -> Result<Vec<u8>, String> {
+        self.check_byte_select()?;
+        let images = self.export(microcode)?;
+        let mut combined = vec![0; self.combined_size()];
+        for (chip, image) in images.iter().enumerate() {
+            for address in self.unselected_addresses() {
+                combined[self.layout.select_byte(address, chip)] = image[address];
+            }
+        }
+        Ok(combined)
+    }
+
+    /// Splits a combined image into the image of each chip
+    pub fn split_combined(&self, combined: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        self.check_byte_select()?;
+        if combined.len() != self.combined_size() {
+            return Err(format!(
+                "combined image is {} bytes long, expected {}",
+                combined.len(),
+                self.combined_size()
+            ));
+        }
+        Ok((0..self.chips)
+            .map(|chip| {
+                let mut image = vec![0; self.chip_size()];
+                for address in self.unselected_addresses() {
+                    image[address] = combined[self.layout.select_byte(address, chip)];
+                }
+                image
+            })
+            .collect())
+    }
+
+    /// Rebuilds the microcode from the images of every chip, in order
+    pub fn import(&self, images: &[Vec<u8>]) -> Result<Box<[u32; MICROCODE_SIZE]>, String> {
+        self.check_chips()?;
+        if images.len() != self.chips {
+            return Err(format!(
+                "expected {} chip images, got {}",
+                self.chips,
+                images.len()
+            ));
+        }
+        for (chip, image) in images.iter().enumerate() {
+            if image.len() != self.chip_size() {
+                return Err(format!(
+                    "image of chip {} is {} bytes long, expected {}",
+                    chip,
+                    image.len(),
+                    self.chip_size()
+                ));
+            }
+        }
+        let default = AddressLayout::default();
+        let mut microcode = Box::new([0; MICROCODE_SIZE]);
+        for (address, word) in microcode.iter_mut().enumerate() {
+            let fields = default.unpack(address);
+            let chip_address = self.layout.pack(fields);
+            // Fields too wide for the chip are not stored anywhere
+            if self.layout.unpack(chip_address) != fields {
+                continue;
+            }
+            let stored = images.iter().enumerate().fold(0, |word, (chip, image)| {
+                word | (image[chip_address] as u32) << (8 * chip)
+            });
+            *word = (stored ^ self.active_low) & self.mask();
+        }
+        Ok(microcode)
+    }
+
+    /// The control word is 32 bits wide, so it takes 1 to 4 chips
+    fn check_chips(&self) -> Result<(), String> {
+        if (1..=4).contains(&self.chips) {
+            Ok(())
+        } else {
+            Err(format!(
+                "Invalid chip count {}, expected 1 to 4",
+                self.chips
+            ))
+        }
+    }
+
+    fn check_byte_select(&self) -> Result<(), String> {
+        match self.layout.byte_select {
+            Some(select) if 1 << select.width < self.chips => Err(format!(
+                "{} byte select lines cannot address {} chips",
+                select.width, self.chips
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn combined_size(&self) -> usize {
+        match self.layout.byte_select {
+            Some(_) => self.chip_size(),
+            None => self.chip_size() * self.chips,
+        }
+    }
+
+    /// Addresses of a chip image with the byte select lines low
+    fn unselected_addresses(&self) -> impl Iterator<Item = usize> {
+        let select = self.layout.byte_select;
+        (0..self.chip_size()).filter(move |&address| match select {
+            Some(select) => address >> select.shift & ((1 << select.width) - 1) == 0,
+            None => true,
+        })
+    }
+}
@@ -34,11 +34,11 @@ pub enum VisualRepresentation<'a> {
 }
 
 pub trait GraphicalModule: Module {
-    fn representation(&self) -> VisualRepresentation;
+    fn representation(&self) -> VisualRepresentation<'_>;
 }
 
 impl GraphicalModule for EmptyModule {
-    fn representation(&self) -> VisualRepresentation {
+    fn representation(&self) -> VisualRepresentation<'_> {
         VisualRepresentation::Empty
     }
 }
@@ -119,7 +119,3 @@ pub const CONTROL_HEIGHT: usize = 80;
 const FONT_DATA: &[u8] = include_bytes!("../assets/FiraSans-Regular.ttf");
 const FONT_SIZE: u32 = 40;
 
-pub const CW_LABELS: [&str; 32] = [
-    "HLT", "MI", "RI", "RO", "IO", "II", "AI", "AO", "ΣO", "SU", "BI", "OI", "CE", "CO", "J",
-    "FI", "NI", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
-];
@@ -66,7 +66,7 @@ impl<'a, 'b: 'a> GraphicsState<'a, G2d<'b>> {
         transform.trans(f64::from(num_bits) * 12.0, 0.0)
     }
 
-    pub fn display_modules(&mut self, modules: &[Box<dyn GraphicalModule>]) {
+    pub fn display_modules(&mut self, modules: &[Box<dyn GraphicalModule>], highlight: Option<&str>) {
         let n_lines = modules.len() / 2 + modules.len() % 2;
         for (index, module) in modules.iter().enumerate() {
             let top_left_y = (index % n_lines) * MODULE_HEIGHT;
@@ -75,6 +75,14 @@ impl<'a, 'b: 'a> GraphicsState<'a, G2d<'b>> {
             } else {
                 0
             };
+            if highlight == Some(module.get_name()) {
+                Rectangle::new_border(color::RED, 1.5).draw(
+                    [2.0, 2.0, MODULE_WIDTH as f64 - 4.0, MODULE_HEIGHT as f64 - 4.0],
+                    &DrawState::default(),
+                    self.transform.trans(top_left_x as f64, top_left_y as f64),
+                    self.g,
+                );
+            }
             let transform = self
                 .transform
                 .trans(top_left_x as f64, top_left_y as f64)
@@ -120,7 +128,7 @@ impl<'a, 'b: 'a> GraphicsState<'a, G2d<'b>> {
         VisualRepresentation::led(bus).display(self, transform.trans(5.0, 35.0));
     }
 
-    pub fn display_cw(&mut self, cw: ControlWord, n_modules: usize) {
+    pub fn display_cw(&mut self, cw: ControlWord, names: &ControlNames, n_modules: usize) {
         let transform = self.transform.trans(
             0.0,
             (MODULE_HEIGHT * (n_modules / 2 + n_modules % 2)) as f64,
@@ -131,7 +139,7 @@ impl<'a, 'b: 'a> GraphicsState<'a, G2d<'b>> {
             32,
             LedColor::new(0.3, 0.3, 1.0),
             transform.trans(5.0, 30.0),
-            Some(&CW_LABELS),
+            Some(&names.labels()),
         );
     }
 }
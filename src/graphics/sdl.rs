@@ -1,5 +1,5 @@
 use super::*;
-use crate::control::ControlWord;
+use crate::control::{ControlNames, ControlWord};
 use sdl2::gfx::primitives::DrawRenderer;
 pub use sdl2::{
     pixels::Color,
@@ -102,7 +102,7 @@ impl<'a> GraphicsState<'a> {
     ) -> Result<(i32, i32), String> {
         for bit in 1..=num_bits {
             let (x, y) = (x + (i32::from(bit) - 1) * 12 + 5, y);
-            let [r, g, b, _] = if (value & (1 << num_bits - bit)) > 0 {
+            let [r, g, b, _] = if (value & (1 << (num_bits - bit))) > 0 {
                 color.on_color
             } else {
                 color.off_color
@@ -128,7 +128,7 @@ impl<'a> GraphicsState<'a> {
         VisualRepresentation::led(bus).display(self, MODULE_WIDTH as i32 + 5, 35)
     }
 
-    pub fn display_cw(&mut self, cw: ControlWord) -> Result<(), String> {
+    pub fn display_cw(&mut self, cw: ControlWord, names: &ControlNames) -> Result<(), String> {
         let (x, y) = (5, MODULE_HEIGHT as i32 * self.n_lines + 5);
         self.write("Control Word", x, y)?;
         self.draw_leds(
@@ -136,12 +136,16 @@ impl<'a> GraphicsState<'a> {
             32,
             LedColor::new(0.3, 0.3, 1.0),
             (x, y + 30),
-            Some(&CW_LABELS),
+            Some(&names.labels()),
         )?;
         Ok(())
     }
 
-    pub fn display_modules(&mut self, modules: &[Box<dyn GraphicalModule>]) -> Result<(), String> {
+    pub fn display_modules(
+        &mut self,
+        modules: &[Box<dyn GraphicalModule>],
+        highlight: Option<&str>,
+    ) -> Result<(), String> {
         for (index, module) in modules.iter().enumerate() {
             let top_left_y = (index % self.n_lines as usize) * MODULE_HEIGHT;
             let top_left_x = if index >= self.n_lines as usize {
@@ -149,6 +153,17 @@ impl<'a> GraphicsState<'a> {
             } else {
                 0
             };
+            if highlight == Some(module.get_name()) {
+                self.canvas.set_draw_color((220, 0, 0));
+                for inset in 2..4 {
+                    self.canvas.draw_rect(Rect::new(
+                        top_left_x as i32 + inset,
+                        top_left_y as i32 + inset,
+                        MODULE_WIDTH as u32 - 2 * inset as u32,
+                        MODULE_HEIGHT as u32 - 2 * inset as u32,
+                    ))?;
+                }
+            }
             let (x, y) = (top_left_x as i32 + 5, top_left_y as i32 + 5);
             self.write(module.get_name(), x, y)?;
             module.representation().display(self, x, y + 30)?;
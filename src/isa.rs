@@ -0,0 +1,133 @@
+// The instruction set understood by the decoders. An instruction is a single
+// byte: the opcode lives in the high nibble and the operand in the low nibble.
+// The extended machine has 256 bytes of RAM, so its operands take the whole
+// byte following the opcode.
+//
+// The decoders match on these constants, and the assembler/disassembler are
+// built from INSTRUCTION_SET, so the encoding only lives in one place.
+
+use std::str::FromStr;
+
+pub const NOP: u8 = 0x0;
+pub const LDA: u8 = 0x1;
+pub const ADD: u8 = 0x2;
+pub const SUB: u8 = 0x3;
+pub const STA: u8 = 0x4;
+pub const LDI: u8 = 0x5;
+pub const JMP: u8 = 0x6;
+pub const JC: u8 = 0x7;
+pub const JZ: u8 = 0x8;
+pub const OUT: u8 = 0xe;
+pub const HLT: u8 = 0xf;
+
+/// How instructions are laid out in memory
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// One byte, the operand in the low nibble, for 16 bytes of RAM
+    #[default]
+    Compact,
+    /// The opcode byte then, for instructions that take one, an operand byte,
+    /// for 256 bytes of RAM
+    Extended,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "compact" => Ok(Encoding::Compact),
+            "extended" => Ok(Encoding::Extended),
+            _ => Err(format!("unknown instruction encoding {}", s)),
+        }
+    }
+}
+
+impl Encoding {
+    /// Bytes of RAM that programs can address
+    pub fn ram_size(self) -> usize {
+        match self {
+            Encoding::Compact => 16,
+            Encoding::Extended => 256,
+        }
+    }
+
+    /// Largest operand an instruction can hold
+    pub fn max_operand(self) -> u8 {
+        (self.ram_size() - 1) as u8
+    }
+}
+
+/// How the operand of an instruction is interpreted
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    /// Address of a byte in RAM that is read or written
+    Memory,
+    /// Address of the next instruction to execute
+    Jump,
+    /// Literal value
+    Immediate,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub opcode: u8,
+    pub operand: Operand,
+}
+
+impl Instruction {
+    pub fn encode(&self, operand: u8) -> u8 {
+        (self.opcode << 4) | (operand & 0xf)
+    }
+
+    /// Bytes taken by the instruction in memory
+    pub fn size(&self, encoding: Encoding) -> usize {
+        match (encoding, self.operand) {
+            (Encoding::Extended, Operand::None) | (Encoding::Compact, _) => 1,
+            (Encoding::Extended, _) => 2,
+        }
+    }
+
+    pub fn encode_with(&self, encoding: Encoding, operand: u8) -> Vec<u8> {
+        match self.size(encoding) {
+            1 if encoding == Encoding::Extended => vec![self.opcode << 4],
+            1 => vec![self.encode(operand)],
+            _ => vec![self.opcode << 4, operand],
+        }
+    }
+}
+
+const fn instruction(mnemonic: &'static str, opcode: u8, operand: Operand) -> Instruction {
+    Instruction {
+        mnemonic,
+        opcode,
+        operand,
+    }
+}
+
+pub const INSTRUCTION_SET: [Instruction; 11] = [
+    instruction("NOP", NOP, Operand::None),
+    instruction("LDA", LDA, Operand::Memory),
+    instruction("ADD", ADD, Operand::Memory),
+    instruction("SUB", SUB, Operand::Memory),
+    instruction("STA", STA, Operand::Memory),
+    instruction("LDI", LDI, Operand::Immediate),
+    instruction("JMP", JMP, Operand::Jump),
+    instruction("JC", JC, Operand::Jump),
+    instruction("JZ", JZ, Operand::Jump),
+    instruction("OUT", OUT, Operand::None),
+    instruction("HLT", HLT, Operand::None),
+];
+
+/// Case-insensitive lookup
+pub fn by_mnemonic(mnemonic: &str) -> Option<&'static Instruction> {
+    INSTRUCTION_SET
+        .iter()
+        .find(|i| i.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
+
+pub fn by_opcode(opcode: u8) -> Option<&'static Instruction> {
+    INSTRUCTION_SET.iter().find(|i| i.opcode == opcode)
+}
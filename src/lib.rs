@@ -1,7 +1,19 @@
+pub mod assembler;
 pub mod breadboard_builder;
+pub mod breakpoints;
+pub mod bus;
+pub mod decoder_diff;
+pub mod disassembler;
+pub mod eeprom;
 pub mod graphics;
+pub mod isa;
+pub mod microcode_compiler;
+pub mod microcode_lint;
 pub mod modules;
+pub mod program;
+pub mod save_state;
 pub mod shareable;
 pub mod state;
+pub mod trace;
 
 use modules::*;
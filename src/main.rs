@@ -1,13 +1,31 @@
+pub mod assembler;
+pub mod breadboard_builder;
+pub mod breakpoints;
+pub mod bus;
+pub mod disassembler;
+pub mod eeprom;
 pub mod graphics;
+pub mod isa;
 pub mod modules;
+pub mod program;
+pub mod save_state;
 pub mod shareable;
 pub mod state;
+pub mod trace;
 
-use clap::{App, Arg};
+use breadboard_builder::{BreadboardBuilder, MachineDescription, ModuleSpec};
+use clap::{App, Arg, ArgMatches, Values};
+use eeprom::{parse_active_low, ChipLayout};
 use graphics::*;
 use modules::*;
-use state::{write_sample_program, BreadboardState};
+use program::{load_program, ProgramFormat};
+use save_state::SaveState;
+use breakpoints::{Breakpoint, Breakpoints};
+use bus::{ContentionPolicy, FloatingPolicy};
+use isa::Encoding;
+use state::{write_extended_sample_program, write_sample_program, BreadboardState};
 use std::time::{Duration, Instant};
+use trace::{FileSink, StdoutSink, TraceFormat, Tracer};
 
 fn fibo(n: usize) -> usize {
     if n == 0 || n == 1 {
@@ -22,7 +40,7 @@ fn fibo(n: usize) -> usize {
 }
 
 #[cfg(feature = "piston")]
-fn interactive_loop_piston<I>(mut state: BreadboardState<I>) -> Result<(), String>
+fn interactive_loop_piston<I>(mut state: BreadboardState<I>, save_path: &str) -> Result<(), String>
 where
     I: InstructionDecoder,
 {
@@ -37,18 +55,30 @@ where
     let mut clock_divider = 2;
     let mut cycle_number = 0;
     while let Some(e) = window.next() {
+        print_warnings(&mut state);
         if let (Some(_), true) = (e.update_args(), changed) {
             changed = false;
-            state.pre_step();
+            state.settle();
+            if let (Some(hit), false) = (state.breakpoint_hit(), manual) {
+                println!("Breakpoint {}", hit);
+                manual = true;
+                window.events.set_lazy(manual);
+            }
+            if let (Some(contention), true, false) = (state.bus_contention(), state.halted(), manual) {
+                println!("Bus contention: {}", contention);
+                manual = true;
+                window.events.set_lazy(manual);
+            }
         }
 
         window.draw_2d(&e, |c, g, device| {
             clear([0.75, 0.73, 0.7, 1.0], g);
             let mut graphics = GraphicsState::new(c, g, glyphs);
             graphics.draw_lines(n_modules);
-            graphics.display_modules(&state.modules());
+            let highlight = state.breakpoint_hit().and_then(|hit| hit.module.as_deref());
+            graphics.display_modules(&state.modules(), highlight);
             graphics.display_bus(state.bus());
-            graphics.display_cw(state.cw(), n_modules);
+            graphics.display_cw(state.cw(), state.control_names(), n_modules);
             glyphs.factory.encoder.flush(device);
         });
 
@@ -57,8 +87,7 @@ where
             if !manual && cycle_number % fibo(clock_divider) == 0 {
                 cycle_number = 0;
                 changed = true;
-                state.rising_edge();
-                state.falling_edge();
+                state.step_cycle();
             }
         });
 
@@ -66,10 +95,15 @@ where
             if let Button::Keyboard(key) = button {
                 match key {
                     Key::Return if manual => {
-                        state.rising_edge();
-                        state.falling_edge();
-                        state.pre_step();
+                        state.step_cycle();
+                    }
+                    Key::Backspace if manual => {
+                        state.step_back_cycle();
                     }
+                    Key::B if manual => {
+                        state.step_back_instruction();
+                    }
+                    Key::S => save_state(&state, save_path),
                     Key::R => {
                         changed = true;
                         state.reset();
@@ -100,6 +134,7 @@ where
 #[cfg(not(feature = "piston"))]
 fn handle_event<I>(
     state: &mut BreadboardState<I>,
+    save_path: &str,
     manual: &mut bool,
     clock_divider: &mut usize,
     event: sdl2::event::Event,
@@ -114,10 +149,18 @@ where
     {
         match key {
             Keycode::Return if *manual => {
-                state.rising_edge();
-                state.falling_edge();
+                state.step_cycle();
+                return true;
+            }
+            Keycode::Backspace if *manual => {
+                state.step_back_cycle();
+                return true;
+            }
+            Keycode::B if *manual => {
+                state.step_back_instruction();
                 return true;
             }
+            Keycode::S => save_state(state, save_path),
             Keycode::C => {
                 *manual = !*manual;
             }
@@ -137,7 +180,7 @@ where
     false
 }
 
-fn interactive_loop_sdl<I>(mut state: BreadboardState<I>) -> Result<(), String>
+fn interactive_loop_sdl<I>(mut state: BreadboardState<I>, save_path: &str) -> Result<(), String>
 where
     I: InstructionDecoder,
 {
@@ -156,14 +199,24 @@ where
     loop {
         if changed {
             changed = false;
-            state.pre_step();
+            state.settle();
+            print_warnings(&mut state);
+            if let (Some(hit), false) = (state.breakpoint_hit(), manual) {
+                println!("Breakpoint {}", hit);
+                manual = true;
+            }
+            if let (Some(contention), true, false) = (state.bus_contention(), state.halted(), manual) {
+                println!("Bus contention: {}", contention);
+                manual = true;
+            }
         }
         graphics.canvas.set_draw_color((191, 186, 179));
         graphics.canvas.clear();
         graphics.draw_lines()?;
-        graphics.display_modules(state.modules())?;
+        let highlight = state.breakpoint_hit().and_then(|hit| hit.module.as_deref());
+        graphics.display_modules(state.modules(), highlight)?;
         graphics.display_bus(state.bus())?;
-        graphics.display_cw(state.cw())?;
+        graphics.display_cw(state.cw(), state.control_names())?;
         graphics.canvas.present();
         let last_render = Instant::now();
         if !manual {
@@ -171,8 +224,7 @@ where
             if cycle_number % fibo(clock_divider) == 0 {
                 changed = true;
                 cycle_number = 0;
-                state.rising_edge();
-                state.falling_edge();
+                state.step_cycle();
             }
         }
         while last_render.elapsed() < frame_duration {
@@ -194,13 +246,13 @@ where
             {
                 return Ok(());
             }
-            changed |= handle_event(&mut state, &mut manual, &mut clock_divider, event);
+            changed |= handle_event(&mut state, save_path, &mut manual, &mut clock_divider, event);
         }
     }
 }
 
 #[allow(unused)]
-fn write_program(ram: &mut [u8; 16]) {
+fn write_program(ram: &mut [u8]) {
     ram[0x0] = 0x1e; // LDA 14
     ram[0x1] = 0x2f; // ADD 15
     ram[0x2] = 0xe0; // OUT
@@ -210,15 +262,19 @@ fn write_program(ram: &mut [u8; 16]) {
 }
 
 #[cfg(feature = "piston")]
-const DEFAULT_INTERACTIVE_LOOP: &'static str = "piston";
+const DEFAULT_INTERACTIVE_LOOP: &str = "piston";
 #[cfg(not(feature = "piston"))]
-const DEFAULT_INTERACTIVE_LOOP: &'static str = "sdl";
+const DEFAULT_INTERACTIVE_LOOP: &str = "sdl";
 
 #[cfg(feature = "piston")]
-fn interactive_loop<I: InstructionDecoder>(backend: &str, state: BreadboardState<I>) -> Result<(), String> {
+fn interactive_loop<I: InstructionDecoder>(
+    backend: &str,
+    state: BreadboardState<I>,
+    save_path: &str,
+) -> Result<(), String> {
     match backend {
-        "piston" => interactive_loop_piston(state),
-        "sdl" => interactive_loop_sdl(state),
+        "piston" => interactive_loop_piston(state, save_path),
+        "sdl" => interactive_loop_sdl(state, save_path),
         _ => {
             eprintln!("Unknown rendering backend {}", name);
             std::process::exit(1);
@@ -227,20 +283,241 @@ fn interactive_loop<I: InstructionDecoder>(backend: &str, state: BreadboardState
 }
 
 #[cfg(not(feature = "piston"))]
-fn interactive_loop<I: InstructionDecoder>(backend: &str, state: BreadboardState<I>) -> Result<(), String> {
+fn interactive_loop<I: InstructionDecoder>(
+    backend: &str,
+    state: BreadboardState<I>,
+    save_path: &str,
+) -> Result<(), String> {
     if backend == "piston" {
         eprintln!("Error: The program was not compiled with piston enabled.");
         eprintln!("To enable piston, recompile with `--features \"piston\"`");
         std::process::exit(1);
     }
     if backend == "sdl" {
-        interactive_loop_sdl(state)
+        interactive_loop_sdl(state, save_path)
     } else {
         eprintln!("Unknown rendering backend {}", backend);
         std::process::exit(1);
     }
 }
 
+fn save_state<I: InstructionDecoder>(state: &BreadboardState<I>, path: &str) {
+    match state.save_state().save(path) {
+        Ok(()) => println!("Saved state to {}", path),
+        Err(e) => eprintln!("Could not save state: {}", e),
+    }
+}
+
+const DEFAULT_STATE_FILE: &str = "breadboard.state";
+
+const EXIT_HALTED: i32 = 0;
+const EXIT_ERROR: i32 = 1;
+const EXIT_CYCLE_LIMIT: i32 = 2;
+const EXIT_BREAKPOINT: i32 = 3;
+const EXIT_BUS_CONTENTION: i32 = 4;
+
+fn print_machine_state<I: InstructionDecoder>(state: &BreadboardState<I>) {
+    for name in &["Output", "A Register", "B Register"] {
+        if let Some(value) = state.module(name).and_then(|m| m.value()) {
+            println!("{:>10}: {}", name, value);
+        }
+    }
+    for module in state.modules().iter() {
+        if let Some(memory) = module.memory() {
+            println!("{}:", module.get_name());
+            for (row, bytes) in memory.chunks(16).enumerate() {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                println!("  {:#04x}: {}", module.memory_start() + row * 16, bytes.join(" "));
+            }
+        }
+    }
+}
+
+fn print_warnings<I: InstructionDecoder>(state: &mut BreadboardState<I>) {
+    for (cycle, warning) in state.take_warnings() {
+        eprintln!("Warning: cycle {}: {}", cycle, warning);
+    }
+}
+
+fn headless_loop<I>(mut state: BreadboardState<I>, max_cycles: u64, save_path: Option<&str>) -> i32
+where
+    I: InstructionDecoder,
+{
+    let summary = state.run_until_halt(max_cycles);
+    print_warnings(&mut state);
+    let code = if let (true, Some(contention)) = (summary.halted, state.bus_contention()) {
+        println!("Bus contention after {} cycles: {}", summary.cycles, contention);
+        EXIT_BUS_CONTENTION
+    } else if summary.halted {
+        println!("Halted after {} cycles", summary.cycles);
+        EXIT_HALTED
+    } else if let (true, Some(hit)) = (summary.breakpoint, state.breakpoint_hit()) {
+        println!("Breakpoint {} after {} cycles", hit, summary.cycles);
+        EXIT_BREAKPOINT
+    } else {
+        println!("Cycle limit of {} reached", max_cycles);
+        EXIT_CYCLE_LIMIT
+    };
+    print_machine_state(&state);
+    if let Some(path) = save_path {
+        save_state(&state, path);
+    }
+    code
+}
+
+fn tracer(matches: &ArgMatches) -> Result<Tracer, String> {
+    let format: TraceFormat = matches.value_of("trace").unwrap().parse()?;
+    Ok(match matches.value_of("trace-file") {
+        Some(filename) => {
+            let sink = FileSink::create(filename).map_err(|e| format!("{}: {}", filename, e))?;
+            Tracer::new(format, sink)
+        }
+        None => Tracer::new(format, StdoutSink),
+    })
+}
+
+fn breakpoints(matches: &ArgMatches) -> Result<Breakpoints, String> {
+    let breakpoints = match matches.values_of("break") {
+        Some(values) => values.map(str::parse).collect::<Result<Vec<Breakpoint>, _>>()?,
+        None => Vec::new(),
+    };
+    Ok(Breakpoints::new(breakpoints))
+}
+
+fn run<I: InstructionDecoder>(matches: &ArgMatches, state: BreadboardState<I>) -> i32 {
+    let state = match tracer(matches) {
+        Ok(tracer) => state.with_tracer(tracer),
+        Err(e) => {
+            eprintln!("Could not set up trace: {}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let state = match breakpoints(matches) {
+        Ok(breakpoints) => state.with_breakpoints(breakpoints),
+        Err(e) => {
+            eprintln!("Invalid breakpoint: {}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let policy: ContentionPolicy = matches.value_of("bus-contention").unwrap().parse().unwrap();
+    let state = state.with_contention_policy(policy);
+    let policy: FloatingPolicy = matches.value_of("floating-bus").unwrap().parse().unwrap();
+    let state = state.with_floating_policy(policy);
+    if matches.is_present("headless") {
+        let max_cycles = match matches.value_of("max-cycles").unwrap().parse() {
+            Ok(max_cycles) => max_cycles,
+            Err(e) => {
+                eprintln!("Invalid cycle limit: {}", e);
+                return EXIT_ERROR;
+            }
+        };
+        return headless_loop(state, max_cycles, matches.value_of("save-state"));
+    }
+    let backend = matches.value_of("backend").unwrap_or(DEFAULT_INTERACTIVE_LOOP);
+    let save_path = matches.value_of("save-state").unwrap_or(DEFAULT_STATE_FILE);
+    if let Err(s) = interactive_loop(backend, state, save_path) {
+        eprintln!("Error: {}", s);
+        return EXIT_ERROR;
+    }
+    EXIT_HALTED
+}
+
+fn run_loaded<I: InstructionDecoder>(
+    matches: &ArgMatches,
+    mut state: BreadboardState<I>,
+    saved: &SaveState,
+) -> i32 {
+    match state.load_state(saved) {
+        Ok(()) => run(matches, state),
+        Err(e) => {
+            eprintln!("Could not load state: {}", e);
+            EXIT_ERROR
+        }
+    }
+}
+
+fn load_state(matches: &ArgMatches, path: &str) -> i32 {
+    let saved = match SaveState::load(path) {
+        Ok(saved) => saved,
+        Err(e) => {
+            eprintln!("Could not load state: {}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let machine = match matches.value_of("machine") {
+        Some(path) => match MachineDescription::load(path).and_then(|d| d.load_roms()) {
+            Ok(machine) => machine,
+            Err(e) => {
+                eprintln!("Could not build machine: {}", e);
+                return EXIT_ERROR;
+            }
+        },
+        None if matches.is_present("extended") || saved.decoder == DecoderKind::Extended => {
+            BreadboardBuilder::extended()
+        }
+        None => BreadboardBuilder::sap1(),
+    };
+    let state = match saved.decoder {
+        DecoderKind::Simple => machine.build(|_| (), |instruction, _| {
+            Ok(SimpleInstructionDecoder::new(instruction))
+        })
+        .map(|state| run_loaded(matches, state, &saved)),
+        DecoderKind::Branching => machine
+            .build(|_| (), |instruction, flags| {
+                Ok(BranchingInstructionDecoder::new(instruction, flags))
+            })
+            .map(|state| run_loaded(matches, state, &saved)),
+        DecoderKind::Extended => machine
+            .build(|_| (), |instruction, flags| {
+                Ok(ExtendedInstructionDecoder::new(instruction, flags))
+            })
+            .map(|state| run_loaded(matches, state, &saved)),
+        DecoderKind::Microcode => {
+            let microcode = saved.microcode.clone().unwrap_or_default();
+            let layout = saved.layout.unwrap_or_default().without_byte_select();
+            machine.build(|_| (), |instruction, flags| {
+                Ok(MicrocodeDecoder::with_layout(instruction, flags, layout, microcode)?
+                    .with_steps(saved.steps)?
+                    .with_control_names(saved.names.clone()))
+            })
+            .map(|state| run_loaded(matches, state, &saved))
+        }
+    };
+    state.unwrap_or_else(|e| {
+        eprintln!("Could not load state: {}", e);
+        EXIT_ERROR
+    })
+}
+
+fn eeprom_microcode(
+    matches: &ArgMatches,
+    dumps: Values,
+) -> Result<Box<[u32; MICROCODE_SIZE]>, String> {
+    let combined = matches.is_present("combined");
+    if combined && dumps.len() != 1 {
+        return Err(format!("{} dumps given, expected a single combined image", dumps.len()));
+    }
+    if !(1..=4).contains(&dumps.len()) {
+        return Err(format!("{} dumps given, expected 1 to 4, one per chip", dumps.len()));
+    }
+    let mut images = dumps
+        .map(|path| std::fs::read(path).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let chips = match matches.value_of("chips") {
+        Some(chips) => chips.parse().map_err(|_| format!("invalid chip count {}", chips))?,
+        None => images.len(),
+    };
+    let layout = ChipLayout {
+        chips,
+        layout: matches.value_of("layout").map(str::parse).transpose()?.unwrap_or_default(),
+        active_low: parse_active_low(matches.value_of("active-low").unwrap_or("none"))?,
+    };
+    if combined {
+        images = layout.split_combined(&images[0])?;
+    }
+    layout.import(&images)
+}
+
 fn main() {
     let matches = App::new("8bit computer")
         .version("0.1.1")
@@ -253,6 +530,68 @@ fn main() {
                 .help("Use microcode from file instead of predefined logic")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("machine")
+                .long("machine")
+                .value_name("FILE")
+                .conflicts_with_all(&["microcode", "eeprom"])
+                .help(concat!("Build the machine from a TOML or RON description of its modules ",
+                              "and decoder instead of the breadboard build")),
+        )
+        .arg(
+            Arg::with_name("extended")
+                .long("extended")
+                .conflicts_with("machine")
+                .help(concat!("Use the extended machine, with 256 bytes of RAM and ",
+                              "instructions followed by their operand")),
+        )
+        .arg(
+            Arg::with_name("eeprom")
+                .long("eeprom")
+                .value_name("FILE")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with("microcode")
+                .help(concat!("Use microcode rebuilt from EEPROM dumps, given once per ",
+                              "chip starting with the least significant byte")),
+        )
+        .arg(
+            Arg::with_name("layout")
+                .long("layout")
+                .value_name("FIELDS")
+                .requires("eeprom")
+                .help(concat!("Address lines of the EEPROMs, most significant first, with their width ",
+                              "(flags:4,instruction:4,step:4 by default)")),
+        )
+        .arg(
+            Arg::with_name("active-low")
+                .long("active-low")
+                .value_name("FLAGS")
+                .requires("eeprom")
+                .help(concat!("Control lines stored inverted in the EEPROMs: comma-separated flags, ",
+                              "none (the default) or all")),
+        )
+        .arg(
+            Arg::with_name("combined")
+                .long("combined")
+                .requires_all(&["eeprom", "chips"])
+                .help(concat!("Read a single dump holding every chip, the chip number being the ",
+                              "select lines of the layout, or the upper address lines if it has none")),
+        )
+        .arg(
+            Arg::with_name("chips")
+                .long("chips")
+                .value_name("N")
+                .requires("combined")
+                .help("Number of chips in the --combined dump"),
+        )
+        .arg(
+            Arg::with_name("steps")
+                .long("steps")
+                .value_name("N")
+                .requires("eeprom")
+                .help("Steps of the EEPROM microcode before the step counter wraps around, 5 by default"),
+        )
         .arg(
             Arg::with_name("backend")
                 .short("b")
@@ -261,23 +600,176 @@ fn main() {
                 .possible_values(&["sdl", "piston"])
                 .help(concat!("Select the graphics backend if compiled with ",
                               "piston enabled, piston is the default.")),
+        )
+        .arg(
+            Arg::with_name("program")
+                .short("p")
+                .long("program")
+                .value_name("FILE")
+                .help("Load a program into RAM instead of the sample program")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .possible_values(&["bin", "hex", "asm"])
+                .requires("program")
+                .help(concat!("Format of the program file (raw binary, Intel HEX ",
+                              "or assembly), detected from the file if omitted")),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Run without a window until the computer halts"),
+        )
+        .arg(
+            Arg::with_name("max-cycles")
+                .long("max-cycles")
+                .value_name("N")
+                .default_value("10000")
+                .help("Stop a headless run after N clock cycles"),
+        )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .value_name("FORMAT")
+                .possible_values(&["none", "compact", "full", "json"])
+                .default_value("full")
+                .help("How much of the machine state to print every clock cycle"),
+        )
+        .arg(
+            Arg::with_name("trace-file")
+                .long("trace-file")
+                .value_name("FILE")
+                .help("Write the trace to FILE instead of stdout"),
+        )
+        .arg(
+            Arg::with_name("break")
+                .long("break")
+                .value_name("BREAKPOINT")
+                .multiple(true)
+                .number_of_values(1)
+                .help(concat!("Stop running when pc=ADDRESS is fetched, op=MNEMONIC is ",
+                              "executed, flag=FLAG is asserted, or when ram=ADDRESS or ",
+                              "watch=MODULE changes. Can be given multiple times")),
+        )
+        .arg(
+            Arg::with_name("bus-contention")
+                .long("bus-contention")
+                .value_name("POLICY")
+                .possible_values(&["halt", "warn", "and", "or"])
+                .default_value("warn")
+                .help(concat!("What to do when several modules write to the bus at once: ",
+                              "halt, warn and let the first module win, or emulate a ",
+                              "wired-AND or wired-OR bus")),
+        )
+        .arg(
+            Arg::with_name("floating-bus")
+                .long("floating-bus")
+                .value_name("POLICY")
+                .possible_values(&["zero", "pull-up", "previous", "random"])
+                .default_value("zero")
+                .help(concat!("What the bus reads as when no module writes to it: 0, ",
+                              "0xff, the last value written to it or random values")),
+        )
+        .arg(
+            Arg::with_name("load-state")
+                .long("load-state")
+                .value_name("FILE")
+                .conflicts_with_all(&["microcode", "eeprom", "program"])
+                .help(concat!("Resume a machine saved with the S key. Machines built from a ",
+                              "description need the same --machine")),
+        )
+        .arg(
+            Arg::with_name("save-state")
+                .long("save-state")
+                .value_name("FILE")
+                .help(concat!("Where the S key saves the machine (breadboard.state by ",
+                              "default). Headless runs save the machine there when they stop")),
         ).get_matches();
-    let backend = matches.value_of("backend").unwrap_or(DEFAULT_INTERACTIVE_LOOP);
-    if let Some(microcode_filename) = matches.value_of("microcode") {
-        let breadboard =
-            match BreadboardState::from_microcode(microcode_filename, write_sample_program) {
-                Err(s) => {
-                    eprintln!("Could not open microcode file: {}", s);
-                    std::process::exit(1);
-                }
-                Ok(state) => state,
+    if let Some(path) = matches.value_of("load-state") {
+        std::process::exit(load_state(&matches, path));
+    }
+    let description = matches.value_of("machine").map(|path| {
+        MachineDescription::load(path).unwrap_or_else(|s| {
+            eprintln!("Could not build machine: {}", s);
+            std::process::exit(EXIT_ERROR);
+        })
+    });
+    let machine = match &description {
+        Some(description) => description.builder(),
+        None if matches.is_present("extended") => BreadboardBuilder::extended(),
+        None => BreadboardBuilder::sap1(),
+    };
+    let encoding = machine.get_encoding();
+    let program = matches.value_of("program").map(|filename| {
+        let format = matches.value_of("format").map(|f| f.parse::<ProgramFormat>().unwrap());
+        match load_program(filename, format, encoding, machine.ram_size().unwrap_or(0)) {
+            Ok(image) => image,
+            Err(s) => {
+                eprintln!("Could not load program: {}", s);
+                std::process::exit(EXIT_ERROR);
+            }
+        }
+    });
+    // Machines with a ROM already have a program
+    let has_rom = machine.modules().iter().any(|m| matches!(m, ModuleSpec::Rom { .. }));
+    let ram_init = |ram: &mut [u8]| match program {
+        Some(image) => ram[..image.len()].copy_from_slice(&image),
+        None if has_rom => (),
+        None if encoding == Encoding::Extended => write_extended_sample_program(ram),
+        None => write_sample_program(ram),
+    };
+    let code = if let Some(description) = description {
+        match description.build(ram_init) {
+            Err(s) => {
+                eprintln!("Could not build machine: {}", s);
+                EXIT_ERROR
+            }
+            Ok(state) => run(&matches, state),
+        }
+    } else if let Some(microcode_filename) = matches.value_of("microcode") {
+        let state = machine.build(ram_init, |instruction, flags| {
+            MicrocodeDecoder::from_file(instruction, flags, microcode_filename)
+        });
+        match state {
+            Err(s) => {
+                eprintln!("Could not load microcode: {}", s);
+                EXIT_ERROR
+            }
+            Ok(state) => run(&matches, state),
+        }
+    } else if let Some(dumps) = matches.values_of("eeprom") {
+        let state = eeprom_microcode(&matches, dumps).and_then(|microcode| {
+            let steps = match matches.value_of("steps") {
+                None => DEFAULT_STEPS,
+                Some(steps) => steps
+                    .parse()
+                    .map_err(|_| format!("invalid step count {}", steps))?,
             };
-        interactive_loop(backend, breadboard).unwrap();
-    } else {
-        let breadboard = BreadboardState::default_with_ram(write_sample_program);
-        if let Err(s) = interactive_loop(backend, breadboard) {
-            eprintln!("Error: {}", s);
-            std::process::exit(1);
+            machine.build(ram_init, |instruction, flags| {
+                MicrocodeDecoder::with_microcode(instruction, flags, microcode).with_steps(steps)
+            })
+        });
+        match state {
+            Err(s) => {
+                eprintln!("Could not load EEPROM dumps: {}", s);
+                EXIT_ERROR
+            }
+            Ok(state) => run(&matches, state),
         }
-    }
+    } else if encoding == Encoding::Extended {
+        let state = machine.build(ram_init, |instruction, flags| {
+            Ok(ExtendedInstructionDecoder::new(instruction, flags))
+        });
+        run(&matches, state.unwrap())
+    } else {
+        let state = machine.build(ram_init, |instruction, flags| {
+            Ok(BranchingInstructionDecoder::new(instruction, flags))
+        });
+        run(&matches, state.unwrap())
+    };
+    std::process::exit(code);
 }
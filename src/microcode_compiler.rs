@@ -0,0 +1,380 @@
+// Compiler from a textual microcode description to a microcode image, so that
+// new instructions can be designed without writing Rust.
+//
+// Syntax:
+//
+//     ; comments start with a semicolon
+//     fetch:                  ; steps run at the start of every instruction
+//         CO MI
+//         RO II CE
+//     LDA:                    ; known mnemonics use the opcode from `isa`
+//         IO MI
+//         RO AI NI            ; one step per line
+//     SWP 0x9:                ; other instructions give their opcode
+//         -                   ; `-` is a step with no flag set
+//     JC:
+//         if C: IO J NI       ; conditional step, the first matching
+//         else: NI            ; condition wins and `else` ends the step
+//     flag 17 ShiftLeft SL    ; names a control bit wired to a custom module,
+//                             ; with an optional label for the LEDs
+//     steps 8                 ; steps before the counter wraps (5 by default)
+//
+// Control flags are given by label (`RO`) or name (`RamOut`). Conditions test
+// the carry (`C`) and zero (`Z`) flags, negated with `!` and combined with
+// `&`, e.g. `if C & !Z:`. Opcodes that are not described only run the fetch
+// steps.
+use crate::assembler::parse_number;
+use crate::isa;
+use crate::modules::{
+    AddressLayout, ControlNames, MicrocodeAddress, DEFAULT_STEPS, MICROCODE_SIZE,
+};
+use std::fmt::{self, Display, Formatter};
+
+const CARRY: u8 = 0b10;
+const ZERO: u8 = 0b01;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnknownMnemonic(String),
+    UnknownFlag(String),
+    InvalidOpcode(String),
+    InvalidCondition(String),
+    InvalidHeader(String),
+    InvalidFlagDeclaration(String),
+    FlagBitTaken(u8),
+    DuplicateFlagName(String),
+    InvalidSteps(String),
+    DuplicateSteps,
+    DuplicateOpcode(u8),
+    DuplicateFetch,
+    StepOutsideSection,
+    ElseWithoutIf,
+    TooManySteps {
+        name: String,
+        steps: usize,
+        max: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    /// 1-based line number in the source
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use ErrorKind::*;
+        match self {
+            UnknownMnemonic(m) => write!(f, "unknown mnemonic `{}`, give its opcode", m),
+            UnknownFlag(flag) => write!(f, "unknown control flag `{}`", flag),
+            InvalidOpcode(o) => write!(f, "invalid opcode `{}` (expected 0 to 15)", o),
+            InvalidCondition(c) => write!(f, "invalid condition `{}`", c),
+            InvalidHeader(h) => write!(f, "invalid section header `{}`", h),
+            InvalidFlagDeclaration(d) => {
+                write!(
+                    f,
+                    "invalid flag declaration `{}`, expected `flag BIT NAME [LABEL]`",
+                    d
+                )
+            }
+            FlagBitTaken(bit) => write!(f, "control bit {} already has a name", bit),
+            DuplicateFlagName(name) => write!(f, "control flag `{}` already exists", name),
+            InvalidSteps(s) => write!(
+                f,
+                "invalid step count `{}` (expected 1 to {})",
+                s,
+                AddressLayout::default().max_steps()
+            ),
+            DuplicateSteps => write!(f, "the step count is already given"),
+            DuplicateOpcode(opcode) => write!(f, "opcode {:#x} is already described", opcode),
+            DuplicateFetch => write!(f, "the fetch steps are already described"),
+            StepOutsideSection => write!(f, "step outside of an instruction"),
+            ElseWithoutIf => write!(f, "`else` without a matching `if`"),
+            TooManySteps { name, steps, max } => write!(
+                f,
+                "{} has {} steps including fetch, but the step counter wraps at {}",
+                name, steps, max
+            ),
+        }
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+/// Flags that must be set and cleared for a conditional step to apply
+#[derive(Copy, Clone, Default)]
+struct Condition {
+    set: u8,
+    clear: u8,
+}
+
+impl Condition {
+    fn matches(self, flags: u8) -> bool {
+        flags & self.set == self.set && flags & self.clear == 0
+    }
+}
+
+#[derive(Default)]
+struct Step {
+    variants: Vec<(Condition, u32)>,
+    default: u32,
+}
+
+impl Step {
+    fn control_word(&self, flags: u8) -> u32 {
+        self.variants
+            .iter()
+            .find(|(condition, _)| condition.matches(flags))
+            .map_or(self.default, |(_, word)| *word)
+    }
+}
+
+struct Section {
+    name: String,
+    /// None for the fetch steps
+    opcode: Option<u8>,
+    line: usize,
+    steps: Vec<Step>,
+    /// Conditional step that has not been closed by `else` yet
+    pending: Option<Step>,
+}
+
+impl Section {
+    fn close_pending(&mut self) {
+        if let Some(step) = self.pending.take() {
+            self.steps.push(step);
+        }
+    }
+}
+
+/// A compiled microcode image and the names of the control bits it uses
+pub struct Compiled {
+    pub microcode: Box<[u32; MICROCODE_SIZE]>,
+    pub names: ControlNames,
+    /// Steps before the decoder counter wraps around
+    pub steps: usize,
+}
+
+fn parse_flags(s: &str, names: &ControlNames) -> Result<u32, ErrorKind> {
+    let s = s.trim();
+    if s == "-" {
+        return Ok(0);
+    }
+    s.split(|c: char| c.is_whitespace() || c == '|' || c == ',')
+        .filter(|flag| !flag.is_empty())
+        .try_fold(0, |word, flag| {
+            let bit = names
+                .find(flag)
+                .ok_or_else(|| ErrorKind::UnknownFlag(flag.to_string()))?;
+            Ok(word | 1 << bit)
+        })
+}
+
+fn parse_condition(s: &str) -> Result<Condition, ErrorKind> {
+    let invalid = || ErrorKind::InvalidCondition(s.trim().to_string());
+    let mut condition = Condition::default();
+    for term in s.split('&') {
+        let term = term.trim();
+        let (negated, name) = match term.strip_prefix('!') {
+            Some(name) => (true, name.trim()),
+            None => (false, term),
+        };
+        let bit = match name {
+            "C" | "c" => CARRY,
+            "Z" | "z" => ZERO,
+            _ => return Err(invalid()),
+        };
+        if negated {
+            condition.clear |= bit;
+        } else {
+            condition.set |= bit;
+        }
+    }
+    if condition.set & condition.clear != 0 {
+        return Err(invalid());
+    }
+    Ok(condition)
+}
+
+fn parse_header(header: &str) -> Result<(String, Option<u8>), ErrorKind> {
+    let words: Vec<&str> = header.split_whitespace().collect();
+    match words.as_slice() {
+        [name] if name.eq_ignore_ascii_case("fetch") => Ok(("fetch".to_string(), None)),
+        [name] => isa::by_mnemonic(name)
+            .map(|instruction| (instruction.mnemonic.to_string(), Some(instruction.opcode)))
+            .ok_or_else(|| ErrorKind::UnknownMnemonic(name.to_string())),
+        [name, opcode] => match parse_number(opcode) {
+            Some(value) if (0..16).contains(&value) => Ok((name.to_uppercase(), Some(value as u8))),
+            _ => Err(ErrorKind::InvalidOpcode(opcode.to_string())),
+        },
+        _ => Err(ErrorKind::InvalidHeader(header.to_string())),
+    }
+}
+
+/// Names a control bit from a `flag BIT NAME [LABEL]` line
+fn parse_flag_declaration(names: &mut ControlNames, line: &str) -> Result<(), ErrorKind> {
+    let invalid = || ErrorKind::InvalidFlagDeclaration(line.to_string());
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (bit, name, label) = match words.as_slice() {
+        [_, bit, name] => (bit, name, name),
+        [_, bit, name, label] => (bit, name, label),
+        _ => return Err(invalid()),
+    };
+    let bit = match parse_number(bit) {
+        Some(bit) if (0..32).contains(&bit) => bit as u8,
+        _ => return Err(invalid()),
+    };
+    if !names.name(bit).is_empty() {
+        return Err(ErrorKind::FlagBitTaken(bit));
+    }
+    for s in [name, label].iter() {
+        if names.find(s).is_some() {
+            return Err(ErrorKind::DuplicateFlagName(s.to_string()));
+        }
+    }
+    names.set(bit, name, label);
+    Ok(())
+}
+
+/// Adds a step line to the current section
+fn parse_step(section: &mut Section, line: &str, names: &ControlNames) -> Result<(), ErrorKind> {
+    let keyword = line.split_whitespace().next().unwrap_or("");
+    if keyword == "if" {
+        let colon = line
+            .find(':')
+            .ok_or_else(|| ErrorKind::InvalidCondition(line.to_string()))?;
+        let condition = parse_condition(&line[2..colon])?;
+        let word = parse_flags(&line[colon + 1..], names)?;
+        section
+            .pending
+            .get_or_insert_with(Step::default)
+            .variants
+            .push((condition, word));
+    } else if let Some(rest) = line.strip_prefix("else") {
+        let rest = rest.trim_start();
+        let flags = rest.strip_prefix(':').ok_or(ErrorKind::ElseWithoutIf)?;
+        let mut step = section.pending.take().ok_or(ErrorKind::ElseWithoutIf)?;
+        step.default = parse_flags(flags, names)?;
+        section.steps.push(step);
+    } else {
+        let word = parse_flags(line, names)?;
+        section.close_pending();
+        section.steps.push(Step {
+            variants: Vec::new(),
+            default: word,
+        });
+    }
+    Ok(())
+}
+
+/// Compiles a microcode description into an image for `MicrocodeDecoder`.
+/// Every error in the source is reported.
+pub fn compile(source: &str) -> Result<Compiled, Vec<CompileError>> {
+    let mut errors = Vec::new();
+    let mut sections: Vec<Section> = Vec::new();
+    let mut names = ControlNames::default();
+    let mut steps: Option<usize> = None;
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let line = line.split(';').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let is_step = line.starts_with("if ") || line.starts_with("else");
+        let keyword = line.split_whitespace().next();
+        let result = match line.strip_suffix(':') {
+            _ if keyword == Some("flag") => parse_flag_declaration(&mut names, line),
+            _ if keyword == Some("steps") => {
+                let count = line["steps".len()..].trim();
+                match parse_number(count) {
+                    _ if steps.is_some() => Err(ErrorKind::DuplicateSteps),
+                    Some(n) if (1..=AddressLayout::default().max_steps() as i64).contains(&n) => {
+                        steps = Some(n as usize);
+                        Ok(())
+                    }
+                    _ => Err(ErrorKind::InvalidSteps(count.to_string())),
+                }
+            }
+            Some(header) if !is_step => parse_header(header).and_then(|(name, opcode)| {
+                let duplicate = sections.iter().any(|s| s.opcode == opcode);
+                match (duplicate, opcode) {
+                    (true, Some(opcode)) => Err(ErrorKind::DuplicateOpcode(opcode)),
+                    (true, None) => Err(ErrorKind::DuplicateFetch),
+                    (false, _) => {
+                        sections.push(Section {
+                            name,
+                            opcode,
+                            line: number,
+                            steps: Vec::new(),
+                            pending: None,
+                        });
+                        Ok(())
+                    }
+                }
+            }),
+            _ => match sections.last_mut() {
+                Some(section) => parse_step(section, line, &names),
+                None => Err(ErrorKind::StepOutsideSection),
+            },
+        };
+        if let Err(kind) = result {
+            errors.push(CompileError { line: number, kind });
+        }
+    }
+    for section in sections.iter_mut() {
+        section.close_pending();
+    }
+
+    let no_steps = Vec::new();
+    let fetch = sections
+        .iter()
+        .find(|s| s.opcode.is_none())
+        .map_or(&no_steps, |s| &s.steps);
+    let max = steps.unwrap_or(DEFAULT_STEPS);
+    for section in sections.iter().filter(|s| s.opcode.is_some()) {
+        let steps = fetch.len() + section.steps.len();
+        if steps > max {
+            errors.push(CompileError {
+                line: section.line,
+                kind: ErrorKind::TooManySteps {
+                    name: section.name.clone(),
+                    steps,
+                    max,
+                },
+            });
+        }
+    }
+    if !errors.is_empty() {
+        errors.sort_by_key(|e| e.line);
+        return Err(errors);
+    }
+
+    let mut microcode = Box::new([0; MICROCODE_SIZE]);
+    for opcode in 0..16u8 {
+        let own = sections
+            .iter()
+            .find(|s| s.opcode == Some(opcode))
+            .map_or(&no_steps, |s| &s.steps);
+        for (step, microstep) in fetch.iter().chain(own.iter()).enumerate() {
+            for flags in 0..16u8 {
+                let address = MicrocodeAddress {
+                    flags,
+                    instruction: opcode,
+                    step: step as u8,
+                };
+                microcode[AddressLayout::default().pack(address)] = microstep.control_word(flags);
+            }
+        }
+    }
+    Ok(Compiled {
+        microcode,
+        names,
+        steps: max,
+    })
+}
@@ -0,0 +1,348 @@
+// Static checks on microcode images, for people writing their own microcode
+use crate::isa;
+use crate::modules::{
+    AddressLayout, ControlFlag, ControlNames, ControlWord, MicrocodeAddress, MicrocodeFile,
+    DEFAULT_STEPS, MICROCODE_SIZE,
+};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+/// The fetch cycle, shared by every instruction
+const FETCH_STEPS: u8 = 2;
+
+/// Flags that make a module write to the bus
+const BUS_WRITERS: [ControlFlag; 5] = [
+    ControlFlag::RamOut,
+    ControlFlag::InstructionRegisterOut,
+    ControlFlag::ARegisterOut,
+    ControlFlag::SumOut,
+    ControlFlag::CounterOut,
+];
+
+/// Flags that make a module read from the bus
+const BUS_READERS: [ControlFlag; 7] = [
+    ControlFlag::MemoryAddressIn,
+    ControlFlag::RamIn,
+    ControlFlag::InstructionRegisterIn,
+    ControlFlag::ARegisterIn,
+    ControlFlag::BRegisterIn,
+    ControlFlag::OutputRegisterIn,
+    ControlFlag::Jump,
+];
+
+/// Flag bits each conditional instruction is expected to depend on
+/// (carry is bit 1, zero is bit 0)
+pub const EXPECTED_FLAG_DEPENDENCE: [(u8, u8); 2] = [(isa::JC, 0b10), (isa::JZ, 0b01)];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LintKind {
+    MultipleWriters(Vec<ControlFlag>),
+    ReaderWithoutWriter(Vec<ControlFlag>),
+    /// Bits set that have no name, so are not wired to anything
+    UnusedBits(u32),
+    /// The instruction never asserts `NextInstruction` or `Hlt`, so it runs
+    /// until the counter wraps after the given number of steps
+    NoEnd(u8),
+    /// The fetch cycle is not the same as the one most instructions use
+    FetchDiffers {
+        expected: u32,
+        found: u32,
+    },
+    /// The control word depends on flag bits it should not depend on
+    FlagDependence(u8),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub instruction: u8,
+    /// None if the diagnostic is about the whole instruction
+    pub step: Option<u8>,
+    /// Values of the flags nibble for which the problem occurs
+    pub flags: Vec<u8>,
+    pub kind: LintKind,
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self.kind {
+            LintKind::NoEnd(_) | LintKind::FlagDependence(_) => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+fn flag_names(flags: &[ControlFlag]) -> String {
+    let names: Vec<String> = flags.iter().map(|flag| format!("{:?}", flag)).collect();
+    names.join(", ")
+}
+
+impl Display for LintKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            LintKind::MultipleWriters(flags) => {
+                write!(f, "{} all write to the bus", flag_names(flags))
+            }
+            LintKind::ReaderWithoutWriter(flags) => {
+                write!(
+                    f,
+                    "{} read from the bus but nothing writes to it",
+                    flag_names(flags)
+                )
+            }
+            LintKind::UnusedBits(bits) => write!(f, "unused control bits set: {:#x}", bits),
+            LintKind::NoEnd(steps) => write!(
+                f,
+                "never asserts NextInstruction or Hlt before the step counter wraps at {}",
+                steps
+            ),
+            LintKind::FetchDiffers { expected, found } => write!(
+                f,
+                "fetch cycle is {}, other instructions use {}",
+                ControlWord(*found),
+                ControlWord(*expected)
+            ),
+            LintKind::FlagDependence(bits) => {
+                write!(f, "behaves differently depending on flag bits {:04b}", bits)
+            }
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let severity = match self.severity() {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match isa::by_opcode(self.instruction) {
+            Some(instruction) => write!(f, "{}: {}", severity, instruction.mnemonic)?,
+            None => write!(f, "{}: opcode {:#x}", severity, self.instruction)?,
+        }
+        if let Some(step) = self.step {
+            write!(f, " step {}", step)?;
+        }
+        if self.flags.len() < 16 {
+            let flags: Vec<String> = self
+                .flags
+                .iter()
+                .map(|flags| format!("{:04b}", flags))
+                .collect();
+            write!(f, " (flags {})", flags.join(", "))?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+/// Steps of the instruction that run before it ends or the counter wraps
+fn reachable_steps(microcode: &[u32], steps: u8, flags: u8, instruction: u8) -> u8 {
+    for step in 0..steps {
+        let address = MicrocodeAddress {
+            flags,
+            instruction,
+            step,
+        };
+        let cw = ControlWord(microcode[AddressLayout::default().pack(address)]);
+        if cw.has(ControlFlag::NextInstruction) || cw.has(ControlFlag::Hlt) {
+            return step + 1;
+        }
+    }
+    steps
+}
+
+/// Control word used by most instructions for a fetch step
+fn usual_fetch(microcode: &[u32], step: u8) -> u32 {
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for instruction in 0..16 {
+        for flags in 0..16 {
+            let address = MicrocodeAddress {
+                flags,
+                instruction,
+                step,
+            };
+            *counts
+                .entry(microcode[AddressLayout::default().pack(address)])
+                .or_insert(0) += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(word, count)| (count, std::cmp::Reverse(word)))
+        .map_or(0, |(word, _)| word)
+}
+
+/// Adds a diagnostic, merging it with an identical one for other flags
+fn push(
+    diagnostics: &mut Vec<Diagnostic>,
+    flags: u8,
+    instruction: u8,
+    step: Option<u8>,
+    kind: LintKind,
+) {
+    let existing = diagnostics
+        .iter_mut()
+        .find(|d| d.instruction == instruction && d.step == step && d.kind == kind);
+    match existing {
+        Some(diagnostic) => diagnostic.flags.push(flags),
+        None => diagnostics.push(Diagnostic {
+            instruction,
+            step,
+            flags: vec![flags],
+            kind,
+        }),
+    }
+}
+
+/// Checks every reachable (flags, instruction, step) address of a microcode
+/// image laid out like `MicrocodeDecoder` expects
+pub fn lint(microcode: &[u32]) -> Vec<Diagnostic> {
+    lint_with_expected_flags(microcode, &EXPECTED_FLAG_DEPENDENCE)
+}
+
+/// Same as `lint`, with the flag bits each instruction may depend on
+pub fn lint_with_expected_flags(microcode: &[u32], expected: &[(u8, u8)]) -> Vec<Diagnostic> {
+    check(
+        microcode,
+        expected,
+        &ControlNames::default(),
+        DEFAULT_STEPS as u8,
+    )
+}
+
+/// Same as `lint`, with the layout, control bit names and step count of a
+/// microcode file
+pub fn lint_file(file: &MicrocodeFile) -> Vec<Diagnostic> {
+    let microcode = file
+        .layout
+        .convert(&file.microcode, &AddressLayout::default());
+    check(
+        &microcode,
+        &EXPECTED_FLAG_DEPENDENCE,
+        &file.names,
+        file.steps,
+    )
+}
+
+fn check(
+    microcode: &[u32],
+    expected: &[(u8, u8)],
+    names: &ControlNames,
+    max_steps: u8,
+) -> Vec<Diagnostic> {
+    assert_eq!(microcode.len(), MICROCODE_SIZE);
+    let layout = AddressLayout::default();
+    let used_bits = names.mask();
+    let fetch: Vec<u32> = (0..FETCH_STEPS)
+        .map(|step| usual_fetch(microcode, step))
+        .collect();
+    let mut diagnostics = Vec::new();
+    for instruction in 0..16 {
+        for flags in 0..16 {
+            let steps = reachable_steps(microcode, max_steps, flags, instruction);
+            if steps == max_steps {
+                let address = MicrocodeAddress {
+                    flags,
+                    instruction,
+                    step: max_steps - 1,
+                };
+                let last = ControlWord(microcode[layout.pack(address)]);
+                if !last.has(ControlFlag::NextInstruction) && !last.has(ControlFlag::Hlt) {
+                    let kind = LintKind::NoEnd(max_steps);
+                    push(&mut diagnostics, flags, instruction, None, kind);
+                }
+            }
+            for step in 0..steps {
+                let address = MicrocodeAddress {
+                    flags,
+                    instruction,
+                    step,
+                };
+                let word = microcode[layout.pack(address)];
+                let cw = ControlWord(word);
+                let writers: Vec<ControlFlag> =
+                    BUS_WRITERS.iter().copied().filter(|&f| cw.has(f)).collect();
+                let readers: Vec<ControlFlag> =
+                    BUS_READERS.iter().copied().filter(|&f| cw.has(f)).collect();
+                if writers.len() > 1 {
+                    push(
+                        &mut diagnostics,
+                        flags,
+                        instruction,
+                        Some(step),
+                        LintKind::MultipleWriters(writers),
+                    );
+                } else if writers.is_empty() && !readers.is_empty() {
+                    push(
+                        &mut diagnostics,
+                        flags,
+                        instruction,
+                        Some(step),
+                        LintKind::ReaderWithoutWriter(readers),
+                    );
+                }
+                if word & !used_bits != 0 {
+                    push(
+                        &mut diagnostics,
+                        flags,
+                        instruction,
+                        Some(step),
+                        LintKind::UnusedBits(word & !used_bits),
+                    );
+                }
+                if step < FETCH_STEPS {
+                    let expected = fetch[step as usize];
+                    if word != expected {
+                        let kind = LintKind::FetchDiffers {
+                            expected,
+                            found: word,
+                        };
+                        push(&mut diagnostics, flags, instruction, Some(step), kind);
+                    }
+                }
+            }
+        }
+        let allowed = expected
+            .iter()
+            .filter(|(opcode, _)| *opcode == instruction)
+            .fold(0, |allowed, (_, bits)| allowed | bits);
+        for step in 0..max_steps {
+            let mut dependence = 0;
+            for flags in 0..16u8 {
+                for bit in 0..4 {
+                    let other = flags ^ (1 << bit);
+                    let reachable = step
+                        < reachable_steps(microcode, max_steps, flags, instruction)
+                        && step < reachable_steps(microcode, max_steps, other, instruction);
+                    let address = MicrocodeAddress {
+                        flags,
+                        instruction,
+                        step,
+                    };
+                    let flipped = MicrocodeAddress {
+                        flags: other,
+                        ..address
+                    };
+                    if reachable
+                        && microcode[layout.pack(address)] != microcode[layout.pack(flipped)]
+                    {
+                        dependence |= 1 << bit;
+                    }
+                }
+            }
+            if dependence & !allowed != 0 {
+                diagnostics.push(Diagnostic {
+                    instruction,
+                    step: Some(step),
+                    flags: (0..16).collect(),
+                    kind: LintKind::FlagDependence(dependence & !allowed),
+                });
+            }
+        }
+    }
+    diagnostics
+}
@@ -4,6 +4,9 @@ use crate::shareable::{Share, Shareable, Shared};
 use std::fmt::{self, Display, Formatter};
 use std::num::Wrapping;
 
+/// Names of the flags bits, most significant first
+pub const FLAG_LABELS: [&str; 8] = ["", "", "", "", "", "", "C", "Z"];
+
 #[derive(Debug)]
 pub struct Alu {
     pub a: Shared<u8>,
@@ -23,7 +26,7 @@ impl Alu {
     }
 
     pub fn get_labels(&self) -> [&'static str; 8] {
-        ["", "", "", "", "", "", "C", "Z"]
+        FLAG_LABELS
     }
 }
 
@@ -64,6 +67,19 @@ impl Module for Alu {
         self.flags.set(0);
     }
 
+    fn value(&self) -> Option<u8> {
+        Some(self.result)
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        vec![self.result, self.flags.get()]
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        self.result = snapshot[0];
+        self.flags.set(snapshot[1]);
+    }
+
     fn bus_write_flag(&self) -> ControlFlag {
         ControlFlag::SumOut
     }
@@ -74,7 +90,7 @@ impl Module for Alu {
 }
 
 impl GraphicalModule for Alu {
-    fn representation(&self) -> VisualRepresentation {
+    fn representation(&self) -> VisualRepresentation<'_> {
         VisualRepresentation::led(self.result)
     }
 }
@@ -1,8 +1,9 @@
 use std::fmt::{self, Display, Formatter};
 use std::ops::BitOr;
+use std::str::FromStr;
 
 #[repr(u32)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ControlFlag {
     Empty = 0,
     Hlt = 1 << 0,
@@ -24,6 +25,78 @@ pub enum ControlFlag {
     NextInstruction = 1 << 16,
 }
 
+impl ControlFlag {
+    /// Every flag, ordered by bit
+    pub const ALL: [ControlFlag; 17] = [
+        ControlFlag::Hlt,
+        ControlFlag::MemoryAddressIn,
+        ControlFlag::RamIn,
+        ControlFlag::RamOut,
+        ControlFlag::InstructionRegisterOut,
+        ControlFlag::InstructionRegisterIn,
+        ControlFlag::ARegisterIn,
+        ControlFlag::ARegisterOut,
+        ControlFlag::SumOut,
+        ControlFlag::Subtract,
+        ControlFlag::BRegisterIn,
+        ControlFlag::OutputRegisterIn,
+        ControlFlag::CounterEnable,
+        ControlFlag::CounterOut,
+        ControlFlag::Jump,
+        ControlFlag::FlagRegisterIn,
+        ControlFlag::NextInstruction,
+    ];
+
+    /// Position of the flag in the control word
+    pub fn bit(self) -> u8 {
+        (self as u32).trailing_zeros() as u8
+    }
+
+    /// Abbreviation used on the control word LEDs
+    pub fn label(self) -> &'static str {
+        use ControlFlag::*;
+        match self {
+            Empty => "",
+            Hlt => "HLT",
+            MemoryAddressIn => "MI",
+            RamIn => "RI",
+            RamOut => "RO",
+            InstructionRegisterOut => "IO",
+            InstructionRegisterIn => "II",
+            ARegisterIn => "AI",
+            ARegisterOut => "AO",
+            SumOut => "ΣO",
+            Subtract => "SU",
+            BRegisterIn => "BI",
+            OutputRegisterIn => "OI",
+            CounterEnable => "CE",
+            CounterOut => "CO",
+            Jump => "J",
+            FlagRegisterIn => "FI",
+            NextInstruction => "NI",
+        }
+    }
+}
+
+/// Accepts either the full name (`RamIn`) or the abbreviation (`RI`), ignoring
+/// case. `EO` is accepted for `ΣO`.
+impl FromStr for ControlFlag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s.eq_ignore_ascii_case("EO") {
+            return Ok(ControlFlag::SumOut);
+        }
+        ControlFlag::ALL
+            .iter()
+            .copied()
+            .find(|flag| {
+                format!("{:?}", flag).eq_ignore_ascii_case(s) || flag.label().eq_ignore_ascii_case(s)
+            })
+            .ok_or_else(|| format!("unknown control flag {}", s))
+    }
+}
+
 impl BitOr for ControlFlag {
     type Output = ControlWord;
 
@@ -39,45 +112,118 @@ impl ControlWord {
     pub fn has(self, flag: ControlFlag) -> bool {
         self.0 & (flag as u32) > 0
     }
+
+    /// The flags set in this control word
+    pub fn flags(self) -> Vec<ControlFlag> {
+        ControlFlag::ALL
+            .iter()
+            .copied()
+            .filter(|flag| self.has(*flag))
+            .collect()
+    }
+}
+
+impl ControlWord {
+    /// Displays the control word with the given names for its bits
+    pub fn named(self, names: &ControlNames) -> NamedControlWord<'_> {
+        NamedControlWord { cw: self, names }
+    }
 }
 
 impl Display for ControlWord {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        use ControlFlag::*;
+        write!(f, "{}", self.named(&ControlNames::default()))
+    }
+}
 
-        const FLAGS: [ControlFlag; 18] = [
-            Empty,
-            Hlt,
-            MemoryAddressIn,
-            RamIn,
-            RamOut,
-            InstructionRegisterOut,
-            InstructionRegisterIn,
-            ARegisterIn,
-            ARegisterOut,
-            SumOut,
-            Subtract,
-            BRegisterIn,
-            OutputRegisterIn,
-            CounterEnable,
-            CounterOut,
-            Jump,
-            FlagRegisterIn,
-            NextInstruction,
-        ];
-
-        let mut flags = Vec::new();
-        for flag in &FLAGS {
-            if self.has(*flag) {
-                flags.push(*flag);
-            }
-        }
-        if flags.is_empty() {
+pub struct NamedControlWord<'a> {
+    cw: ControlWord,
+    names: &'a ControlNames,
+}
+
+/// Bits without a name are shown as `bit N`
+impl Display for NamedControlWord<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let names = self.names.set_names(self.cw);
+        if names.is_empty() {
             write!(f, "Empty")
         } else {
-            let str_flags: Vec<String> = flags.iter().map(|f| format!("{:?}", f)).collect();
-            write!(f, "{}", &str_flags.join(" | "))
+            write!(f, "{}", names.join(" | "))
+        }
+    }
+}
+
+/// Name and LED label of each of the 32 control bits, so that control lines
+/// added for custom modules can be displayed. Unnamed bits are empty strings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ControlNames {
+    names: Vec<String>,
+    labels: Vec<String>,
+}
+
+/// The built-in flags
+impl Default for ControlNames {
+    fn default() -> Self {
+        let mut names = ControlNames {
+            names: vec![String::new(); 32],
+            labels: vec![String::new(); 32],
+        };
+        for flag in ControlFlag::ALL.iter() {
+            names.set(flag.bit(), &format!("{:?}", flag), flag.label());
         }
+        names
+    }
+}
+
+impl ControlNames {
+    pub fn name(&self, bit: u8) -> &str {
+        &self.names[bit as usize]
+    }
+
+    pub fn label(&self, bit: u8) -> &str {
+        &self.labels[bit as usize]
+    }
+
+    /// The label defaults to the name
+    pub fn set(&mut self, bit: u8, name: &str, label: &str) {
+        let label = if label.is_empty() { name } else { label };
+        self.names[bit as usize] = name.to_string();
+        self.labels[bit as usize] = label.to_string();
+    }
+
+    /// Labels of every bit, least significant first
+    pub fn labels(&self) -> Vec<&str> {
+        self.labels.iter().map(String::as_str).collect()
+    }
+
+    /// Bits that have a name
+    pub fn named_bits(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..32).filter(move |&bit| !self.names[bit as usize].is_empty())
+    }
+
+    /// Mask of the bits that have a name
+    pub fn mask(&self) -> u32 {
+        self.named_bits().fold(0, |mask, bit| mask | 1 << bit)
+    }
+
+    /// Finds a bit by name or label, ignoring case
+    pub fn find(&self, s: &str) -> Option<u8> {
+        if s.eq_ignore_ascii_case("EO") && self.label(ControlFlag::SumOut.bit()) == "ΣO" {
+            return Some(ControlFlag::SumOut.bit());
+        }
+        self.named_bits()
+            .find(|&bit| self.name(bit).eq_ignore_ascii_case(s) || self.label(bit).eq_ignore_ascii_case(s))
+    }
+
+    /// Names of the bits set in a control word, `bit N` for unnamed ones
+    pub fn set_names(&self, cw: ControlWord) -> Vec<String> {
+        (0..32)
+            .filter(|bit| cw.0 & 1 << bit != 0)
+            .map(|bit| match self.name(bit) {
+                "" => format!("bit {}", bit),
+                name => name.to_string(),
+            })
+            .collect()
     }
 }
 
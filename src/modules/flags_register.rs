@@ -35,10 +35,14 @@ impl Module for FlagsRegister {
     }
 
     fn reset(&mut self) {}
+
+    fn value(&self) -> Option<u8> {
+        Some(self.flags.get())
+    }
 }
 
 impl GraphicalModule for FlagsRegister {
-    fn representation(&self) -> VisualRepresentation {
+    fn representation(&self) -> VisualRepresentation<'_> {
         let num_flags = self
             .labels
             .iter()
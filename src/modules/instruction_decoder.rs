@@ -1,20 +1,55 @@
-use super::{ControlFlag, ControlWord, Module};
+use super::microcode_file::MicrocodeFile;
+use super::microcode_layout::{AddressLayout, MicrocodeAddress};
+use super::{ControlFlag, ControlNames, ControlWord, Module};
 use crate::graphics::*;
 use crate::shareable::{Share, Shareable, Shared};
 use std::convert::AsRef;
 use std::fmt::{self, Display, Formatter};
-use std::fs::File;
-use std::io::{self, BufReader, Read};
+use std::io::Read;
 use std::path::Path;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DecoderKind {
+    Simple,
+    Branching,
+    Microcode,
+    Extended,
+}
+
 /// Implementors are expected to own references to the necessary registers
 pub trait InstructionDecoder {
+    fn kind(&self) -> DecoderKind;
     fn decode(&self) -> ControlWord;
     fn step(&mut self);
     fn get_counter(&self) -> usize;
     fn reset_counter(&mut self);
+    /// Used to restore a snapshot of the machine. Counters past the last
+    /// step are clamped to it.
+    fn set_counter(&mut self, counter: usize);
+    /// Contents of the instruction register
+    fn instruction(&self) -> u8;
+    /// Control words the decoder looks up, for microcode-based decoders
+    fn microcode(&self) -> Option<&[u32]> {
+        None
+    }
+    /// How the microcode is addressed, for microcode-based decoders
+    fn microcode_layout(&self) -> Option<AddressLayout> {
+        None
+    }
+    /// Names of the control bits the decoder drives
+    fn control_names(&self) -> ControlNames {
+        ControlNames::default()
+    }
+    /// Number of steps before the counter wraps around
+    fn steps(&self) -> usize {
+        DEFAULT_STEPS
+    }
 }
 
+/// Steps of the original breadboard computer, whose step counter is reset
+/// after T4
+pub const DEFAULT_STEPS: usize = 5;
+
 #[derive(Debug)]
 pub struct SimpleInstructionDecoder {
     counter: Shareable<u8>,
@@ -33,7 +68,12 @@ impl SimpleInstructionDecoder {
 
 #[allow(unused)]
 impl InstructionDecoder for SimpleInstructionDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Simple
+    }
+
     fn decode(&self) -> ControlWord {
+        use crate::isa::*;
         use ControlFlag::*;
 
         let instruction = self.instruction_register.get() >> 4;
@@ -41,41 +81,33 @@ impl InstructionDecoder for SimpleInstructionDecoder {
             (_, 0) => CounterOut | MemoryAddressIn,
             (_, 1) => RamOut | InstructionRegisterIn | CounterEnable,
 
-            // LDA
-            (0x1, 2) => InstructionRegisterOut | MemoryAddressIn,
-            (0x1, 3) => RamOut | ARegisterIn,
+            (LDA, 2) => InstructionRegisterOut | MemoryAddressIn,
+            (LDA, 3) => RamOut | ARegisterIn,
 
-            // ADD
-            (0x2, 2) => InstructionRegisterOut | MemoryAddressIn,
-            (0x2, 3) => RamOut | BRegisterIn,
-            (0x2, 4) => SumOut | ARegisterIn | FlagRegisterIn,
+            (ADD, 2) => InstructionRegisterOut | MemoryAddressIn,
+            (ADD, 3) => RamOut | BRegisterIn,
+            (ADD, 4) => SumOut | ARegisterIn | FlagRegisterIn,
 
-            // SUB
-            (0x3, 2) => InstructionRegisterOut | MemoryAddressIn,
-            (0x3, 3) => RamOut | BRegisterIn,
-            (0x3, 4) => Subtract | SumOut | ARegisterIn | FlagRegisterIn,
+            (SUB, 2) => InstructionRegisterOut | MemoryAddressIn,
+            (SUB, 3) => RamOut | BRegisterIn,
+            (SUB, 4) => Subtract | SumOut | ARegisterIn | FlagRegisterIn,
 
-            // STA
-            (0x4, 2) => InstructionRegisterOut | MemoryAddressIn,
-            (0x4, 3) => ARegisterOut | RamIn,
+            (STA, 2) => InstructionRegisterOut | MemoryAddressIn,
+            (STA, 3) => ARegisterOut | RamIn,
 
-            // LDI
-            (0x5, 2) => InstructionRegisterOut | ARegisterIn,
+            (LDI, 2) => InstructionRegisterOut | ARegisterIn,
 
-            //JMP
-            (0x6, 2) => InstructionRegisterOut | Jump,
+            (JMP, 2) => InstructionRegisterOut | Jump,
 
-            // OUT
-            (0xe, 2) => ARegisterOut | OutputRegisterIn,
+            (OUT, 2) => ARegisterOut | OutputRegisterIn,
 
-            // Hlt
-            (0xf, 2) => ControlWord(Hlt as u32),
+            (HLT, 2) => ControlWord(Hlt as u32),
             _ => ControlWord(0),
         }
     }
 
     fn step(&mut self) {
-        self.counter.set((self.counter.get() + 1) % 5);
+        self.counter.set((self.counter.get() + 1) % DEFAULT_STEPS as u8);
     }
 
     fn get_counter(&self) -> usize {
@@ -85,6 +117,14 @@ impl InstructionDecoder for SimpleInstructionDecoder {
     fn reset_counter(&mut self) {
         self.counter.set(0);
     }
+
+    fn set_counter(&mut self, counter: usize) {
+        self.counter.set(counter.min(self.steps() - 1) as u8);
+    }
+
+    fn instruction(&self) -> u8 {
+        self.instruction_register.get()
+    }
 }
 
 impl Share<u8> for SimpleInstructionDecoder {
@@ -120,7 +160,12 @@ impl Share<u8> for BranchingInstructionDecoder {
 }
 
 impl InstructionDecoder for BranchingInstructionDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Branching
+    }
+
     fn decode(&self) -> ControlWord {
+        use crate::isa::*;
         use ControlFlag::*;
 
         let instruction = self.instruction_register.get() >> 4;
@@ -131,49 +176,39 @@ impl InstructionDecoder for BranchingInstructionDecoder {
             (_, 0) => CounterOut | MemoryAddressIn,
             (_, 1) => RamOut | InstructionRegisterIn | CounterEnable,
 
-            // LDA
-            (0x1, 2) => InstructionRegisterOut | MemoryAddressIn,
-            (0x1, 3) => RamOut | ARegisterIn | NextInstruction,
+            (LDA, 2) => InstructionRegisterOut | MemoryAddressIn,
+            (LDA, 3) => RamOut | ARegisterIn | NextInstruction,
 
-            // ADD
-            (0x2, 2) => InstructionRegisterOut | MemoryAddressIn,
-            (0x2, 3) => RamOut | BRegisterIn,
-            (0x2, 4) => SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
+            (ADD, 2) => InstructionRegisterOut | MemoryAddressIn,
+            (ADD, 3) => RamOut | BRegisterIn,
+            (ADD, 4) => SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
 
-            // SUB
-            (0x3, 2) => InstructionRegisterOut | MemoryAddressIn,
-            (0x3, 3) => RamOut | BRegisterIn,
-            (0x3, 4) => Subtract | SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
+            (SUB, 2) => InstructionRegisterOut | MemoryAddressIn,
+            (SUB, 3) => RamOut | BRegisterIn,
+            (SUB, 4) => Subtract | SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
 
-            // STA
-            (0x4, 2) => InstructionRegisterOut | MemoryAddressIn,
-            (0x4, 3) => ARegisterOut | RamIn | NextInstruction,
+            (STA, 2) => InstructionRegisterOut | MemoryAddressIn,
+            (STA, 3) => ARegisterOut | RamIn | NextInstruction,
 
-            // LDI
-            (0x5, 2) => InstructionRegisterOut | ARegisterIn | NextInstruction,
+            (LDI, 2) => InstructionRegisterOut | ARegisterIn | NextInstruction,
 
-            //JMP
-            (0x6, 2) => InstructionRegisterOut | Jump | NextInstruction,
+            (JMP, 2) => InstructionRegisterOut | Jump | NextInstruction,
 
-            // JC
-            (0x7, 2) if carry => InstructionRegisterOut | Jump | NextInstruction,
-            (0x7, 2) => Empty | NextInstruction,
+            (JC, 2) if carry => InstructionRegisterOut | Jump | NextInstruction,
+            (JC, 2) => Empty | NextInstruction,
 
-            // JZ
-            (0x8, 2) if zero => InstructionRegisterOut | Jump | NextInstruction,
-            (0x8, 2) => Empty | NextInstruction,
+            (JZ, 2) if zero => InstructionRegisterOut | Jump | NextInstruction,
+            (JZ, 2) => Empty | NextInstruction,
 
-            // OUT
-            (0xe, 2) => ARegisterOut | OutputRegisterIn | NextInstruction,
+            (OUT, 2) => ARegisterOut | OutputRegisterIn | NextInstruction,
 
-            // Hlt
-            (0xf, 2) => ControlWord(Hlt as u32),
+            (HLT, 2) => ControlWord(Hlt as u32),
             _ => ControlWord(0),
         }
     }
 
     fn step(&mut self) {
-        self.counter.set((self.counter.get() + 1) % 5);
+        self.counter.set((self.counter.get() + 1) % DEFAULT_STEPS as u8);
     }
 
     fn get_counter(&self) -> usize {
@@ -183,43 +218,221 @@ impl InstructionDecoder for BranchingInstructionDecoder {
     fn reset_counter(&mut self) {
         self.counter.set(0);
     }
+
+    fn set_counter(&mut self, counter: usize) {
+        self.counter.set(counter.min(self.steps() - 1) as u8);
+    }
+
+    fn instruction(&self) -> u8 {
+        self.instruction_register.get()
+    }
+}
+
+/// Steps of the extended machine, whose ADD and SUB take two more cycles to
+/// fetch their operand
+pub const EXTENDED_STEPS: usize = 6;
+
+/// Decoder of the extended machine, whose instructions are followed by their
+/// operand in memory
+pub struct ExtendedInstructionDecoder {
+    counter: Shareable<u8>,
+    instruction_register: Shared<u8>,
+    flags: Shared<u8>,
+}
+
+impl ExtendedInstructionDecoder {
+    pub fn new(instruction_register: Shared<u8>, flags: Shared<u8>) -> Self {
+        ExtendedInstructionDecoder {
+            counter: Shareable::new(0),
+            instruction_register,
+            flags,
+        }
+    }
+}
+
+impl Share<u8> for ExtendedInstructionDecoder {
+    fn share(&self) -> Shared<u8> {
+        self.counter.share()
+    }
+}
+
+impl InstructionDecoder for ExtendedInstructionDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Extended
+    }
+
+    fn decode(&self) -> ControlWord {
+        use crate::isa::*;
+        use ControlFlag::*;
+
+        let instruction = self.instruction_register.get() >> 4;
+        let flags = self.flags.get();
+        let carry = flags & 0b10 > 0;
+        let zero = flags & 0b01 > 0;
+        let takes_operand = by_opcode(instruction).is_some_and(|i| i.operand != Operand::None);
+        match (instruction, self.counter.get()) {
+            (_, 0) => CounterOut | MemoryAddressIn,
+            (_, 1) => RamOut | InstructionRegisterIn | CounterEnable,
+
+            // Conditional jumps that are not taken skip their operand
+            (JC, 2) if !carry => CounterEnable | NextInstruction,
+            (JZ, 2) if !zero => CounterEnable | NextInstruction,
+            // Point the memory address at the operand
+            (_, 2) if takes_operand => CounterOut | MemoryAddressIn,
+
+            (LDA, 3) => RamOut | MemoryAddressIn | CounterEnable,
+            (LDA, 4) => RamOut | ARegisterIn | NextInstruction,
+
+            (ADD, 3) => RamOut | MemoryAddressIn | CounterEnable,
+            (ADD, 4) => RamOut | BRegisterIn,
+            (ADD, 5) => SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
+
+            (SUB, 3) => RamOut | MemoryAddressIn | CounterEnable,
+            (SUB, 4) => RamOut | BRegisterIn,
+            (SUB, 5) => Subtract | SumOut | ARegisterIn | NextInstruction | FlagRegisterIn,
+
+            (STA, 3) => RamOut | MemoryAddressIn | CounterEnable,
+            (STA, 4) => ARegisterOut | RamIn | NextInstruction,
+
+            (LDI, 3) => RamOut | ARegisterIn | CounterEnable | NextInstruction,
+
+            (JMP, 3) | (JC, 3) | (JZ, 3) => RamOut | Jump | NextInstruction,
+
+            (OUT, 2) => ARegisterOut | OutputRegisterIn | NextInstruction,
+
+            (HLT, 2) => ControlWord(Hlt as u32),
+            _ => ControlWord(0),
+        }
+    }
+
+    fn step(&mut self) {
+        self.counter.set((self.counter.get() + 1) % EXTENDED_STEPS as u8);
+    }
+
+    fn get_counter(&self) -> usize {
+        self.counter.get() as usize
+    }
+
+    fn reset_counter(&mut self) {
+        self.counter.set(0);
+    }
+
+    fn set_counter(&mut self, counter: usize) {
+        self.counter.set(counter.min(self.steps() - 1) as u8);
+    }
+
+    fn instruction(&self) -> u8 {
+        self.instruction_register.get()
+    }
+
+    fn steps(&self) -> usize {
+        EXTENDED_STEPS
+    }
 }
 
+/// Number of control words in a microcode image with the default layout
+pub const MICROCODE_SIZE: usize = 1 << 12;
+
 pub struct MicrocodeDecoder {
     counter: Shareable<u8>,
     instruction_register: Shared<u8>,
     flags: Shared<u8>,
-    microcode: Box<[u32; 1 << 12]>,
+    layout: AddressLayout,
+    microcode: Vec<u32>,
+    names: ControlNames,
+    steps: u8,
 }
 
 impl MicrocodeDecoder {
     pub fn new<R: Read>(
         instruction_register: Shared<u8>,
         flags: Shared<u8>,
-        mut reader: R,
-    ) -> io::Result<Self> {
-        let mut decoder = MicrocodeDecoder {
+        reader: R,
+    ) -> Result<Self, String> {
+        let file = MicrocodeFile::read(reader)?;
+        Self::with_file(instruction_register, flags, file)
+    }
+
+    /// Microcode with the default layout
+    pub fn with_microcode(
+        instruction_register: Shared<u8>,
+        flags: Shared<u8>,
+        microcode: Box<[u32; MICROCODE_SIZE]>,
+    ) -> Self {
+        Self::with_layout(instruction_register, flags, AddressLayout::default(), microcode.to_vec())
+            .expect("the default layout addresses MICROCODE_SIZE words")
+    }
+
+    /// The microcode must hold `layout.without_byte_select().size()` control
+    /// words: byte select lines only choose which byte an EEPROM outputs, so
+    /// the decoder leaves them out of its layout
+    pub fn with_layout(
+        instruction_register: Shared<u8>,
+        flags: Shared<u8>,
+        layout: AddressLayout,
+        microcode: Vec<u32>,
+    ) -> Result<Self, String> {
+        let layout = layout.without_byte_select();
+        if microcode.len() != layout.size() {
+            return Err(format!(
+                "{} control words given, the layout {} addresses {}",
+                microcode.len(),
+                layout,
+                layout.size()
+            ));
+        }
+        Ok(MicrocodeDecoder {
             counter: Shareable::new(0),
             instruction_register,
             flags,
-            microcode: Box::new([0; 1 << 12]),
-        };
-        for address in 0..(1 << 12) {
-            let mut buf = [0u8; 4];
-            reader.read_exact(&mut buf)?;
-            decoder.microcode[address] = u32::from_le_bytes(buf);
+            layout,
+            microcode,
+            names: ControlNames::default(),
+            steps: DEFAULT_STEPS as u8,
+        })
+    }
+
+    /// Files with byte select lines repeat each word for every byte, only one
+    /// copy is kept
+    pub fn with_file(
+        instruction_register: Shared<u8>,
+        flags: Shared<u8>,
+        file: MicrocodeFile,
+    ) -> Result<Self, String> {
+        let layout = file.layout.without_byte_select();
+        let microcode = file.layout.convert(&file.microcode, &layout);
+        Self::with_layout(instruction_register, flags, layout, microcode)?
+            .with_control_names(file.names)
+            .with_steps(file.steps as usize)
+    }
+
+    /// The step field of the layout must be wide enough to count the steps
+    pub fn with_steps(mut self, steps: usize) -> Result<Self, String> {
+        if steps < 1 || steps > self.layout.max_steps() {
+            return Err(format!(
+                "{} steps per instruction, the layout {} counts 1 to {}",
+                steps,
+                self.layout,
+                self.layout.max_steps()
+            ));
         }
-        Ok(decoder)
+        self.steps = steps as u8;
+        Ok(self)
+    }
+
+    /// Names for the control bits of custom modules
+    pub fn with_control_names(mut self, names: ControlNames) -> Self {
+        self.names = names;
+        self
     }
 
     pub fn from_file<P: AsRef<Path>>(
         instruction_register: Shared<u8>,
         flags: Shared<u8>,
         path: P,
-    ) -> io::Result<Self> {
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
-        Self::new(instruction_register, flags, &mut reader)
+    ) -> Result<Self, String> {
+        let file = MicrocodeFile::load(path)?;
+        Self::with_file(instruction_register, flags, file)
     }
 }
 
@@ -230,16 +443,21 @@ impl Share<u8> for MicrocodeDecoder {
 }
 
 impl InstructionDecoder for MicrocodeDecoder {
+    fn kind(&self) -> DecoderKind {
+        DecoderKind::Microcode
+    }
+
     fn decode(&self) -> ControlWord {
-        let instruction = self.instruction_register.get() >> 4;
-        let address = ((self.flags.get() as u16 & 0b1111) << 8)
-            | ((instruction as u16 & 0b1111) << 4)
-            | (self.counter.get() as u16 & 0b1111);
-        ControlWord(self.microcode[address as usize])
+        let address = self.layout.pack(MicrocodeAddress {
+            flags: self.flags.get(),
+            instruction: self.instruction_register.get() >> 4,
+            step: self.counter.get(),
+        });
+        ControlWord(self.microcode[address])
     }
 
     fn step(&mut self) {
-        self.counter.set((self.counter.get() + 1) % 5);
+        self.counter.set((self.counter.get() + 1) % self.steps);
     }
 
     fn get_counter(&self) -> usize {
@@ -249,10 +467,129 @@ impl InstructionDecoder for MicrocodeDecoder {
     fn reset_counter(&mut self) {
         self.counter.set(0);
     }
+
+    fn set_counter(&mut self, counter: usize) {
+        self.counter.set(counter.min(self.steps() - 1) as u8);
+    }
+
+    fn instruction(&self) -> u8 {
+        self.instruction_register.get()
+    }
+
+    fn microcode(&self) -> Option<&[u32]> {
+        Some(&self.microcode)
+    }
+
+    fn microcode_layout(&self) -> Option<AddressLayout> {
+        Some(self.layout)
+    }
+
+    fn control_names(&self) -> ControlNames {
+        self.names.clone()
+    }
+
+    fn steps(&self) -> usize {
+        self.steps as usize
+    }
+}
+
+/// One of the decoders, for when it is chosen at run time
+pub enum AnyDecoder {
+    Simple(SimpleInstructionDecoder),
+    Branching(BranchingInstructionDecoder),
+    Microcode(MicrocodeDecoder),
+    Extended(ExtendedInstructionDecoder),
+}
+
+impl AnyDecoder {
+    fn inner(&self) -> &dyn InstructionDecoder {
+        match self {
+            AnyDecoder::Simple(decoder) => decoder,
+            AnyDecoder::Branching(decoder) => decoder,
+            AnyDecoder::Microcode(decoder) => decoder,
+            AnyDecoder::Extended(decoder) => decoder,
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut dyn InstructionDecoder {
+        match self {
+            AnyDecoder::Simple(decoder) => decoder,
+            AnyDecoder::Branching(decoder) => decoder,
+            AnyDecoder::Microcode(decoder) => decoder,
+            AnyDecoder::Extended(decoder) => decoder,
+        }
+    }
+}
+
+impl InstructionDecoder for AnyDecoder {
+    fn kind(&self) -> DecoderKind {
+        self.inner().kind()
+    }
+
+    fn decode(&self) -> ControlWord {
+        self.inner().decode()
+    }
+
+    fn step(&mut self) {
+        self.inner_mut().step()
+    }
+
+    fn get_counter(&self) -> usize {
+        self.inner().get_counter()
+    }
+
+    fn reset_counter(&mut self) {
+        self.inner_mut().reset_counter()
+    }
+
+    fn set_counter(&mut self, counter: usize) {
+        self.inner_mut().set_counter(counter)
+    }
+
+    fn instruction(&self) -> u8 {
+        self.inner().instruction()
+    }
+
+    fn microcode(&self) -> Option<&[u32]> {
+        self.inner().microcode()
+    }
+
+    fn microcode_layout(&self) -> Option<AddressLayout> {
+        self.inner().microcode_layout()
+    }
+
+    fn control_names(&self) -> ControlNames {
+        self.inner().control_names()
+    }
+
+    fn steps(&self) -> usize {
+        self.inner().steps()
+    }
+}
+
+impl Share<u8> for AnyDecoder {
+    fn share(&self) -> Shared<u8> {
+        match self {
+            AnyDecoder::Simple(decoder) => decoder.share(),
+            AnyDecoder::Branching(decoder) => decoder.share(),
+            AnyDecoder::Microcode(decoder) => decoder.share(),
+            AnyDecoder::Extended(decoder) => decoder.share(),
+        }
+    }
 }
 
+/// Step counter of the decoder, with one LED per step
 #[derive(Debug)]
-pub struct DecoderStep(pub Shared<u8>);
+pub struct DecoderStep {
+    step: Shared<u8>,
+    steps: usize,
+}
+
+impl DecoderStep {
+    pub fn new(step: Shared<u8>, steps: usize) -> Self {
+        DecoderStep { step, steps }
+    }
+}
 
 impl Module for DecoderStep {
     fn get_name(&self) -> &'static str {
@@ -260,16 +597,23 @@ impl Module for DecoderStep {
     }
 
     fn reset(&mut self) {}
+
+    fn value(&self) -> Option<u8> {
+        Some(self.step.get())
+    }
 }
 
 impl Display for DecoderStep {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{:03b}", self.0.get())
+        let width = (usize::BITS - (self.steps - 1).leading_zeros()).max(1) as usize;
+        write!(f, "{:0width$b}", self.step.get(), width = width)
     }
 }
 
 impl GraphicalModule for DecoderStep {
-    fn representation(&self) -> VisualRepresentation {
-        VisualRepresentation::LedN(self.0.get() as usize, 3, LedColor::default())
+    fn representation(&self) -> VisualRepresentation<'_> {
+        // The first step is the leftmost LED
+        let lit = 1 << (self.steps - 1 - self.step.get() as usize);
+        VisualRepresentation::LedN(lit, self.steps as u8, LedColor::default())
     }
 }
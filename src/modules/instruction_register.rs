@@ -29,6 +29,18 @@ impl Module for InstructionRegister {
         self.value.set(0);
     }
 
+    fn value(&self) -> Option<u8> {
+        Some(self.value.get())
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        vec![self.value.get()]
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        self.value.set(snapshot[0]);
+    }
+
     fn bus_write_flag(&self) -> ControlFlag {
         ControlFlag::InstructionRegisterOut
     }
@@ -47,7 +59,7 @@ impl Module for InstructionRegister {
 }
 
 impl GraphicalModule for InstructionRegister {
-    fn representation(&self) -> VisualRepresentation {
+    fn representation(&self) -> VisualRepresentation<'_> {
         VisualRepresentation::LedSplit(
             self.value.get(),
             LedColor::new(0.3, 0.3, 1.0),
@@ -0,0 +1,28 @@
+// Address decoding in front of RAM
+use std::ops::Range;
+
+/// Address ranges that RAM leaves to read-only chips. RAM neither drives the
+/// bus nor latches it when one of them is selected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    read_only: Vec<Range<usize>>,
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_only(mut self, range: Range<usize>) -> Self {
+        self.read_only.push(range);
+        self
+    }
+
+    pub fn is_read_only(&self, address: usize) -> bool {
+        self.read_only.iter().any(|range| range.contains(&address))
+    }
+
+    pub fn regions(&self) -> &[Range<usize>] {
+        &self.read_only
+    }
+}
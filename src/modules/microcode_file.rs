@@ -0,0 +1,268 @@
+// Reading and writing microcode images, either in a self-describing container
+// or as the raw dumps of earlier versions
+use super::{AddressLayout, ControlFlag, ControlNames, DEFAULT_STEPS, MICROCODE_SIZE};
+use crate::save_state::Reader;
+use std::convert::{AsRef, TryFrom};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"BB8MCODE";
+pub const VERSION: u8 = 1;
+
+// File layout, all integers little-endian:
+//   magic, version: u8, layout length: u8 and layout (e.g.
+//   `flags:4,instruction:4,step:4`), steps before the counter wraps: u8,
+//   flag count: u8, then for each named bit:
+//     control bit: u8, name length: u8, name, label length: u8, label
+//   word count: u32, words: u32, CRC-32 of everything before it: u32
+//
+// Raw files are the control words of the default layout, without anything
+// around them.
+
+/// Bits of the control word that are wired to something
+const USED_BITS: u32 = ((ControlFlag::NextInstruction as u32) << 1) - 1;
+
+/// A microcode image and how it is addressed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MicrocodeFile {
+    pub layout: AddressLayout,
+    /// `layout.size()` control words
+    pub microcode: Vec<u32>,
+    /// Names of the control bits, which must agree with the built-in flags
+    pub names: ControlNames,
+    /// Steps before the decoder counter wraps around
+    pub steps: u8,
+}
+
+impl MicrocodeFile {
+    /// Fails if a name or the layout is too long for its length field
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        let layout = self.layout.to_string();
+        bytes.push(u8::try_from(layout.len()).map_err(|_| too_long("address layout", &layout))?);
+        bytes.extend_from_slice(layout.as_bytes());
+        bytes.push(self.steps);
+        // At most 32 bits are named
+        bytes.push(self.names.named_bits().count() as u8);
+        for bit in self.names.named_bits() {
+            bytes.push(bit);
+            for s in [self.names.name(bit), self.names.label(bit)].iter() {
+                bytes.push(
+                    u8::try_from(s.len())
+                        .map_err(|_| too_long(&format!("name of bit {}", bit), s))?,
+                );
+                bytes.extend_from_slice(s.as_bytes());
+            }
+        }
+        let count = u32::try_from(self.microcode.len()).map_err(|_| {
+            format!(
+                "{} control words do not fit in a microcode file",
+                self.microcode.len()
+            )
+        })?;
+        bytes.extend_from_slice(&count.to_le_bytes());
+        for word in self.microcode.iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        let checksum = crc32(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Control words with the default layout and step count, and no header
+    pub fn to_raw_bytes(&self) -> Result<Vec<u8>, String> {
+        if self.layout != AddressLayout::default() {
+            return Err(format!(
+                "raw microcode images use the default layout, not {}",
+                self.layout
+            ));
+        }
+        if self.steps as usize != DEFAULT_STEPS {
+            return Err(format!(
+                "raw microcode images wrap after {} steps, not {}",
+                DEFAULT_STEPS, self.steps
+            ));
+        }
+        Ok(self
+            .microcode
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect())
+    }
+
+    /// Reads a container, or a raw image if the bytes do not start like one
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.starts_with(MAGIC) {
+            Self::from_container(bytes)
+        } else {
+            Self::from_raw(bytes)
+        }
+    }
+
+    fn from_container(bytes: &[u8]) -> Result<Self, String> {
+        let truncated = |e: String| format!("truncated microcode file: {}", e);
+        let mut reader = Reader(&bytes[MAGIC.len()..]);
+        let version = reader.u8().map_err(truncated)?;
+        if version != VERSION {
+            return Err(format!(
+                "unsupported microcode file version {} (this emulator reads version {})",
+                version, VERSION
+            ));
+        }
+        let len = reader.u8().map_err(truncated)? as usize;
+        let layout: AddressLayout = std::str::from_utf8(reader.take(len).map_err(truncated)?)
+            .map_err(|_| "invalid address layout".to_string())?
+            .parse()
+            .map_err(|e| format!("invalid address layout: {}", e))?;
+        let steps = reader.u8().map_err(truncated)?;
+        if steps == 0 || steps as usize > layout.max_steps() {
+            return Err(format!(
+                "{} steps, but the {} step lines of layout {} can only count {}",
+                steps,
+                layout.step.width,
+                layout,
+                layout.max_steps()
+            ));
+        }
+        let mut names = ControlNames::default();
+        for _ in 0..reader.u8().map_err(truncated)? {
+            let bit = reader.u8().map_err(truncated)?;
+            let len = reader.u8().map_err(truncated)? as usize;
+            let name = String::from_utf8_lossy(reader.take(len).map_err(truncated)?).into_owned();
+            let len = reader.u8().map_err(truncated)? as usize;
+            let label = String::from_utf8_lossy(reader.take(len).map_err(truncated)?).into_owned();
+            check_flag(bit, &name)?;
+            if ControlFlag::ALL.iter().all(|flag| flag.bit() != bit) {
+                names.set(bit, &name, &label);
+            }
+        }
+        let count = reader.u32().map_err(truncated)? as usize;
+        if count != layout.size() {
+            return Err(format!(
+                "{} control words for layout {}, expected {}",
+                count,
+                layout,
+                layout.size()
+            ));
+        }
+        let microcode = (0..count)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(truncated)?;
+        let end = bytes.len() - reader.0.len();
+        let stored = reader.u32().map_err(truncated)?;
+        let computed = crc32(&bytes[..end]);
+        if stored != computed {
+            return Err(format!(
+                "checksum mismatch (stored {:#010x}, computed {:#010x}), the file is corrupted",
+                stored, computed
+            ));
+        }
+        if !reader.0.is_empty() {
+            return Err(format!("{} unexpected bytes at the end", reader.0.len()));
+        }
+        Ok(MicrocodeFile {
+            layout,
+            microcode,
+            names,
+            steps,
+        })
+    }
+
+    fn from_raw(bytes: &[u8]) -> Result<Self, String> {
+        let expected = MICROCODE_SIZE * 4;
+        if bytes.len() != expected {
+            let hint = if bytes.len() < expected {
+                ", it may be truncated"
+            } else {
+                ""
+            };
+            return Err(format!(
+                "not a microcode file: raw images are {} control words of 4 bytes ({} bytes), \
+                 this one is {} bytes long{}",
+                MICROCODE_SIZE,
+                expected,
+                bytes.len(),
+                hint
+            ));
+        }
+        let microcode: Vec<u32> = bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect();
+        let unused = |word: &u32| word & !USED_BITS != 0;
+        if microcode.iter().any(unused)
+            && !microcode.iter().map(|w| w.swap_bytes()).any(|w| unused(&w))
+        {
+            return Err(concat!(
+                "control words set bits that are not wired to anything, unless their bytes are ",
+                "swapped: the image looks big-endian, control words must be little-endian"
+            )
+            .to_string());
+        }
+        Ok(MicrocodeFile {
+            layout: AddressLayout::default(),
+            microcode,
+            names: ControlNames::default(),
+            steps: DEFAULT_STEPS as u8,
+        })
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let bytes = self
+            .to_bytes()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        writer.write_all(&bytes)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_bytes(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Checks that a built-in control bit has the same meaning in the file and
+/// here. Other bits drive custom modules and can have any name.
+fn check_flag(bit: u8, name: &str) -> Result<(), String> {
+    if bit >= 32 {
+        return Err(format!("invalid control bit {} ({})", bit, name));
+    }
+    match ControlFlag::ALL.iter().find(|flag| flag.bit() == bit) {
+        Some(flag) if format!("{:?}", flag) != name => Err(format!(
+            "control bit {} is {} in the file but {:?} in this emulator",
+            bit, name, flag
+        )),
+        _ if name.is_empty() => Err(format!("control bit {} has an empty name", bit)),
+        _ => Ok(()),
+    }
+}
+
+fn too_long(what: &str, s: &str) -> String {
+    format!(
+        "the {} ({} bytes) does not fit in a microcode file",
+        what,
+        s.len()
+    )
+}
+
+/// CRC-32 as used by zip and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
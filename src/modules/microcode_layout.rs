@@ -0,0 +1,217 @@
+// How the flags, instruction and step are wired to the address lines of the
+// microcode ROM
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+/// Contiguous address lines holding one part of the address
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BitField {
+    pub shift: u8,
+    pub width: u8,
+}
+
+impl BitField {
+    fn mask(self) -> usize {
+        (1 << self.width) - 1
+    }
+
+    fn pack(self, value: u8) -> usize {
+        (value as usize & self.mask()) << self.shift
+    }
+
+    fn unpack(self, address: usize) -> u8 {
+        ((address >> self.shift) & self.mask()) as u8
+    }
+}
+
+/// The parts of a microcode address
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MicrocodeAddress {
+    pub flags: u8,
+    pub instruction: u8,
+    pub step: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AddressLayout {
+    pub flags: BitField,
+    pub instruction: BitField,
+    pub step: BitField,
+    /// Lines selecting which byte of the control word an EEPROM outputs, on
+    /// builds that share one image between the chips
+    pub byte_select: Option<BitField>,
+}
+
+/// `flags << 8 | instruction << 4 | step`, 4 bits each
+impl Default for AddressLayout {
+    fn default() -> Self {
+        AddressLayout {
+            flags: BitField { shift: 8, width: 4 },
+            instruction: BitField { shift: 4, width: 4 },
+            step: BitField { shift: 0, width: 4 },
+            byte_select: None,
+        }
+    }
+}
+
+/// Parses the fields and their width, most significant first, e.g.
+/// `flags:2,select:1,instruction:4,step:3`
+impl FromStr for AddressLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut fields: Vec<(&str, BitField)> = Vec::new();
+        let mut shift = 0;
+        for part in s.split(',').rev() {
+            let (name, width) = match part.find(':') {
+                Some(i) => (part[..i].trim(), part[i + 1..].trim()),
+                None => {
+                    return Err(format!(
+                        "invalid layout field {}, expected NAME:WIDTH",
+                        part
+                    ))
+                }
+            };
+            let name = match name {
+                "flags" | "f" => "flags",
+                "instruction" | "i" => "instruction",
+                "step" | "s" => "step",
+                "select" => "select",
+                _ => return Err(format!("unknown layout field {}", name)),
+            };
+            let max_width = if name == "select" { 2 } else { 4 };
+            let width = match width.parse() {
+                Ok(width) if (1..=max_width).contains(&width) => width,
+                _ => {
+                    return Err(format!(
+                        "invalid width {} for {}, expected 1 to {}",
+                        width, name, max_width
+                    ))
+                }
+            };
+            if fields.iter().any(|(n, _)| *n == name) {
+                return Err(format!("layout field {} is given twice", name));
+            }
+            fields.push((name, BitField { shift, width }));
+            shift += width;
+        }
+        let field = |name: &str| fields.iter().find(|(n, _)| *n == name).map(|(_, f)| *f);
+        let missing = |name: &str| format!("layout has no {} field", name);
+        Ok(AddressLayout {
+            flags: field("flags").ok_or_else(|| missing("flags"))?,
+            instruction: field("instruction").ok_or_else(|| missing("instruction"))?,
+            step: field("step").ok_or_else(|| missing("step"))?,
+            byte_select: field("select"),
+        })
+    }
+}
+
+impl Display for AddressLayout {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut fields = vec![
+            ("flags", self.flags),
+            ("instruction", self.instruction),
+            ("step", self.step),
+        ];
+        if let Some(select) = self.byte_select {
+            fields.push(("select", select));
+        }
+        fields.sort_by_key(|(_, field)| std::cmp::Reverse(field.shift));
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(name, field)| format!("{}:{}", name, field.width))
+            .collect();
+        write!(f, "{}", fields.join(","))
+    }
+}
+
+impl AddressLayout {
+    /// Number of address lines
+    pub fn bits(&self) -> u8 {
+        let fields = [
+            Some(self.flags),
+            Some(self.instruction),
+            Some(self.step),
+            self.byte_select,
+        ];
+        fields
+            .iter()
+            .flatten()
+            .map(|field| field.shift + field.width)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Number of control words in an image with this layout, the byte select
+    /// lines included
+    pub fn size(&self) -> usize {
+        1 << self.bits()
+    }
+
+    /// The layout with its byte select lines taken out and the fields above
+    /// them moved down. Decoders hold whole control words, so this is how
+    /// they address them.
+    pub fn without_byte_select(&self) -> AddressLayout {
+        let select = match self.byte_select {
+            Some(select) => select,
+            None => return *self,
+        };
+        let remove = |field: BitField| {
+            if field.shift > select.shift {
+                BitField {
+                    shift: field.shift - select.width,
+                    ..field
+                }
+            } else {
+                field
+            }
+        };
+        AddressLayout {
+            flags: remove(self.flags),
+            instruction: remove(self.instruction),
+            step: remove(self.step),
+            byte_select: None,
+        }
+    }
+
+    /// Most steps the step lines can count
+    pub fn max_steps(&self) -> usize {
+        1 << self.step.width
+    }
+
+    /// Values that do not fit in their field are truncated
+    pub fn pack(&self, address: MicrocodeAddress) -> usize {
+        self.flags.pack(address.flags)
+            | self.instruction.pack(address.instruction)
+            | self.step.pack(address.step)
+    }
+
+    pub fn unpack(&self, address: usize) -> MicrocodeAddress {
+        MicrocodeAddress {
+            flags: self.flags.unpack(address),
+            instruction: self.instruction.unpack(address),
+            step: self.step.unpack(address),
+        }
+    }
+
+    /// Address of the given byte of the control word, on builds where the
+    /// chips share one image
+    pub fn select_byte(&self, address: usize, byte: usize) -> usize {
+        match self.byte_select {
+            Some(select) => address | select.pack(byte as u8),
+            None => address | byte << self.bits(),
+        }
+    }
+
+    /// Rearranges microcode written for this layout into another one
+    pub fn convert(&self, microcode: &[u32], to: &AddressLayout) -> Vec<u32> {
+        (0..to.size())
+            .map(|address| {
+                microcode
+                    .get(self.pack(to.unpack(address)))
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .collect()
+    }
+}
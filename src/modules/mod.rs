@@ -3,20 +3,28 @@ pub mod control;
 pub mod flags_register;
 pub mod instruction_decoder;
 pub mod instruction_register;
+pub mod memory_map;
+pub mod microcode_file;
+pub mod microcode_layout;
 pub mod output_register;
 pub mod program_counter;
 pub mod ram;
 pub mod register;
+pub mod rom;
 
-pub use alu::Alu;
-pub use control::{ControlFlag, ControlWord};
+pub use alu::{Alu, FLAG_LABELS};
+pub use control::{ControlFlag, ControlNames, ControlWord};
 pub use flags_register::FlagsRegister;
 pub use instruction_decoder::*;
 pub use instruction_register::InstructionRegister;
+pub use memory_map::MemoryMap;
+pub use microcode_file::MicrocodeFile;
+pub use microcode_layout::{AddressLayout, BitField, MicrocodeAddress};
 pub use output_register::OutputRegister;
 pub use program_counter::ProgramCounter;
-pub use ram::Ram;
+pub use ram::{Ram, EXTENDED_RAM_SIZE, RAM_SIZE};
 pub use register::Register;
+pub use rom::Rom;
 
 pub trait Module: std::fmt::Debug + std::fmt::Display {
     fn get_name(&self) -> &str;
@@ -25,6 +33,41 @@ pub trait Module: std::fmt::Debug + std::fmt::Display {
     fn step(&mut self, _cw: ControlWord, _bus: u8) {}
     fn reset(&mut self);
 
+    /// Value currently held by the module, if it holds one
+    fn value(&self) -> Option<u8> {
+        None
+    }
+    /// Contents of memory-like modules
+    fn memory(&self) -> Option<&[u8]> {
+        None
+    }
+    /// Address currently selected in memory-like modules
+    fn address(&self) -> Option<usize> {
+        None
+    }
+    /// Address of the first byte of `memory`
+    fn memory_start(&self) -> usize {
+        0
+    }
+    /// Byte a memory-like module answers with when `address` is selected,
+    /// if it answers to it
+    fn read_address(&self, _address: usize) -> Option<u8> {
+        None
+    }
+
+    /// Internal state of the module, restored with `restore`. Modules whose
+    /// state is owned by another module can leave it empty.
+    fn snapshot(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn restore(&mut self, _snapshot: &[u8]) {}
+
+    /// Address and value of a write to read-only memory that was ignored on
+    /// the last rising edge
+    fn rejected_write(&self) -> Option<(usize, u8)> {
+        None
+    }
+
     fn bus_read_flag(&self) -> ControlFlag {
         ControlFlag::Empty
     }
@@ -14,6 +14,18 @@ impl Module for OutputRegister {
         self.0 = 0;
     }
 
+    fn value(&self) -> Option<u8> {
+        Some(self.0)
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        vec![self.0]
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        self.0 = snapshot[0];
+    }
+
     fn bus_read_flag(&self) -> ControlFlag {
         ControlFlag::OutputRegisterIn
     }
@@ -24,7 +36,7 @@ impl Module for OutputRegister {
 }
 
 impl GraphicalModule for OutputRegister {
-    fn representation(&self) -> VisualRepresentation {
+    fn representation(&self) -> VisualRepresentation<'_> {
         VisualRepresentation::Text(format!("{}", self.0))
     }
 }
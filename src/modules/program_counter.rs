@@ -4,7 +4,30 @@ use std::fmt::{self, Display, Formatter};
 use std::num::Wrapping;
 
 #[derive(Debug)]
-pub struct ProgramCounter(pub u8);
+pub struct ProgramCounter {
+    value: u8,
+    /// Number of bits of the counter
+    bits: u8,
+}
+
+impl ProgramCounter {
+    /// Counts up to `2^bits - 1` then wraps around to 0
+    pub fn new(bits: u8) -> Self {
+        assert!((1..=8).contains(&bits));
+        ProgramCounter { value: 0, bits }
+    }
+
+    fn mask(&self) -> u8 {
+        (0xffu16 >> (8 - self.bits)) as u8
+    }
+}
+
+/// The 4-bit counter of the breadboard build
+impl Default for ProgramCounter {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
 
 impl Module for ProgramCounter {
     fn get_name(&self) -> &'static str {
@@ -13,13 +36,25 @@ impl Module for ProgramCounter {
 
     fn step(&mut self, cw: ControlWord, _bus: u8) {
         if cw.has(ControlFlag::CounterEnable) {
-            let Wrapping(res) = Wrapping(self.0) + Wrapping(1);
-            self.0 = res & 0b1111;
+            let Wrapping(res) = Wrapping(self.value) + Wrapping(1);
+            self.value = res & self.mask();
         }
     }
 
     fn reset(&mut self) {
-        self.0 = 0;
+        self.value = 0;
+    }
+
+    fn value(&self) -> Option<u8> {
+        Some(self.value)
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        vec![self.value]
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        self.value = snapshot[0] & self.mask();
     }
 
     fn bus_read_flag(&self) -> ControlFlag {
@@ -31,22 +66,27 @@ impl Module for ProgramCounter {
     }
 
     fn read_from_bus(&mut self, bus: u8) {
-        self.0 = bus;
+        self.value = bus & self.mask();
     }
 
     fn write_to_bus(&mut self) -> u8 {
-        self.0
+        self.value
     }
 }
 
 impl GraphicalModule for ProgramCounter {
-    fn representation(&self) -> VisualRepresentation {
-        VisualRepresentation::LedHalf(self.0, LedColor::new(0.0, 1.0, 0.0))
+    fn representation(&self) -> VisualRepresentation<'_> {
+        let color = LedColor::new(0.0, 1.0, 0.0);
+        if self.bits <= 4 {
+            VisualRepresentation::LedHalf(self.value, color)
+        } else {
+            VisualRepresentation::LedN(self.value as usize, self.bits, color)
+        }
     }
 }
 
 impl Display for ProgramCounter {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{:04b}", self.0)
+        write!(f, "{:0width$b}", self.value, width = self.bits.max(4) as usize)
     }
 }
@@ -4,20 +4,60 @@ use crate::shareable::Shared;
 use std::default::Default;
 use std::fmt::{self, Display, Formatter};
 
+/// Bytes of RAM of the breadboard build
+pub const RAM_SIZE: usize = 16;
+/// Bytes of RAM of the extended machine, addressed by the whole memory
+/// address register
+pub const EXTENDED_RAM_SIZE: usize = 256;
+
 #[derive(Debug)]
 pub struct Ram {
     address: Shared<u8>,
-    pub memory: [u8; 16],
+    pub memory: Vec<u8>,
+    /// Address lines that are wired, the others are ignored
+    mask: usize,
+    /// Addresses taken by read-only chips
+    map: MemoryMap,
+    rejected: Option<(usize, u8)>,
     byte: u8,
 }
 
 impl Ram {
     pub fn new(address: Shared<u8>) -> Ram {
-        Ram {
+        Ram::with_size(address, RAM_SIZE).expect("the breadboard RAM size is valid")
+    }
+
+    /// RAM of `size` bytes, a power of two up to 256, addressed by the low
+    /// bits of the address register
+    pub fn with_size(address: Shared<u8>, size: usize) -> Result<Ram, String> {
+        if !size.is_power_of_two() || size > EXTENDED_RAM_SIZE {
+            return Err(format!(
+                "RAM cannot hold {} bytes, only powers of two up to 256",
+                size
+            ));
+        }
+        Ok(Ram {
             address,
-            memory: [0; 16],
+            memory: vec![0; size],
+            mask: size - 1,
+            map: MemoryMap::default(),
+            rejected: None,
             byte: Default::default(),
-        }
+        })
+    }
+
+    pub fn with_map(mut self, map: MemoryMap) -> Ram {
+        self.map = map;
+        self
+    }
+
+    /// Whether RAM rather than a read-only chip answers the selected address
+    fn selected(&self) -> bool {
+        !self.map.is_read_only(self.index())
+    }
+
+    fn index(&self) -> usize {
+        self.address.get() as usize & self.mask
     }
 }
 
@@ -27,11 +67,48 @@ impl Module for Ram {
     }
 
     fn pre_step(&mut self, _cw: ControlWord) {
-        self.byte = self.memory[self.address.get() as usize];
+        self.byte = self.memory[self.index()];
+    }
+
+    fn step(&mut self, _cw: ControlWord, _bus: u8) {
+        self.rejected = None;
     }
 
     fn reset(&mut self) {}
 
+    fn value(&self) -> Option<u8> {
+        Some(self.byte)
+    }
+
+    fn memory(&self) -> Option<&[u8]> {
+        Some(&self.memory)
+    }
+
+    fn address(&self) -> Option<usize> {
+        Some(self.index()).filter(|_| self.selected())
+    }
+
+    fn read_address(&self, address: usize) -> Option<u8> {
+        let index = address & self.mask;
+        Some(self.memory[index]).filter(|_| !self.map.is_read_only(index))
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut snapshot = self.memory.to_vec();
+        snapshot.push(self.byte);
+        snapshot
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        let size = self.memory.len();
+        self.memory.copy_from_slice(&snapshot[..size]);
+        self.byte = snapshot[size];
+    }
+
+    fn rejected_write(&self) -> Option<(usize, u8)> {
+        self.rejected
+    }
+
     fn bus_read_flag(&self) -> ControlFlag {
         ControlFlag::RamIn
     }
@@ -41,16 +118,29 @@ impl Module for Ram {
     }
 
     fn read_from_bus(&mut self, bus: u8) {
-        self.memory[self.address.get() as usize] = bus;
+        if !self.selected() {
+            self.rejected = Some((self.address.get() as usize, bus));
+            return;
+        }
+        let index = self.index();
+        self.memory[index] = bus;
     }
 
     fn write_to_bus(&mut self) -> u8 {
         self.byte
     }
+
+    fn bus_write(&mut self, cw: ControlWord) -> Option<u8> {
+        if cw.has(self.bus_write_flag()) && self.selected() {
+            Some(self.write_to_bus())
+        } else {
+            None
+        }
+    }
 }
 
 impl GraphicalModule for Ram {
-    fn representation(&self) -> VisualRepresentation {
+    fn representation(&self) -> VisualRepresentation<'_> {
         VisualRepresentation::led(self.byte)
     }
 }
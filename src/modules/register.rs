@@ -41,6 +41,18 @@ impl Module for Register {
         self.value.set(0);
     }
 
+    fn value(&self) -> Option<u8> {
+        Some(self.value.get())
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        vec![self.value.get()]
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        self.value.set(snapshot[0]);
+    }
+
     fn bus_read_flag(&self) -> ControlFlag {
         self.in_flag
     }
@@ -59,7 +71,7 @@ impl Module for Register {
 }
 
 impl GraphicalModule for Register {
-    fn representation(&self) -> VisualRepresentation {
+    fn representation(&self) -> VisualRepresentation<'_> {
         VisualRepresentation::led(self.value.get())
     }
 }
@@ -0,0 +1,138 @@
+use super::*;
+use crate::graphics::*;
+use crate::shareable::Shared;
+use std::default::Default;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Range;
+
+/// A fixed program at `start` in the address space, such as an EEPROM. It
+/// drives the bus on `RamOut` when one of its addresses is selected, and
+/// ignores `RamIn`.
+#[derive(Debug)]
+pub struct Rom {
+    name: String,
+    address: Shared<u8>,
+    start: usize,
+    contents: Vec<u8>,
+    /// Address lines that are wired, the others are ignored
+    mask: usize,
+    byte: u8,
+}
+
+impl Rom {
+    pub fn new(name: &str, address: Shared<u8>, start: usize, contents: Vec<u8>) -> Rom {
+        Rom {
+            name: name.to_string(),
+            address,
+            start,
+            contents,
+            mask: EXTENDED_RAM_SIZE - 1,
+            byte: Default::default(),
+        }
+    }
+
+    /// Decodes only the low address lines, like RAM of `size` bytes sharing
+    /// the address register. `size` is a power of two up to 256.
+    pub fn with_size(mut self, size: usize) -> Result<Rom, String> {
+        if !size.is_power_of_two() || size > EXTENDED_RAM_SIZE {
+            return Err(format!(
+                "a ROM cannot decode {} addresses, only powers of two up to 256",
+                size
+            ));
+        }
+        self.mask = size - 1;
+        Ok(self)
+    }
+
+    /// Addresses the ROM answers to, before the unwired lines are ignored
+    pub fn region(&self) -> Range<usize> {
+        self.start..self.start + self.contents.len()
+    }
+
+    /// Offset of an address in the ROM, if it answers to it
+    fn offset(&self, address: usize) -> Option<usize> {
+        let address = address & self.mask;
+        if self.region().contains(&address) {
+            Some(address - self.start)
+        } else {
+            None
+        }
+    }
+
+    /// Offset of the selected address in the ROM, if it is selected
+    fn selected(&self) -> Option<usize> {
+        self.offset(self.address.get() as usize)
+    }
+}
+
+impl Module for Rom {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn pre_step(&mut self, _cw: ControlWord) {
+        self.byte = self.selected().map_or(0, |offset| self.contents[offset]);
+    }
+
+    fn reset(&mut self) {}
+
+    fn value(&self) -> Option<u8> {
+        Some(self.byte)
+    }
+
+    fn memory(&self) -> Option<&[u8]> {
+        Some(&self.contents)
+    }
+
+    fn address(&self) -> Option<usize> {
+        self.selected()
+    }
+
+    fn memory_start(&self) -> usize {
+        self.start
+    }
+
+    fn read_address(&self, address: usize) -> Option<u8> {
+        self.offset(address).map(|offset| self.contents[offset])
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut snapshot = self.contents.clone();
+        snapshot.push(self.byte);
+        snapshot
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) {
+        let size = self.contents.len();
+        self.contents.copy_from_slice(&snapshot[..size]);
+        self.byte = snapshot[size];
+    }
+
+    fn bus_write_flag(&self) -> ControlFlag {
+        ControlFlag::RamOut
+    }
+
+    fn write_to_bus(&mut self) -> u8 {
+        self.byte
+    }
+
+    fn bus_write(&mut self, cw: ControlWord) -> Option<u8> {
+        if cw.has(self.bus_write_flag()) && self.selected().is_some() {
+            Some(self.write_to_bus())
+        } else {
+            None
+        }
+    }
+}
+
+impl GraphicalModule for Rom {
+    fn representation(&self) -> VisualRepresentation<'_> {
+        VisualRepresentation::led(self.byte)
+    }
+}
+
+impl Display for Rom {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:08b}", self.byte)
+    }
+}
@@ -0,0 +1,175 @@
+// Loading RAM images from disk
+use crate::assembler::assemble_image;
+use crate::isa::Encoding;
+use std::convert::AsRef;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProgramFormat {
+    /// Raw bytes, loaded at address 0
+    Binary,
+    IntelHex,
+    /// Source for the assembler
+    Assembly,
+}
+
+impl FromStr for ProgramFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "bin" | "binary" => Ok(ProgramFormat::Binary),
+            "hex" | "ihex" => Ok(ProgramFormat::IntelHex),
+            "asm" | "assembly" => Ok(ProgramFormat::Assembly),
+            _ => Err(format!("unknown program format {}", s)),
+        }
+    }
+}
+
+impl ProgramFormat {
+    /// Guesses the format from the file extension, falling back to the
+    /// contents of the file
+    pub fn detect(path: &Path, contents: &[u8]) -> ProgramFormat {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("bin") | Some("rom") => return ProgramFormat::Binary,
+            Some("hex") | Some("ihex") => return ProgramFormat::IntelHex,
+            Some("asm") | Some("s") => return ProgramFormat::Assembly,
+            _ => (),
+        }
+        let text = match std::str::from_utf8(contents) {
+            Ok(text) => text,
+            Err(_) => return ProgramFormat::Binary,
+        };
+        if text
+            .chars()
+            .any(|c| c.is_control() && !c.is_ascii_whitespace())
+        {
+            ProgramFormat::Binary
+        } else if text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .all(|l| l.starts_with(':'))
+        {
+            ProgramFormat::IntelHex
+        } else {
+            ProgramFormat::Assembly
+        }
+    }
+}
+
+/// Parses data records of an Intel HEX file into a memory image of at most
+/// `size` bytes. The image is as long as the highest address written to.
+pub fn parse_intel_hex(text: &str, size: usize) -> Result<Vec<u8>, String> {
+    let mut image = Vec::new();
+    let mut base = 0usize;
+    for (number, line) in text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| format!("line {}: record does not start with ':'", number))?;
+        if hex.len() % 2 != 0 || hex.len() < 10 {
+            return Err(format!("line {}: malformed record", number));
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| format!("line {}: invalid hex digit", number))?;
+        let length = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            return Err(format!("line {}: record length does not match", number));
+        }
+        let checksum = bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        if checksum != 0 {
+            return Err(format!("line {}: checksum mismatch", number));
+        }
+        let address = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        let data = &bytes[4..4 + length];
+        match bytes[3] {
+            0x00 => {
+                let start = base + address;
+                if start + length > size {
+                    return Err(format!(
+                        "line {}: record ends at {:#x} but RAM only holds {} bytes",
+                        number,
+                        start + length,
+                        size
+                    ));
+                }
+                if image.len() < start + length {
+                    image.resize(start + length, 0);
+                }
+                image[start..start + length].copy_from_slice(data);
+            }
+            0x01 => break,
+            // Extended segment address
+            0x02 if length == 2 => base = ((data[0] as usize) << 8 | data[1] as usize) << 4,
+            // Extended linear address
+            0x04 if length == 2 => base = ((data[0] as usize) << 8 | data[1] as usize) << 16,
+            // Start addresses are meaningless here
+            0x03 | 0x05 => (),
+            record => {
+                return Err(format!(
+                    "line {}: unsupported record type {:02x}",
+                    number, record
+                ))
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// Parses a RAM image for a machine using `encoding`, with `size` bytes of RAM
+pub fn parse_program(
+    contents: &[u8],
+    format: ProgramFormat,
+    encoding: Encoding,
+    size: usize,
+) -> Result<Vec<u8>, String> {
+    let image = match format {
+        ProgramFormat::Binary => contents.to_vec(),
+        ProgramFormat::IntelHex => {
+            let text = std::str::from_utf8(contents).map_err(|e| e.to_string())?;
+            parse_intel_hex(text, size)?
+        }
+        ProgramFormat::Assembly => {
+            let text = std::str::from_utf8(contents).map_err(|e| e.to_string())?;
+            let (mut image, end) = assemble_image(text, encoding).map_err(|e| e.to_string())?;
+            // The assembler pads the image to the whole address space,
+            // smaller RAMs only need the bytes up to the last one written
+            image.truncate(end.max(size));
+            image
+        }
+    };
+    if image.len() > size {
+        return Err(format!(
+            "program is {} bytes long but RAM only holds {} bytes",
+            image.len(),
+            size
+        ));
+    }
+    Ok(image)
+}
+
+/// Reads a RAM image from a file. The format is detected if not given.
+pub fn load_program<P: AsRef<Path>>(
+    path: P,
+    format: Option<ProgramFormat>,
+    encoding: Encoding,
+    size: usize,
+) -> Result<Vec<u8>, String> {
+    let path = path.as_ref();
+    let contents = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let format = format.unwrap_or_else(|| ProgramFormat::detect(path, &contents));
+    parse_program(&contents, format, encoding, size)
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
@@ -0,0 +1,244 @@
+// Saving the whole machine to disk and loading it back
+use crate::modules::{
+    AddressLayout, ControlFlag, ControlNames, ControlWord, DecoderKind, DEFAULT_STEPS,
+    EXTENDED_STEPS,
+};
+use std::convert::{AsRef, TryFrom};
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"BB8STATE";
+pub const VERSION: u8 = 1;
+
+// File layout, all integers little-endian:
+//   magic, version: u8, decoder kind: u8,
+//   microcode word count: u16, words: u32, layout length: u8, layout and
+//   decoder steps: u8 (microcode decoders only),
+//   custom control bit count: u8, then for each bit:
+//     control bit: u8, name length: u8, name, label length: u8, label
+//   halted: u8, cycles: u64, decoder step: u8, bus: u8, control word: u32,
+//   module count: u8, then for each module:
+//     name length: u8, name, snapshot length: u16, snapshot
+
+/// Everything needed to resume a machine where it was saved
+#[derive(Clone)]
+pub struct SaveState {
+    pub decoder: DecoderKind,
+    pub microcode: Option<Vec<u32>>,
+    /// Address layout of the microcode
+    pub layout: Option<AddressLayout>,
+    /// Steps before the decoder counter wraps around
+    pub steps: usize,
+    pub halted: bool,
+    pub cycles: u64,
+    pub step: usize,
+    pub bus: u8,
+    pub cw: ControlWord,
+    /// Names of the control bits, for those driving custom modules
+    pub names: ControlNames,
+    /// Snapshot of each module, by name
+    pub modules: Vec<(String, Vec<u8>)>,
+}
+
+impl SaveState {
+    pub fn module(&self, name: &str) -> Option<&[u8]> {
+        self.modules
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, snapshot)| snapshot.as_slice())
+    }
+
+    /// Fails if a count or length does not fit in its field
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(match self.decoder {
+            DecoderKind::Simple => 0,
+            DecoderKind::Branching => 1,
+            DecoderKind::Microcode => 2,
+            DecoderKind::Extended => 3,
+        });
+        if let Some(microcode) = &self.microcode {
+            let len = u16::try_from(microcode.len())
+                .map_err(|_| too_long("microcode length", microcode.len()))?;
+            bytes.extend_from_slice(&len.to_le_bytes());
+            for word in microcode {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+            let layout = self.layout.unwrap_or_default().to_string();
+            bytes.push(
+                u8::try_from(layout.len()).map_err(|_| too_long("address layout", layout.len()))?,
+            );
+            bytes.extend_from_slice(layout.as_bytes());
+            bytes.push(u8::try_from(self.steps).map_err(|_| too_long("step count", self.steps))?);
+        }
+        let custom: Vec<u8> = self
+            .names
+            .named_bits()
+            .filter(|&bit| !built_in(bit))
+            .collect();
+        bytes.push(
+            u8::try_from(custom.len()).map_err(|_| too_long("control bit count", custom.len()))?,
+        );
+        for bit in custom {
+            bytes.push(bit);
+            for s in [self.names.name(bit), self.names.label(bit)].iter() {
+                bytes.push(
+                    u8::try_from(s.len())
+                        .map_err(|_| too_long(&format!("name of bit {}", bit), s.len()))?,
+                );
+                bytes.extend_from_slice(s.as_bytes());
+            }
+        }
+        bytes.push(self.halted as u8);
+        bytes.extend_from_slice(&self.cycles.to_le_bytes());
+        bytes.push(u8::try_from(self.step).map_err(|_| too_long("decoder step", self.step))?);
+        bytes.push(self.bus);
+        bytes.extend_from_slice(&self.cw.0.to_le_bytes());
+        let count = u8::try_from(self.modules.len())
+            .map_err(|_| too_long("module count", self.modules.len()))?;
+        bytes.push(count);
+        for (name, snapshot) in &self.modules {
+            bytes.push(
+                u8::try_from(name.len())
+                    .map_err(|_| too_long(&format!("name of {}", name), name.len()))?,
+            );
+            bytes.extend_from_slice(name.as_bytes());
+            let len = u16::try_from(snapshot.len())
+                .map_err(|_| too_long(&format!("snapshot of {}", name), snapshot.len()))?;
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(snapshot);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if !bytes.starts_with(MAGIC) {
+            return Err("not a saved state".to_string());
+        }
+        let mut reader = Reader(&bytes[MAGIC.len()..]);
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!(
+                "unsupported saved state version {} (expected {})",
+                version, VERSION
+            ));
+        }
+        let decoder = match reader.u8()? {
+            0 => DecoderKind::Simple,
+            1 => DecoderKind::Branching,
+            2 => DecoderKind::Microcode,
+            3 => DecoderKind::Extended,
+            kind => return Err(format!("unknown decoder kind {}", kind)),
+        };
+        let (microcode, layout, steps) = if decoder == DecoderKind::Microcode {
+            let len = reader.u16()? as usize;
+            let microcode = (0..len).map(|_| reader.u32()).collect::<Result<_, _>>()?;
+            let len = reader.u8()? as usize;
+            let layout = std::str::from_utf8(reader.take(len)?)
+                .map_err(|_| "invalid microcode layout".to_string())?
+                .parse()?;
+            let steps = reader.u8()? as usize;
+            (Some(microcode), Some(layout), steps)
+        } else if decoder == DecoderKind::Extended {
+            (None, None, EXTENDED_STEPS)
+        } else {
+            (None, None, DEFAULT_STEPS)
+        };
+        let mut names = ControlNames::default();
+        for _ in 0..reader.u8()? {
+            let bit = reader.u8()?;
+            if bit >= 32 || built_in(bit) {
+                return Err(format!("control bit {} cannot be renamed", bit));
+            }
+            let mut text = || -> Result<String, String> {
+                let len = reader.u8()? as usize;
+                String::from_utf8(reader.take(len)?.to_vec())
+                    .map_err(|_| format!("invalid name for control bit {}", bit))
+            };
+            let (name, label) = (text()?, text()?);
+            names.set(bit, &name, &label);
+        }
+        let halted = reader.u8()? != 0;
+        let cycles = u64::from_le_bytes(reader.array()?);
+        let step = reader.u8()? as usize;
+        let bus = reader.u8()?;
+        let cw = ControlWord(reader.u32()?);
+        let mut modules = Vec::new();
+        for _ in 0..reader.u8()? {
+            let len = reader.u8()? as usize;
+            let name = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| "invalid module name".to_string())?;
+            let len = reader.u16()? as usize;
+            modules.push((name, reader.take(len)?.to_vec()));
+        }
+        if !reader.0.is_empty() {
+            return Err(format!("{} unexpected bytes at the end", reader.0.len()));
+        }
+        Ok(SaveState {
+            decoder,
+            microcode,
+            layout,
+            steps,
+            halted,
+            cycles,
+            step,
+            bus,
+            cw,
+            names,
+            modules,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes()?).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::from_bytes(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+/// Reads little-endian integers from a byte slice
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("unexpected end of file".to_string());
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+}
+
+/// Whether a control bit is one of the built-in flags, whose names are fixed
+fn built_in(bit: u8) -> bool {
+    ControlFlag::ALL.iter().any(|flag| flag.bit() == bit)
+}
+
+fn too_long(what: &str, len: usize) -> String {
+    format!("the {} ({}) does not fit in a saved state", what, len)
+}
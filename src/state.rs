@@ -1,19 +1,112 @@
-use atty::Stream;
+use crate::breadboard_builder::BreadboardBuilder;
+use crate::breakpoints::{Breakpoints, Hit};
+use crate::bus::{Contention, ContentionPolicy, FloatingPolicy, Noise};
 use crate::graphics::GraphicalModule;
+use crate::isa::Encoding;
 use crate::modules::*;
+use crate::save_state::SaveState;
 use crate::shareable::{Share, Shared};
+use crate::trace::{CycleTrace, StdoutSink, TraceFormat, Tracer};
+use std::collections::VecDeque;
 use std::convert::AsRef;
 use std::default::Default;
+use std::fmt::{self, Display, Formatter};
 use std::path::Path;
 
 pub type Modules = Vec<Box<dyn GraphicalModule>>;
 
+/// Number of cycles that can be stepped back by default
+pub const DEFAULT_HISTORY_LENGTH: usize = 1024;
+
+/// Number of warnings kept until they are taken, older ones are dropped
+pub const MAX_WARNINGS: usize = 1024;
+
+/// State of the whole machine at the start of a clock cycle
+#[derive(Clone)]
+struct Snapshot {
+    modules: Vec<Vec<u8>>,
+    counter: usize,
+    bus: u8,
+    cw: ControlWord,
+    cycles: u64,
+    contention: Option<Contention>,
+    noise: Noise,
+    /// Values seen by the watchpoints
+    watched: Vec<Option<u8>>,
+}
+
 pub struct BreadboardState<I: InstructionDecoder = BranchingInstructionDecoder> {
     modules: Modules,
     decoder: I,
+    /// Names of the control bits, from the decoder
+    control_names: ControlNames,
+    /// How programs are laid out in memory
+    encoding: Encoding,
     bus: u8,
     cw: ControlWord,
-    output: Vec<(String, String)>,
+    tracer: Tracer,
+    breakpoints: Breakpoints,
+    breakpoint_hit: Option<Hit>,
+    contention_policy: ContentionPolicy,
+    /// Modules that drove the bus at the same time during the current cycle
+    contention: Option<Contention>,
+    floating_policy: FloatingPolicy,
+    noise: Noise,
+    /// Warnings not taken yet, with the cycle they happened on
+    warnings: VecDeque<(u64, Warning)>,
+    halted: bool,
+    cycles: u64,
+    /// Whether the control word and bus are up to date for the current cycle
+    settled: bool,
+    /// Snapshots of the previous cycles, most recent last
+    history: VecDeque<Snapshot>,
+    history_length: usize,
+}
+
+/// Something the machine did that the hardware would not do reliably
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// Several modules wrote to the bus, with the `Warn` contention policy
+    Contention(Contention),
+    /// The named module read the bus while nothing wrote to it
+    FloatingRead(String),
+    /// A memory ignored a write to one of its read-only addresses
+    RejectedWrite {
+        module: String,
+        address: usize,
+        value: u8,
+    },
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Warning::Contention(contention) => write!(f, "{}", contention),
+            Warning::FloatingRead(module) => {
+                write!(f, "{} reads from the bus but nothing writes to it", module)
+            }
+            Warning::RejectedWrite {
+                module,
+                address,
+                value,
+            } => write!(
+                f,
+                "{} ignored a write of {:08b} to read-only address {:#04x}",
+                module, value, address
+            ),
+        }
+    }
+}
+
+/// What happened while stepping the clock
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StepSummary {
+    /// Number of clock cycles that were executed
+    pub cycles: u64,
+    /// Whether the computer is halted
+    pub halted: bool,
+    /// Whether a breakpoint stopped the run
+    pub breakpoint: bool,
 }
 
 impl Default for BreadboardState {
@@ -23,7 +116,7 @@ impl Default for BreadboardState {
 }
 
 impl BreadboardState {
-    pub fn default_with_ram<F: FnOnce(&mut [u8; 16])>(
+    pub fn default_with_ram<F: FnOnce(&mut [u8])>(
         f: F,
     ) -> BreadboardState<BranchingInstructionDecoder> {
         Self::default_with_decoder(f, |i, f| Ok(BranchingInstructionDecoder::new(i, f))).unwrap()
@@ -35,71 +128,147 @@ impl BreadboardState {
     ) -> Result<BreadboardState<MicrocodeDecoder>, String>
     where
         P: AsRef<Path>,
-        F: FnOnce(&mut [u8; 16]),
+        F: FnOnce(&mut [u8]),
     {
         Self::default_with_decoder(
             ram_init,
             |instruction, flags| {
-                MicrocodeDecoder::from_file(instruction, flags, microcode_path).map_err(|e| e.to_string())
+                MicrocodeDecoder::from_file(instruction, flags, microcode_path)
             },
         )
     }
 
     pub fn default_with_decoder<R, D, I>(ram_init: R, get_decoder: D) -> Result<BreadboardState<I>, String>
     where
-        R: FnOnce(&mut [u8; 16]),
+        R: FnOnce(&mut [u8]),
         D: FnOnce(Shared<u8>, Shared<u8>) -> Result<I, String>,
         I: InstructionDecoder + Share<u8>,
     {
-        let a = Register::new(
-            "A Register",
-            ControlFlag::ARegisterIn,
-            ControlFlag::ARegisterOut,
-        );
-        let b = Register::new_ro("B Register", ControlFlag::BRegisterIn);
-        let alu = Alu::new(a.share(), b.share());
-        let flags_register = FlagsRegister::new(alu.share(), alu.get_labels());
-        let output = OutputRegister(0);
-        let address_register = Register::new_ro("Memory Address", ControlFlag::MemoryAddressIn);
-        let mut ram = Ram::new(address_register.share());
-        ram_init(&mut ram.memory);
-        let instruction_register = InstructionRegister::default();
-        let instruction = instruction_register.share();
-        let program_counter = ProgramCounter(0);
-        let decoder = get_decoder(instruction, alu.share())?;
-        let decoder_step = DecoderStep(decoder.share());
-        let modules: Modules = vec![
-            Box::new(program_counter),
-            Box::new(address_register),
-            Box::new(ram),
-            Box::new(instruction_register),
-            Box::new(decoder_step),
-            Box::new(a),
-            Box::new(alu),
-            Box::new(flags_register),
-            Box::new(b),
-            Box::new(output),
-        ];
-        Ok(BreadboardState::new(modules, decoder))
+        BreadboardBuilder::sap1().build(ram_init, get_decoder)
     }
 }
 
 impl<I: InstructionDecoder> BreadboardState<I> {
     pub fn new(modules: Modules, decoder: I) -> Self {
-        let output = Vec::with_capacity(modules.len());
         BreadboardState {
             modules,
+            control_names: decoder.control_names(),
+            encoding: Encoding::default(),
             decoder,
             bus: 0,
             cw: ControlWord(0),
-            output,
+            tracer: Tracer::default(),
+            breakpoints: Breakpoints::default(),
+            breakpoint_hit: None,
+            contention_policy: ContentionPolicy::default(),
+            contention: None,
+            floating_policy: FloatingPolicy::default(),
+            noise: Noise::default(),
+            warnings: VecDeque::new(),
+            halted: false,
+            cycles: 0,
+            settled: false,
+            history: VecDeque::new(),
+            history_length: DEFAULT_HISTORY_LENGTH,
+        }
+    }
+
+    /// Sets how many cycles can be stepped back, 0 disables the history
+    pub fn with_history_length(mut self, length: usize) -> Self {
+        self.history_length = length;
+        self.history.truncate(length);
+        self
+    }
+
+    /// Sets how programs are laid out in memory, for the trace
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Replaces the default trace, which prints every module to stdout
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = tracer;
+    }
+
+    /// Prints every module of the current cycle to stdout
+    #[deprecated(note = "use a `Tracer` with `TraceFormat::Full` instead")]
+    pub fn pretty_print_output(&self) {
+        let cycle = CycleTrace {
+            cycle: self.cycles,
+            step: self.decoder.get_counter(),
+            instruction: self.decoder.instruction(),
+            encoding: self.encoding,
+            cw: self.cw,
+            names: &self.control_names,
+            bus: self.bus,
+            modules: &self.modules,
+        };
+        Tracer::new(TraceFormat::Full, StdoutSink).trace(&cycle);
+    }
+
+    pub fn with_breakpoints(mut self, breakpoints: Breakpoints) -> Self {
+        self.breakpoints = breakpoints;
+        self
+    }
+
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// The breakpoint triggered by the current cycle, if any
+    pub fn breakpoint_hit(&self) -> Option<&Hit> {
+        self.breakpoint_hit.as_ref()
+    }
+
+    pub fn with_contention_policy(mut self, policy: ContentionPolicy) -> Self {
+        self.contention_policy = policy;
+        self
+    }
+
+    pub fn with_floating_policy(mut self, policy: FloatingPolicy) -> Self {
+        self.floating_policy = policy;
+        self
+    }
+
+    /// The modules that collided on the bus during the current cycle, if any
+    pub fn bus_contention(&self) -> Option<&Contention> {
+        self.contention.as_ref()
+    }
+
+    /// The warnings raised since the last call, with the cycle they happened
+    /// on, oldest first
+    pub fn take_warnings(&mut self) -> Vec<(u64, Warning)> {
+        self.warnings.drain(..).collect()
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        if self.warnings.len() == MAX_WARNINGS {
+            self.warnings.pop_front();
         }
+        self.warnings.push_back((self.cycles, warning));
     }
 
     pub fn modules(&self) -> &Modules {
         &self.modules
     }
 
+    pub fn module(&self, name: &str) -> Option<&dyn GraphicalModule> {
+        self.modules
+            .iter()
+            .find(|m| m.get_name() == name)
+            .map(|m| m.as_ref())
+    }
+
     pub fn bus(&self) -> u8 {
         self.bus
     }
@@ -108,14 +277,248 @@ impl<I: InstructionDecoder> BreadboardState<I> {
         self.cw
     }
 
+    pub fn control_names(&self) -> &ControlNames {
+        &self.control_names
+    }
+
+    /// Contents of the instruction register
+    pub fn instruction(&self) -> u8 {
+        self.decoder.instruction()
+    }
+
+    /// Step of the decoder within the current instruction
+    pub fn step(&self) -> usize {
+        self.decoder.get_counter()
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Number of clock cycles executed since the last reset
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
     pub fn reset(&mut self) {
         for module in self.modules.iter_mut() {
             module.reset();
         }
         self.decoder.reset_counter();
+        self.halted = false;
+        self.cycles = 0;
+        self.settled = false;
+        self.history.clear();
+        self.breakpoints.reset();
+        self.breakpoint_hit = None;
+    }
+
+    /// Decodes the current cycle and drives the bus, if that has not been
+    /// done yet
+    pub fn settle(&mut self) {
+        if !self.settled {
+            self.pre_step();
+        }
+    }
+
+    /// Executes one clock cycle, unless the computer is halted, then decodes
+    /// the next one
+    pub fn step_cycle(&mut self) -> StepSummary {
+        self.settle();
+        let cycles = self.cycles;
+        if !self.halted {
+            self.rising_edge();
+            // The halting cycle was already decoded and traced
+            if !self.halted {
+                self.falling_edge();
+                self.pre_step();
+            }
+        }
+        StepSummary {
+            cycles: self.cycles - cycles,
+            halted: self.halted,
+            breakpoint: self.breakpoint_hit.is_some(),
+        }
+    }
+
+    /// Executes clock cycles until the decoder moves on to the next
+    /// instruction or the computer halts
+    pub fn step_instruction(&mut self) -> StepSummary {
+        let mut summary = StepSummary::default();
+        loop {
+            let step = self.step_cycle();
+            summary.cycles += step.cycles;
+            summary.halted = step.halted;
+            summary.breakpoint = step.breakpoint;
+            if step.halted || self.decoder.get_counter() == 0 {
+                return summary;
+            }
+        }
+    }
+
+    /// Executes clock cycles until the computer halts or reaches a
+    /// breakpoint, or at most `max_cycles` cycles. A breakpoint on the
+    /// current cycle does not stop the run, so that it can be resumed.
+    pub fn run_until_halt(&mut self, max_cycles: u64) -> StepSummary {
+        let mut summary = StepSummary {
+            halted: self.halted,
+            ..StepSummary::default()
+        };
+        while !summary.halted && !summary.breakpoint && summary.cycles < max_cycles {
+            let step = self.step_cycle();
+            summary.cycles += step.cycles;
+            summary.halted = step.halted;
+            summary.breakpoint = step.breakpoint;
+        }
+        summary
+    }
+
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            decoder: self.decoder.kind(),
+            microcode: self.decoder.microcode().map(|m| m.to_vec()),
+            layout: self.decoder.microcode_layout(),
+            steps: self.decoder.steps(),
+            halted: self.halted,
+            cycles: self.cycles,
+            step: self.decoder.get_counter(),
+            bus: self.bus,
+            cw: self.cw,
+            names: self.control_names.clone(),
+            modules: self
+                .modules
+                .iter()
+                .filter(|m| !m.get_name().is_empty())
+                .map(|m| (m.get_name().to_string(), m.snapshot()))
+                .collect(),
+        }
+    }
+
+    /// Puts the machine back in a saved state. The machine must have the same
+    /// decoder and modules as the one that was saved.
+    pub fn load_state(&mut self, saved: &SaveState) -> Result<(), String> {
+        if saved.decoder != self.decoder.kind() {
+            return Err(format!(
+                "state was saved with a {:?} decoder, not {:?}",
+                saved.decoder,
+                self.decoder.kind()
+            ));
+        }
+        if saved.microcode.as_deref() != self.decoder.microcode()
+            || saved.layout != self.decoder.microcode_layout()
+            || saved.steps != self.decoder.steps()
+        {
+            return Err("state was saved with different microcode".to_string());
+        }
+        if saved.names != self.control_names {
+            return Err("state was saved with different control bit names".to_string());
+        }
+        if saved.step >= self.decoder.steps() {
+            return Err(format!(
+                "state was saved at step {} but the decoder only has {} steps",
+                saved.step,
+                self.decoder.steps()
+            ));
+        }
+        for module in self.modules.iter().filter(|m| !m.get_name().is_empty()) {
+            let snapshot = saved
+                .module(module.get_name())
+                .ok_or_else(|| format!("no saved state for {}", module.get_name()))?;
+            if snapshot.len() != module.snapshot().len() {
+                return Err(format!(
+                    "saved state for {} is {} bytes long, expected {}",
+                    module.get_name(),
+                    snapshot.len(),
+                    module.snapshot().len()
+                ));
+            }
+        }
+        if let Some((name, _)) = saved.modules.iter().find(|(name, _)| self.module(name).is_none()) {
+            return Err(format!("state was saved with a {} module, which this machine does not have", name));
+        }
+        for module in self.modules.iter_mut() {
+            if let Some(snapshot) = saved.module(module.get_name()) {
+                module.restore(snapshot);
+            }
+        }
+        self.decoder.set_counter(saved.step);
+        self.bus = saved.bus;
+        self.cw = saved.cw;
+        self.halted = saved.halted;
+        self.cycles = saved.cycles;
+        self.breakpoint_hit = None;
+        // Decode the saved cycle again so that it gets traced
+        self.settled = false;
+        self.history.clear();
+        Ok(())
+    }
+
+    /// Number of cycles that can currently be stepped back
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Restores the machine to the start of the previous clock cycle.
+    /// Returns false if there is no history left.
+    pub fn step_back_cycle(&mut self) -> bool {
+        let snapshot = match self.history.pop_back() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        for (module, state) in self.modules.iter_mut().zip(snapshot.modules.iter()) {
+            module.restore(state);
+        }
+        self.decoder.set_counter(snapshot.counter);
+        self.bus = snapshot.bus;
+        self.cw = snapshot.cw;
+        self.cycles = snapshot.cycles;
+        self.contention = snapshot.contention;
+        self.noise = snapshot.noise;
+        self.breakpoints.set_watched(&snapshot.watched);
+        self.halted = false;
+        self.breakpoint_hit = None;
+        self.settled = true;
+        true
+    }
+
+    /// Steps back to the start of the current instruction, or of the
+    /// previous one if the current instruction has not started executing.
+    /// Returns the number of cycles that were stepped back.
+    pub fn step_back_instruction(&mut self) -> u64 {
+        let mut cycles = 0;
+        while self.step_back_cycle() {
+            cycles += 1;
+            if self.decoder.get_counter() == 0 {
+                break;
+            }
+        }
+        cycles
+    }
+
+    fn record_snapshot(&mut self) {
+        if self.history_length == 0 {
+            return;
+        }
+        if self.history.len() == self.history_length {
+            self.history.pop_front();
+        }
+        self.history.push_back(Snapshot {
+            modules: self.modules.iter().map(|m| m.snapshot()).collect(),
+            counter: self.decoder.get_counter(),
+            bus: self.bus,
+            cw: self.cw,
+            cycles: self.cycles,
+            contention: self.contention.clone(),
+            noise: self.noise.clone(),
+            watched: self.breakpoints.watched().to_vec(),
+        });
     }
 
     pub fn falling_edge(&mut self) {
+        if self.halted {
+            return;
+        }
+        self.cycles += 1;
         self.decoder.step();
         if self.cw.has(ControlFlag::NextInstruction) {
             self.decoder.reset_counter();
@@ -124,8 +527,12 @@ impl<I: InstructionDecoder> BreadboardState<I> {
 
     pub fn rising_edge(&mut self) {
         if self.cw.has(ControlFlag::Hlt) {
+            self.halted = true;
+            self.cycles += 1;
             return;
         }
+        self.record_snapshot();
+        self.settled = false;
         for module in self.modules.iter_mut() {
             module.step(self.cw, self.bus);
         }
@@ -133,6 +540,20 @@ impl<I: InstructionDecoder> BreadboardState<I> {
         for module in self.modules.iter_mut() {
             module.bus_read(self.cw, self.bus);
         }
+        let rejected: Vec<Warning> = self
+            .modules
+            .iter()
+            .filter_map(|m| {
+                m.rejected_write().map(|(address, value)| Warning::RejectedWrite {
+                    module: m.get_name().to_string(),
+                    address,
+                    value,
+                })
+            })
+            .collect();
+        for warning in rejected {
+            self.warn(warning);
+        }
     }
 
     pub fn pre_step(&mut self) {
@@ -140,60 +561,98 @@ impl<I: InstructionDecoder> BreadboardState<I> {
         for module in self.modules.iter_mut() {
             module.pre_step(self.cw);
         }
-        let mut maybe_bus = None;
         let cw = self.cw;
-        for module in self.modules.iter_mut() {
-            maybe_bus = maybe_bus.or_else(|| module.bus_write(cw));
-        }
-        self.bus = maybe_bus.unwrap_or(0);
-        self.output.clear();
-
-        for module in self.modules.iter() {
-            self.output
-                .push((module.get_name().to_string(), format!("{}", module)));
-        }
-        self.output
-            .push(("Control word".to_string(), format!("{}", self.cw)));
-        self.output
-            .push(("Bus".to_string(), format!("{:08b}", self.bus)));
-        self.pretty_print_output();
-    }
-
-    pub fn pretty_print_output(&self) {
-        let longest = self
-            .output
-            .iter()
-            .map(|(name, _)| name.len())
-            .max()
-            .unwrap_or(0);
-        for (ref name, ref contents) in self.output.iter() {
-            if name.is_empty() && contents.is_empty() {
-                continue;
+        let drivers: Vec<(String, u8)> = self
+            .modules
+            .iter_mut()
+            .filter_map(|m| m.bus_write(cw).map(|value| (m.get_name().to_string(), value)))
+            .collect();
+        self.contention = None;
+        self.bus = match drivers.len() {
+            0 => {
+                let readers: Vec<String> = self
+                    .modules
+                    .iter()
+                    .filter(|m| cw.has(m.bus_read_flag()))
+                    .map(|m| m.get_name().to_string())
+                    .collect();
+                for reader in readers {
+                    self.warn(Warning::FloatingRead(reader));
+                }
+                self.floating_policy.resolve(self.bus, &mut self.noise)
             }
-            if atty::is(Stream::Stdout) {
-                println!(
-                    "\x1b[1;32m{:>width$}\x1b[0m {}",
-                    name,
-                    contents,
-                    width = longest
-                );
-            } else {
-                println!("{:>width$} {}", name, contents, width = longest);
+            1 => drivers[0].1,
+            _ => {
+                let contention = Contention { drivers };
+                match self.contention_policy {
+                    ContentionPolicy::Warn => self.warn(Warning::Contention(contention.clone())),
+                    ContentionPolicy::Halt => self.halted = true,
+                    _ => (),
+                }
+                let bus = self.contention_policy.resolve(&contention);
+                self.contention = Some(contention);
+                bus
             }
-        }
-        println!();
+        };
+        let cycle = CycleTrace {
+            cycle: self.cycles,
+            step: self.decoder.get_counter(),
+            instruction: self.decoder.instruction(),
+            encoding: self.encoding,
+            cw: self.cw,
+            names: &self.control_names,
+            bus: self.bus,
+            modules: &self.modules,
+        };
+        self.tracer.trace(&cycle);
+        self.breakpoint_hit = self.breakpoints.check(&cycle);
+        self.settled = true;
     }
 }
 
-pub fn write_sample_program(ram: &mut [u8; 16]) {
+pub fn write_sample_program(ram: &mut [u8]) {
     // Increments A to 255 then decrements it down to 0 and repeats
-    ram[0x0] = 0xe0; // OUT
-    ram[0x1] = 0x2f; // ADD 15
-    ram[0x2] = 0x74; // JC 4
-    ram[0x3] = 0x60; // JMP 0
-    ram[0x4] = 0x3f; // SUB 15
-    ram[0x5] = 0xe0; // OUT
-    ram[0x6] = 0x80; // JZ 0
-    ram[0x7] = 0x64; // JMP 4
-    ram[0xf] = 1;
+    write_sample(
+        ram,
+        &[
+            0xe0, // OUT
+            0x2f, // ADD 15
+            0x74, // JC 4
+            0x60, // JMP 0
+            0x3f, // SUB 15
+            0xe0, // OUT
+            0x80, // JZ 0
+            0x64, // JMP 4
+        ],
+        0xf,
+    );
+}
+
+/// The sample program laid out for the extended machine, with each operand
+/// in the byte after its instruction
+pub fn write_extended_sample_program(ram: &mut [u8]) {
+    write_sample(
+        ram,
+        &[
+            0xe0, // OUT
+            0x20, 0xff, // ADD 255
+            0x70, 0x07, // JC 7
+            0x60, 0x00, // JMP 0
+            0x30, 0xff, // SUB 255
+            0xe0, // OUT
+            0x80, 0x00, // JZ 0
+            0x60, 0x07, // JMP 7
+        ],
+        0xff,
+    );
+}
+
+/// Writes what fits of `program`, and the 1 it adds at `one`, which wraps
+/// around to the last byte of smaller RAMs
+fn write_sample(ram: &mut [u8], program: &[u8], one: usize) {
+    let len = program.len().min(ram.len());
+    ram[..len].copy_from_slice(&program[..len]);
+    if !ram.is_empty() {
+        ram[one & (ram.len() - 1)] = 1;
+    }
 }
@@ -0,0 +1,272 @@
+// Per-cycle trace of the machine state
+use crate::disassembler::disassemble_register;
+use crate::isa::Encoding;
+use crate::modules::{ControlFlag, ControlNames, ControlWord};
+use crate::state::Modules;
+use atty::Stream;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// Destination of the trace
+pub trait TraceSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()>;
+
+    /// Whether the output can be colored with ANSI escape codes
+    fn is_terminal(&self) -> bool {
+        false
+    }
+}
+
+pub struct StdoutSink;
+
+impl TraceSink for StdoutSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", line)
+    }
+
+    fn is_terminal(&self) -> bool {
+        atty::is(Stream::Stdout)
+    }
+}
+
+pub struct FileSink(BufWriter<File>);
+
+impl FileSink {
+    pub fn create<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        Ok(FileSink(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl TraceSink for FileSink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.0, "{}", line)
+    }
+}
+
+/// Keeps the trace in memory. Clones share the same buffer, so a clone can be
+/// kept to read the trace back after handing the sink to a `Tracer`.
+#[derive(Clone, Default)]
+pub struct MemorySink(Rc<RefCell<Vec<String>>>);
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.0.borrow().clone()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl TraceSink for MemorySink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.0.borrow_mut().push(line.to_string());
+        Ok(())
+    }
+}
+
+pub struct NullSink;
+
+impl TraceSink for NullSink {
+    fn write_line(&mut self, _line: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    None,
+    /// One line per cycle
+    Compact,
+    /// Every module's contents, one per line
+    Full,
+    /// One JSON object per line, for external tools
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "none" => Ok(TraceFormat::None),
+            "compact" => Ok(TraceFormat::Compact),
+            "full" => Ok(TraceFormat::Full),
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("unknown trace format {}", s)),
+        }
+    }
+}
+
+/// State of the machine for one clock cycle, once the control word has been
+/// decoded and the bus has settled
+pub struct CycleTrace<'a> {
+    pub cycle: u64,
+    pub step: usize,
+    pub instruction: u8,
+    /// How the instruction is laid out in memory
+    pub encoding: Encoding,
+    pub cw: ControlWord,
+    /// Names of the control bits
+    pub names: &'a ControlNames,
+    pub bus: u8,
+    pub modules: &'a Modules,
+}
+
+pub struct Tracer {
+    format: TraceFormat,
+    sink: Box<dyn TraceSink>,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer::new(TraceFormat::Full, StdoutSink)
+    }
+}
+
+impl Tracer {
+    pub fn new<S: TraceSink + 'static>(format: TraceFormat, sink: S) -> Self {
+        Tracer {
+            format,
+            sink: Box::new(sink),
+        }
+    }
+
+    pub fn none() -> Self {
+        Tracer::new(TraceFormat::None, NullSink)
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    pub fn trace(&mut self, cycle: &CycleTrace) {
+        let result = match self.format {
+            TraceFormat::None => Ok(()),
+            TraceFormat::Compact => self.compact(cycle),
+            TraceFormat::Full => self.full(cycle),
+            TraceFormat::Json => self.json(cycle),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not write trace, disabling it: {}", e);
+            self.format = TraceFormat::None;
+        }
+    }
+
+    fn compact(&mut self, cycle: &CycleTrace) -> io::Result<()> {
+        self.sink.write_line(&format!(
+            "{:>6} T{} {:<8} bus {:08b}  {}",
+            cycle.cycle,
+            cycle.step,
+            disassemble_register(cycle.instruction, cycle.encoding),
+            cycle.bus,
+            cycle.cw.named(cycle.names)
+        ))
+    }
+
+    fn json(&mut self, cycle: &CycleTrace) -> io::Result<()> {
+        let flags: Vec<String> = cycle
+            .names
+            .set_names(cycle.cw)
+            .iter()
+            .map(|name| json_string(name))
+            .collect();
+        let modules: Vec<String> = cycle
+            .modules
+            .iter()
+            .filter(|m| !m.get_name().is_empty())
+            .map(|m| {
+                let value = m.value().map_or("null".to_string(), |v| v.to_string());
+                format!("{}:{}", json_string(m.get_name()), value)
+            })
+            .collect();
+        // Memory latches the bus on the rising edge, unless the clock is halted
+        let memory_write = cycle
+            .modules
+            .iter()
+            .filter(|m| m.memory().is_some() && cycle.cw.has(m.bus_read_flag()))
+            .filter(|_| !cycle.cw.has(ControlFlag::Hlt))
+            .filter_map(|m| {
+                let address = m.address()?;
+                Some(format!(
+                    r#"{{"module":{},"address":{},"value":{}}}"#,
+                    json_string(m.get_name()),
+                    address,
+                    cycle.bus
+                ))
+            })
+            .next()
+            .unwrap_or_else(|| "null".to_string());
+        self.sink.write_line(&format!(
+            concat!(
+                r#"{{"cycle":{},"step":{},"instruction":{},"#,
+                r#""control_word":{{"raw":{},"flags":[{}]}},"bus":{},"#,
+                r#""modules":{{{}}},"memory_write":{}}}"#
+            ),
+            cycle.cycle,
+            cycle.step,
+            cycle.instruction,
+            cycle.cw.0,
+            flags.join(","),
+            cycle.bus,
+            modules.join(","),
+            memory_write
+        ))
+    }
+
+    fn full(&mut self, cycle: &CycleTrace) -> io::Result<()> {
+        let mut output: Vec<(String, String)> = cycle
+            .modules
+            .iter()
+            .filter(|m| !m.get_name().is_empty())
+            .map(|m| (m.get_name().to_string(), m.to_string()))
+            .collect();
+        output.push((
+            "Instruction".to_string(),
+            disassemble_register(cycle.instruction, cycle.encoding),
+        ));
+        output.push((
+            "Control word".to_string(),
+            cycle.cw.named(cycle.names).to_string(),
+        ));
+        output.push(("Bus".to_string(), format!("{:08b}", cycle.bus)));
+        let longest = output.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        let color = self.sink.is_terminal();
+        for (name, contents) in output.iter() {
+            let line = if color {
+                format!(
+                    "\x1b[1;32m{:>width$}\x1b[0m {}",
+                    name,
+                    contents,
+                    width = longest
+                )
+            } else {
+                format!("{:>width$} {}", name, contents, width = longest)
+            };
+            self.sink.write_line(&line)?;
+        }
+        self.sink.write_line("")
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
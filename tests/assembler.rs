@@ -0,0 +1,141 @@
+use breadboard_8bit::assembler::{
+    assemble, assemble_image, assemble_with, AssemblyError, ErrorKind,
+};
+use breadboard_8bit::disassembler::{disassemble, ByteKind};
+use breadboard_8bit::isa::{self, Encoding, INSTRUCTION_SET};
+use breadboard_8bit::modules::{BranchingInstructionDecoder, ControlFlag, InstructionDecoder};
+use breadboard_8bit::shareable::{Share, Shareable};
+
+fn error(source: &str) -> AssemblyError {
+    assemble(source).unwrap_err()
+}
+
+#[test]
+fn sample_program_matches_the_hand_encoded_one() {
+    let source = std::fs::read_to_string("assets/programs/sample.asm").unwrap();
+    let mut expected = [0; 16];
+    breadboard_8bit::state::write_sample_program(&mut expected);
+    assert_eq!(assemble(&source).unwrap(), expected);
+}
+
+#[test]
+fn labels_can_be_used_before_they_are_defined() {
+    let ram = assemble("JMP end\nOUT\nend: HLT\n").unwrap();
+    assert_eq!(ram[..3], [0x62, 0xe0, 0xf0]);
+}
+
+#[test]
+fn numbers_can_be_decimal_hex_or_binary() {
+    let ram = assemble("LDI 10\nLDI 0xa\nLDI 0b1010\n.byte -1, 0xff\n").unwrap();
+    assert_eq!(ram[..5], [0x5a, 0x5a, 0x5a, 0xff, 0xff]);
+}
+
+#[test]
+fn org_moves_the_location_counter() {
+    let ram = assemble(".org 14\nx: .byte 14\n.org 0\nLDA x\n").unwrap();
+    assert_eq!(ram[0], 0x1e);
+    assert_eq!(ram[14], 14);
+}
+
+#[test]
+fn comments_and_blank_lines_are_ignored() {
+    let ram = assemble("; nothing\n\n   OUT ; show A\n").unwrap();
+    assert_eq!(ram[0], 0xe0);
+}
+
+#[test]
+fn errors_give_their_line() {
+    let e = error("OUT\n\nFOO 1\n");
+    assert_eq!(e.line, 3);
+    assert_eq!(e.kind, ErrorKind::UnknownMnemonic("FOO".to_string()));
+    assert_eq!(e.to_string(), "line 3: unknown mnemonic `FOO`");
+}
+
+#[test]
+fn invalid_programs_are_refused() {
+    let cases = [
+        (
+            "LDA 16",
+            ErrorKind::OperandOutOfRange {
+                value: 16,
+                min: 0,
+                max: 15,
+            },
+        ),
+        ("LDA", ErrorKind::MissingOperand("LDA")),
+        ("OUT 1", ErrorKind::UnexpectedOperand("OUT")),
+        (
+            "JMP nowhere",
+            ErrorKind::UndefinedLabel("nowhere".to_string()),
+        ),
+        ("a: OUT\na: OUT", ErrorKind::DuplicateLabel("a".to_string())),
+        ("1a: OUT", ErrorKind::InvalidLabel("1a".to_string())),
+        (".word 1", ErrorKind::UnknownDirective(".word".to_string())),
+        ("LDI 0x1g", ErrorKind::InvalidNumber("0x1g".to_string())),
+        (
+            ".byte 256",
+            ErrorKind::OperandOutOfRange {
+                value: 256,
+                min: -128,
+                max: 255,
+            },
+        ),
+        (
+            ".org 15\n.byte 1, 2",
+            ErrorKind::Overflow {
+                address: 16,
+                size: 16,
+            },
+        ),
+        ("OUT\n.org 0\nHLT", ErrorKind::Overlap(0)),
+    ];
+    for (source, kind) in cases.iter() {
+        assert_eq!(&error(source).kind, kind, "{}", source);
+    }
+}
+
+#[test]
+fn extended_instructions_take_their_operand_from_the_next_byte() {
+    let ram = assemble_with(
+        "LDA x\nOUT\nHLT\n.org 200\nx: .byte 42\n",
+        Encoding::Extended,
+    )
+    .unwrap();
+    assert_eq!(ram.len(), 256);
+    assert_eq!(ram[..4], [0x10, 200, 0xe0, 0xf0]);
+    assert_eq!(ram[200], 42);
+    assert!(assemble_with("LDA 256", Encoding::Extended).is_err());
+}
+
+#[test]
+fn images_report_where_the_written_bytes_end() {
+    let (image, end) = assemble_image("HLT\n.org 20\n.byte 0\n", Encoding::Extended).unwrap();
+    assert_eq!((image.len(), end), (256, 21));
+    assert_eq!(assemble_image("", Encoding::Compact).unwrap().1, 0);
+}
+
+#[test]
+fn opcodes_match_the_decoder() {
+    // Every instruction does something past the fetch cycle on the decoder
+    for instruction in INSTRUCTION_SET.iter().filter(|i| i.opcode != isa::NOP) {
+        let register = Shareable::new(instruction.encode(0));
+        let mut decoder =
+            BranchingInstructionDecoder::new(register.share(), Shareable::new(0b11).share());
+        decoder.set_counter(2);
+        assert_ne!(decoder.decode().0, 0, "{}", instruction.mnemonic);
+    }
+    let register = Shareable::new(isa::by_mnemonic("HLT").unwrap().encode(0));
+    let mut decoder = BranchingInstructionDecoder::new(register.share(), Shareable::new(0).share());
+    decoder.set_counter(2);
+    assert!(decoder.decode().has(ControlFlag::Hlt));
+}
+
+#[test]
+fn disassembly_marks_data() {
+    let ram = assemble("LDA 14\nADD 15\nOUT\nHLT\n.org 14\n.byte 14\n.byte 28\n").unwrap();
+    let bytes = disassemble(&ram);
+    assert_eq!(bytes[0].kind, ByteKind::Code);
+    assert_eq!(bytes[4].kind, ByteKind::Unreachable);
+    assert_eq!(bytes[14].kind, ByteKind::Data);
+    assert_eq!(bytes[0].to_string(), "0x00: 1e  LDA 14");
+}
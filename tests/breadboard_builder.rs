@@ -0,0 +1,516 @@
+use breadboard_8bit::assembler::assemble_with;
+use breadboard_8bit::breadboard_builder::{
+    BreadboardBuilder, DecoderSpec, MachineDescription, ModuleSpec,
+};
+use breadboard_8bit::isa::Encoding;
+use breadboard_8bit::modules::{
+    BranchingInstructionDecoder, ControlFlag, ExtendedInstructionDecoder, InstructionDecoder, Ram,
+    Rom, SimpleInstructionDecoder,
+};
+use breadboard_8bit::shareable::{Share, Shareable};
+use breadboard_8bit::state::{BreadboardState, Warning};
+use breadboard_8bit::trace::Tracer;
+
+mod common;
+
+use common::{machine, program, ADD};
+
+const MULTIPLY: &str = "LDA p\nADD x\nSTA p\nLDA y\nSUB one\nSTA y\nJZ done\nJMP 0\n\
+                        done: LDA p\nOUT\nHLT\n\
+                        .org 200\nx: .byte 7\ny: .byte 6\none: .byte 1\np: .byte 0\n";
+
+fn extended_program(source: &str) -> impl FnOnce(&mut [u8]) {
+    let image = assemble_with(source, Encoding::Extended).unwrap();
+    move |ram: &mut [u8]| ram.copy_from_slice(&image)
+}
+
+fn extended() -> BreadboardState<ExtendedInstructionDecoder> {
+    BreadboardBuilder::extended()
+        .build(extended_program(MULTIPLY), |instruction, flags| {
+            Ok(ExtendedInstructionDecoder::new(instruction, flags))
+        })
+        .unwrap()
+        .with_tracer(Tracer::none())
+}
+
+fn sap1() -> BreadboardState {
+    machine(ADD, BranchingInstructionDecoder::new)
+}
+
+fn names<I: InstructionDecoder>(state: &BreadboardState<I>) -> Vec<String> {
+    state
+        .modules()
+        .iter()
+        .map(|m| m.get_name().to_string())
+        .collect()
+}
+
+fn output<I: InstructionDecoder>(state: &BreadboardState<I>) -> Option<u8> {
+    state.module("Output").and_then(|m| m.value())
+}
+
+#[test]
+fn sap1_has_the_modules_of_the_breadboard_build() {
+    let expected = BreadboardState::default_with_ram(|_| {});
+    assert_eq!(names(&sap1()), names(&expected));
+    assert_eq!(
+        names(&sap1()),
+        [
+            "Program Counter",
+            "Memory Address",
+            "Memory Contents",
+            "Instruction Register",
+            "Decoder Step",
+            "A Register",
+            "Sum Register",
+            "Flags",
+            "B Register",
+            "Output",
+        ]
+    );
+}
+
+#[test]
+fn sap1_runs_a_program() {
+    let mut state = sap1();
+    assert!(state.run_until_halt(100).halted);
+    assert_eq!(output(&state), Some(42));
+    assert_eq!(state.module("A Register").and_then(|m| m.value()), Some(42));
+    assert_eq!(state.module("B Register").and_then(|m| m.value()), Some(28));
+}
+
+#[test]
+fn sap1_wires_registers_to_their_flags() {
+    let state = sap1();
+    let a = state.module("A Register").unwrap();
+    assert_eq!(a.bus_read_flag(), ControlFlag::ARegisterIn);
+    assert_eq!(a.bus_write_flag(), ControlFlag::ARegisterOut);
+    let b = state.module("B Register").unwrap();
+    assert_eq!(b.bus_read_flag(), ControlFlag::BRegisterIn);
+    assert_eq!(b.bus_write_flag(), ControlFlag::Empty);
+    let address = state.module("Memory Address").unwrap();
+    assert_eq!(address.bus_read_flag(), ControlFlag::MemoryAddressIn);
+}
+
+#[test]
+fn ram_observes_the_address_register() {
+    let mut state = sap1();
+    // Fetching LDA 14 then loading its operand into the memory address
+    for _ in 0..3 {
+        state.step_cycle();
+    }
+    state.settle();
+    let ram = state.module("Memory Contents").unwrap();
+    assert_eq!(ram.address(), Some(14));
+    assert_eq!(ram.value(), Some(14));
+}
+
+#[test]
+fn decoder_is_selected_by_the_caller() {
+    // The simple decoder does not end instructions early, so the program
+    // takes a full 5 steps per instruction
+    let mut simple = BreadboardBuilder::sap1()
+        .build(program(ADD), |instruction, _| {
+            Ok(SimpleInstructionDecoder::new(instruction))
+        })
+        .unwrap()
+        .with_tracer(Tracer::none());
+    let mut branching = sap1();
+    let simple_cycles = simple.run_until_halt(100).cycles;
+    let branching_cycles = branching.run_until_halt(100).cycles;
+    assert_eq!(output(&simple), Some(42));
+    assert!(simple_cycles > branching_cycles);
+}
+
+#[test]
+fn decoder_errors_are_returned() {
+    let result = BreadboardBuilder::sap1().build(
+        |_| {},
+        |_, _| -> Result<BranchingInstructionDecoder, String> { Err("no decoder".to_string()) },
+    );
+    assert_eq!(result.err(), Some("no decoder".to_string()));
+}
+
+#[test]
+fn modules_can_be_added_in_any_order() {
+    // The ALU is declared before the registers it observes
+    let state = BreadboardBuilder::new()
+        .alu("A Register", "B Register")
+        .module(ModuleSpec::InstructionRegister)
+        .register(
+            "A Register",
+            ControlFlag::ARegisterIn,
+            Some(ControlFlag::ARegisterOut),
+        )
+        .register("B Register", ControlFlag::BRegisterIn, None)
+        .module(ModuleSpec::Empty)
+        .build(
+            |_| {},
+            |instruction, flags| Ok(BranchingInstructionDecoder::new(instruction, flags)),
+        )
+        .unwrap();
+    assert_eq!(
+        names(&state),
+        [
+            "Sum Register",
+            "Instruction Register",
+            "A Register",
+            "B Register",
+            ""
+        ]
+    );
+}
+
+#[test]
+fn missing_modules_are_reported() {
+    let error = BreadboardBuilder::new()
+        .module(ModuleSpec::InstructionRegister)
+        .ram("Memory Address")
+        .build(
+            |_| {},
+            |instruction, _| Ok(SimpleInstructionDecoder::new(instruction)),
+        )
+        .err()
+        .unwrap();
+    assert!(error.contains("Memory Address"), "{}", error);
+}
+
+#[test]
+fn decoder_inputs_must_exist() {
+    let error = BreadboardBuilder::sap1()
+        .decoder_inputs("Instruction Register", "Carry")
+        .check()
+        .unwrap_err();
+    assert!(error.contains("Carry"), "{}", error);
+}
+
+#[test]
+fn duplicate_names_are_refused() {
+    let error = BreadboardBuilder::sap1()
+        .register("A Register", ControlFlag::OutputRegisterIn, None)
+        .check()
+        .unwrap_err();
+    assert!(error.contains("A Register"), "{}", error);
+    // Saved states keep one snapshot per name
+    let error = BreadboardBuilder::sap1()
+        .module(ModuleSpec::Output)
+        .check()
+        .unwrap_err();
+    assert!(error.contains("Output"), "{}", error);
+    let error = BreadboardBuilder::sap1()
+        .ram("Memory Address")
+        .check()
+        .unwrap_err();
+    assert!(error.contains("Memory Contents"), "{}", error);
+}
+
+#[test]
+fn loops_are_refused() {
+    let result = BreadboardBuilder::new()
+        .module(ModuleSpec::InstructionRegister)
+        .alu("Sum Register", "Sum Register")
+        .build(
+            |_| {},
+            |instruction, flags| Ok(BranchingInstructionDecoder::new(instruction, flags)),
+        );
+    assert!(result.is_err());
+}
+
+#[test]
+fn toml_description_matches_sap1() {
+    let description = MachineDescription::load("assets/machines/sap1.toml").unwrap();
+    assert_eq!(description.decoder, DecoderSpec::Branching);
+    assert_eq!(description.builder(), BreadboardBuilder::sap1());
+    let mut state = description
+        .build(program(ADD))
+        .unwrap()
+        .with_tracer(Tracer::none());
+    assert!(state.run_until_halt(100).halted);
+    assert_eq!(output(&state), Some(42));
+}
+
+#[test]
+fn ron_description_adds_a_register() {
+    let description = MachineDescription::load("assets/machines/display.ron").unwrap();
+    let mut state = description
+        .build(program(ADD))
+        .unwrap()
+        .with_tracer(Tracer::none());
+    assert!(state.run_until_halt(100).halted);
+    assert_eq!(state.module("Display").and_then(|m| m.value()), Some(42));
+}
+
+#[test]
+fn invalid_descriptions_are_refused() {
+    let unknown_flag = "[[module]]\nkind = \"register\"\nname = \"C\"\nread = \"CI\"\n";
+    assert!(MachineDescription::from_toml(unknown_flag)
+        .unwrap_err()
+        .contains("CI"));
+    let unknown_kind = "[[module]]\nkind = \"stack\"\n";
+    assert!(MachineDescription::from_toml(unknown_kind).is_err());
+    let unknown_field =
+        "[[module]]\nkind = \"register\"\nname = \"C\"\nread = \"AI\"\ncolor = \"red\"\n";
+    assert!(MachineDescription::from_toml(unknown_field).is_err());
+}
+
+#[test]
+fn extended_machine_addresses_all_of_ram() {
+    let mut state = extended();
+    assert_eq!(state.encoding(), Encoding::Extended);
+    assert!(state.run_until_halt(1000).halted);
+    assert_eq!(output(&state), Some(42));
+    let ram = state.module("Memory Contents").unwrap();
+    assert_eq!(ram.memory().map(|m| m.len()), Some(256));
+    assert_eq!(ram.memory().map(|m| (m[201], m[203])), Some((0, 42)));
+}
+
+#[test]
+fn extended_program_counter_counts_past_15() {
+    let mut state = extended();
+    state.run_until_halt(1000);
+    // HLT is the last instruction, at 0x13
+    assert_eq!(
+        state.module("Program Counter").and_then(|m| m.value()),
+        Some(0x14)
+    );
+}
+
+#[test]
+fn extended_description_matches_the_builder() {
+    let description = MachineDescription::load("assets/machines/extended.toml").unwrap();
+    assert_eq!(description.decoder, DecoderSpec::Extended);
+    assert_eq!(description.encoding, Encoding::Extended);
+    assert_eq!(description.builder(), BreadboardBuilder::extended());
+    let mut state = description
+        .build(extended_program(MULTIPLY))
+        .unwrap()
+        .with_tracer(Tracer::none());
+    assert!(state.run_until_halt(1000).halted);
+    assert_eq!(output(&state), Some(42));
+}
+
+#[test]
+fn program_counter_width_is_checked() {
+    let error = BreadboardBuilder::new()
+        .program_counter(9)
+        .check()
+        .unwrap_err();
+    assert!(error.contains("9 bits"), "{}", error);
+}
+
+#[test]
+fn ram_size_is_checked() {
+    let error = BreadboardBuilder::new()
+        .register("Memory Address", ControlFlag::MemoryAddressIn, None)
+        .ram_with_size("Memory Address", 100)
+        .check()
+        .unwrap_err();
+    assert!(error.contains("100"), "{}", error);
+    let error = Ram::with_size(Shareable::new(0).share(), 100).unwrap_err();
+    assert!(error.contains("100"), "{}", error);
+    let description = "[[module]]\nkind = \"ram\"\naddress = \"A\"\nsize = 64\n";
+    let description = MachineDescription::from_toml(description).unwrap();
+    assert_eq!(
+        description.modules,
+        [ModuleSpec::Ram {
+            address: "A".to_string(),
+            size: 64
+        }]
+    );
+}
+
+#[test]
+fn ram_ignores_unwired_address_lines() {
+    // The extended machine with only 16 bytes of RAM, where STA 0xf3 writes
+    // to address 3
+    let mut description = MachineDescription::load("assets/machines/extended.toml").unwrap();
+    for module in description.modules.iter_mut() {
+        if let ModuleSpec::Ram { size, .. } = module {
+            *size = 16;
+        }
+    }
+    let image = assemble_with("LDI 42\nSTA 0xf3\nHLT\n", Encoding::Extended).unwrap();
+    let mut state = description
+        .build(|ram: &mut [u8]| ram.copy_from_slice(&image[..16]))
+        .unwrap()
+        .with_tracer(Tracer::none());
+    assert!(state.run_until_halt(100).halted);
+    let ram = state.module("Memory Contents").unwrap();
+    assert_eq!(ram.memory().map(|m| m.len()), Some(16));
+    assert_eq!(ram.memory().map(|m| m[3]), Some(42));
+}
+
+#[test]
+fn rom_answers_to_the_mirrors_of_its_addresses() {
+    // 16 bytes of RAM with a ROM over the first 12, both ignoring the high
+    // address lines
+    let mut description = MachineDescription::load("assets/machines/extended.toml").unwrap();
+    for module in description.modules.iter_mut() {
+        if let ModuleSpec::Ram { size, .. } = module {
+            *size = 16;
+        }
+    }
+    let source = "LDI 42\nSTA 0x13\nSTA 0x1d\nLDA 13\nOUT\nHLT\n";
+    let rom = assemble_with(source, Encoding::Extended).unwrap();
+    let mut state = description
+        .builder()
+        .rom("Memory Address", 0, rom[..12].to_vec())
+        .build(
+            |_| (),
+            |instruction, flags| Ok(ExtendedInstructionDecoder::new(instruction, flags)),
+        )
+        .unwrap()
+        .with_tracer(Tracer::none());
+    assert!(state.run_until_halt(100).halted);
+    assert_eq!(output(&state), Some(42));
+    // 0x13 is 0x03 in the ROM, 0x1d is 0x0d in RAM
+    let rejected = Warning::RejectedWrite {
+        module: "Memory Contents".to_string(),
+        address: 0x13,
+        value: 42,
+    };
+    assert_eq!(state.take_warnings(), [(8, rejected)]);
+    let ram = state.module("Memory Contents").unwrap();
+    assert_eq!(ram.memory().map(|m| (m[3], m[13])), Some((0, 42)));
+    assert_eq!(ram.read_address(0x13), None);
+    assert_eq!(ram.read_address(0x1d), Some(42));
+    let rom = state.module("ROM@0x00").unwrap();
+    assert_eq!(rom.read_address(0x13), Some(0x13));
+    assert_eq!(rom.read_address(0x1d), None);
+}
+
+#[test]
+fn rom_sizes_are_checked() {
+    let rom = || Rom::new("ROM", Shareable::new(0).share(), 0, vec![1, 2]);
+    for &size in [0, 12, 512].iter() {
+        let error = rom().with_size(size).unwrap_err();
+        assert!(error.contains(&size.to_string()), "{}", error);
+    }
+    assert!(rom().with_size(16).is_ok());
+}
+
+fn rom_machine(rom: &str, ram: &str) -> BreadboardState<ExtendedInstructionDecoder> {
+    let rom = assemble_with(rom, Encoding::Extended).unwrap();
+    let ram = extended_program(ram);
+    BreadboardBuilder::extended()
+        .rom("Memory Address", 0, rom[..0x80].to_vec())
+        .build(ram, |instruction, flags| {
+            Ok(ExtendedInstructionDecoder::new(instruction, flags))
+        })
+        .unwrap()
+        .with_tracer(Tracer::none())
+}
+
+#[test]
+fn rom_runs_its_program_with_variables_in_ram() {
+    let mut state = rom_machine("LDI 42\nSTA 0x80\nLDA 0x80\nOUT\nHLT\n", "");
+    assert!(state.run_until_halt(100).halted);
+    assert_eq!(output(&state), Some(42));
+    let ram = state.module("Memory Contents").unwrap();
+    assert_eq!(ram.memory().map(|m| m[0x80]), Some(42));
+}
+
+#[test]
+fn rom_ignores_writes() {
+    // RAM holds a different program behind the ROM, which is never run
+    let mut state = rom_machine("LDI 42\nSTA 5\nLDA 5\nOUT\nHLT\n", "OUT\nHLT\n");
+    assert!(state.run_until_halt(100).halted);
+    // LDA 5 reads the operand of LDA from the ROM
+    assert_eq!(output(&state), Some(5));
+    let rom = state.module("ROM@0x00").unwrap();
+    assert_eq!(rom.memory().map(|m| m[5]), Some(5));
+    let ram = state.module("Memory Contents").unwrap();
+    assert_eq!(ram.memory().map(|m| m[5]), Some(0));
+    let rejected = Warning::RejectedWrite {
+        module: "Memory Contents".to_string(),
+        address: 5,
+        value: 42,
+    };
+    assert_eq!(state.take_warnings(), [(8, rejected)]);
+}
+
+#[test]
+fn rom_regions_are_checked() {
+    let error = BreadboardBuilder::extended()
+        .rom("Memory Address", 0, vec![0; 16])
+        .rom("Memory Address", 8, vec![0; 16])
+        .check()
+        .unwrap_err();
+    assert!(error.contains("0x08"), "{}", error);
+    let error = BreadboardBuilder::extended()
+        .rom("Memory Address", 0xf0, vec![0; 32])
+        .check()
+        .unwrap_err();
+    assert!(error.contains("past the end"), "{}", error);
+    let error = BreadboardBuilder::sap1()
+        .rom("Memory Address", 0x10, vec![0; 16])
+        .check()
+        .unwrap_err();
+    assert!(error.contains("(16 bytes)"), "{}", error);
+    assert!(BreadboardBuilder::sap1()
+        .rom("Memory Address", 8, vec![0; 8])
+        .check()
+        .is_ok());
+    let error = BreadboardBuilder::extended()
+        .rom("Memory Address", 0, Vec::new())
+        .check()
+        .unwrap_err();
+    assert!(error.contains("empty"), "{}", error);
+}
+
+#[test]
+fn roms_are_named_after_their_start() {
+    let rom = |name: Option<&str>, address: &str, start| ModuleSpec::Rom {
+        name: name.map(str::to_string),
+        address: address.to_string(),
+        start,
+        size: None,
+        program: None,
+        contents: vec![0; 16],
+    };
+    let state = BreadboardBuilder::extended()
+        .module(rom(None, "Memory Address", 0))
+        .module(rom(Some("Boot"), "Memory Address", 0x10))
+        .build(
+            |_| (),
+            |instruction, flags| Ok(ExtendedInstructionDecoder::new(instruction, flags)),
+        )
+        .unwrap();
+    assert!(state.module("ROM@0x00").is_some());
+    assert!(state.module("Boot").is_some());
+
+    let error = BreadboardBuilder::new()
+        .register("Memory Address", ControlFlag::MemoryAddressIn, None)
+        .register("Other Address", ControlFlag::BRegisterIn, None)
+        .module(rom(None, "Memory Address", 0))
+        .module(rom(None, "Other Address", 0))
+        .check()
+        .unwrap_err();
+    assert!(
+        error.contains("two modules are named ROM@0x00"),
+        "{}",
+        error
+    );
+    let error = BreadboardBuilder::extended()
+        .module(rom(Some("A Register"), "Memory Address", 0))
+        .check()
+        .unwrap_err();
+    assert!(
+        error.contains("two modules are named A Register"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn rom_description_loads_its_program() {
+    let description = MachineDescription::load("assets/machines/rom.toml").unwrap();
+    let mut state = description
+        .build(|_: &mut [u8]| ())
+        .unwrap()
+        .with_tracer(Tracer::none());
+    assert!(state.run_until_halt(1000).halted);
+    assert_eq!(output(&state), Some(42));
+    let rom = state.module("ROM@0x00").unwrap();
+    assert_eq!(rom.memory().map(|m| m.len()), Some(0x80));
+}
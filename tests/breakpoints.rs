@@ -0,0 +1,169 @@
+use breadboard_8bit::breadboard_builder::MachineDescription;
+use breadboard_8bit::breakpoints::{Breakpoint, Breakpoints};
+use breadboard_8bit::modules::{
+    BranchingInstructionDecoder, ControlFlag, ExtendedInstructionDecoder,
+};
+use breadboard_8bit::state::BreadboardState;
+use breadboard_8bit::trace::Tracer;
+use std::process::Command;
+
+mod common;
+
+use common::ADD;
+
+fn machine(source: &str, breakpoint: &str) -> BreadboardState {
+    common::machine(source, BranchingInstructionDecoder::new)
+        .with_breakpoints(Breakpoints::new(vec![breakpoint.parse().unwrap()]))
+}
+
+#[test]
+fn parses_every_kind() {
+    let cases = [
+        ("pc=0x4", Breakpoint::Address(4)),
+        ("pc=255", Breakpoint::Address(255)),
+        ("op=add", Breakpoint::Opcode(2)),
+        ("op=0xe", Breakpoint::Opcode(0xe)),
+        ("flag=RI", Breakpoint::Flag(ControlFlag::RamIn)),
+        ("ram=15", Breakpoint::Memory(15)),
+        (
+            "watch=A Register",
+            Breakpoint::Watch("A Register".to_string()),
+        ),
+    ];
+    for (text, breakpoint) in cases.iter() {
+        let parsed: Breakpoint = text.parse().unwrap();
+        assert_eq!(&parsed, breakpoint, "{}", text);
+        assert_eq!(parsed.to_string().parse(), Ok(parsed), "{}", text);
+    }
+}
+
+#[test]
+fn refuses_invalid_breakpoints() {
+    let cases = [
+        ("pc=0x100", "does not fit"),
+        ("op=0x1e", "wider than 4 bits"),
+        ("op=16", "wider than 4 bits"),
+        ("op=FOO", "invalid number"),
+        ("pc=x", "invalid number"),
+        ("pc", "expected KIND=VALUE"),
+        ("sp=1", "unknown breakpoint kind"),
+    ];
+    for (text, message) in cases.iter() {
+        let error = text.parse::<Breakpoint>().unwrap_err();
+        assert!(error.contains(message), "{}: {}", text, error);
+    }
+    assert!("flag=XX".parse::<Breakpoint>().is_err());
+}
+
+#[test]
+fn stops_when_an_instruction_is_fetched_from_the_address() {
+    let mut state = machine(ADD, "pc=2");
+    let summary = state.run_until_halt(100);
+    assert!(summary.breakpoint);
+    assert!(!summary.halted);
+    // LDA and ADD take 9 cycles
+    assert_eq!(summary.cycles, 9);
+    let hit = state.breakpoint_hit().unwrap();
+    assert_eq!(hit.breakpoint, Breakpoint::Address(2));
+    assert_eq!(hit.module.as_deref(), Some("Program Counter"));
+    // The run can be resumed
+    assert!(state.run_until_halt(100).halted);
+}
+
+#[test]
+fn stops_when_the_watched_ram_cell_changes() {
+    let mut state = machine("LDI 5\nSTA 15\nSTA 15\nLDI 7\nSTA 15\nHLT\n", "ram=15");
+    // LDI then STA, whose write shows on the next cycle
+    assert_eq!(state.run_until_halt(100).cycles, 7);
+    let hit = state.breakpoint_hit().unwrap();
+    assert_eq!(hit.module.as_deref(), Some("Memory Contents"));
+    assert_eq!(hit.reason, "RAM at 0xf changed to 5");
+    // Writing the same value again is not a change
+    assert_eq!(state.run_until_halt(100).cycles, 11);
+    assert_eq!(
+        state.breakpoint_hit().unwrap().reason,
+        "RAM at 0xf changed to 7"
+    );
+    assert!(state.run_until_halt(100).halted);
+}
+
+#[test]
+fn watched_addresses_are_resolved_through_the_memory_map() {
+    let description = MachineDescription::load("assets/machines/rom.toml").unwrap();
+    let machine = |breakpoint: &str| {
+        description
+            .load_roms()
+            .unwrap()
+            .build(
+                |_| (),
+                |instruction, flags| Ok(ExtendedInstructionDecoder::new(instruction, flags)),
+            )
+            .unwrap()
+            .with_tracer(Tracer::none())
+            .with_breakpoints(Breakpoints::new(vec![breakpoint.parse().unwrap()]))
+    };
+    // The product is in RAM, past the ROM
+    let mut state = machine("ram=0x81");
+    assert!(state.run_until_halt(1000).breakpoint);
+    let hit = state.breakpoint_hit().unwrap();
+    assert_eq!(hit.module.as_deref(), Some("Memory Contents"));
+    assert_eq!(hit.reason, "RAM at 0x81 changed to 7");
+    // RAM behind the ROM is never read, and the ROM never changes
+    let mut state = machine("ram=0x70");
+    assert!(state.run_until_halt(1000).halted);
+}
+
+#[test]
+fn stops_when_the_watched_module_changes() {
+    let mut state = machine(ADD, "watch=Output");
+    let summary = state.run_until_halt(100);
+    assert!(summary.breakpoint);
+    let hit = state.breakpoint_hit().unwrap();
+    assert_eq!(hit.module.as_deref(), Some("Output"));
+    assert_eq!(hit.reason, "Output changed to 42");
+}
+
+#[test]
+fn resetting_forgets_the_watched_values() {
+    let mut state = machine(ADD, "watch=Output");
+    let first = state.run_until_halt(100).cycles;
+    assert!(state.run_until_halt(100).halted);
+    // Output going back to 0 is not a change made by the program
+    state.reset();
+    state.settle();
+    assert!(state.breakpoint_hit().is_none());
+    assert_eq!(state.run_until_halt(100).cycles, first);
+    assert_eq!(
+        state.breakpoint_hit().unwrap().reason,
+        "Output changed to 42"
+    );
+}
+
+#[test]
+fn headless_runs_exit_with_3_on_a_breakpoint() {
+    let output = Command::new(env!("CARGO_BIN_EXE_breadboard_8bit"))
+        .args([
+            "--headless",
+            "--trace",
+            "none",
+            "--program",
+            "assets/programs/add.asm",
+            "--break",
+            "op=OUT",
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("Breakpoint op=OUT: executing 0xe0 after 11 cycles\n"),
+        "{}",
+        stdout
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_breadboard_8bit"))
+        .args(["--headless", "--break", "pc=0x100"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+}
@@ -0,0 +1,33 @@
+// Programs and machines shared by the integration tests, each of which uses
+// only some of them
+#![allow(dead_code)]
+
+use breadboard_8bit::assembler::assemble;
+use breadboard_8bit::breadboard_builder::BreadboardBuilder;
+use breadboard_8bit::modules::InstructionDecoder;
+use breadboard_8bit::shareable::{Share, Shared};
+use breadboard_8bit::state::BreadboardState;
+use breadboard_8bit::trace::Tracer;
+
+/// Outputs 14 + 28, then halts after 15 cycles
+pub const ADD: &str = "LDA 14\nADD 15\nOUT\nHLT\n.org 14\n.byte 14, 28\n";
+
+/// Fills the RAM of the breadboard build with a program
+pub fn program(source: &str) -> impl FnOnce(&mut [u8]) {
+    let image = assemble(source).unwrap();
+    move |ram: &mut [u8]| ram.copy_from_slice(&image)
+}
+
+/// The breadboard build running `source` with `decoder`, without tracing
+pub fn machine<D, F>(source: &str, decoder: F) -> BreadboardState<D>
+where
+    D: InstructionDecoder + Share<u8>,
+    F: FnOnce(Shared<u8>, Shared<u8>) -> D,
+{
+    BreadboardBuilder::sap1()
+        .build(program(source), |instruction, flags| {
+            Ok(decoder(instruction, flags))
+        })
+        .unwrap()
+        .with_tracer(Tracer::none())
+}
@@ -0,0 +1,118 @@
+use breadboard_8bit::decoder_diff::{
+    compare, lockstep, Difference, LockstepOutcome, Mismatch, Probe,
+};
+use breadboard_8bit::isa;
+use breadboard_8bit::microcode_compiler::compile;
+use breadboard_8bit::modules::{
+    BranchingInstructionDecoder, ControlFlag, MicrocodeDecoder, SimpleInstructionDecoder,
+};
+use breadboard_8bit::shareable::Shared;
+
+mod common;
+
+use common::{machine, ADD};
+
+fn microcode(instruction: Shared<u8>, flags: Shared<u8>) -> MicrocodeDecoder {
+    let source = std::fs::read_to_string("assets/microcode/default.mc").unwrap();
+    let compiled = compile(&source).unwrap();
+    MicrocodeDecoder::with_microcode(instruction, flags, compiled.microcode)
+        .with_steps(compiled.steps)
+        .unwrap()
+}
+
+#[test]
+fn the_simple_decoder_never_takes_conditional_jumps() {
+    let mut simple =
+        Probe::new(|instruction, _| Ok(SimpleInstructionDecoder::new(instruction))).unwrap();
+    let mut branching =
+        Probe::new(|instruction, flags| Ok(BranchingInstructionDecoder::new(instruction, flags)))
+            .unwrap();
+    let jump = ControlFlag::InstructionRegisterOut as u32
+        | ControlFlag::Jump as u32
+        | ControlFlag::NextInstruction as u32;
+    let flags = |set: u8| {
+        (0..16)
+            .filter(|flags| flags & set != 0)
+            .collect::<Vec<u8>>()
+    };
+    assert_eq!(
+        compare(&mut simple, &mut branching),
+        [
+            Mismatch {
+                instruction: isa::JC,
+                step: 2,
+                flags: flags(0b10),
+                left: 0,
+                right: jump,
+            },
+            Mismatch {
+                instruction: isa::JZ,
+                step: 2,
+                flags: flags(0b01),
+                left: 0,
+                right: jump,
+            },
+        ]
+    );
+    assert_eq!(
+        compare(&mut simple, &mut branching)[0].to_string(),
+        "JC step 2 (flags 0010, 0011, 0110, 0111, 1010, 1011, 1110, 1111): \
+         Empty / InstructionRegisterOut | Jump | NextInstruction"
+    );
+}
+
+#[test]
+fn the_default_description_matches_the_branching_decoder() {
+    let mut described = Probe::new(|instruction, flags| Ok(microcode(instruction, flags))).unwrap();
+    let mut branching =
+        Probe::new(|instruction, flags| Ok(BranchingInstructionDecoder::new(instruction, flags)))
+            .unwrap();
+    assert_eq!(compare(&mut described, &mut branching), []);
+    assert_eq!(compare(&mut branching, &mut described), []);
+}
+
+#[test]
+fn lockstep_runs_matching_decoders_until_they_halt() {
+    let mut described = machine(ADD, microcode);
+    let mut branching = machine(ADD, BranchingInstructionDecoder::new);
+    assert_eq!(
+        lockstep(&mut described, &mut branching, 100),
+        LockstepOutcome::Halted { cycles: 15 }
+    );
+    let mut described = machine(ADD, microcode);
+    let mut branching = machine(ADD, BranchingInstructionDecoder::new);
+    assert_eq!(
+        lockstep(&mut described, &mut branching, 5),
+        LockstepOutcome::CycleLimit { cycles: 5 }
+    );
+}
+
+#[test]
+fn lockstep_stops_at_the_first_diverging_cycle() {
+    // Only the branching decoder ends OUT on its last step
+    let mut simple = machine("OUT\nHLT\n", |instruction, _| {
+        SimpleInstructionDecoder::new(instruction)
+    });
+    let mut branching = machine("OUT\nHLT\n", BranchingInstructionDecoder::new);
+    let divergence = match lockstep(&mut simple, &mut branching, 100) {
+        LockstepOutcome::Diverged(divergence) => divergence,
+        outcome => panic!("{:?}", outcome),
+    };
+    let out = ControlFlag::ARegisterOut as u32 | ControlFlag::OutputRegisterIn as u32;
+    assert_eq!(divergence.cycle, 2);
+    assert_eq!((divergence.left, divergence.right), ((0xe0, 2), (0xe0, 2)));
+    assert_eq!(
+        divergence.differences,
+        [Difference::ControlWord {
+            left: out,
+            right: out | ControlFlag::NextInstruction as u32,
+        }]
+    );
+    assert_eq!(
+        divergence.to_string(),
+        "cycle 2, OUT T2: control word ARegisterOut | OutputRegisterIn / \
+         ARegisterOut | OutputRegisterIn | NextInstruction"
+    );
+    // Both machines are left where they diverged
+    assert_eq!((simple.cycles(), branching.cycles()), (2, 2));
+}
@@ -0,0 +1,102 @@
+use breadboard_8bit::assembler::{assemble, assemble_with};
+use breadboard_8bit::disassembler::{
+    disassemble, disassemble_instruction, disassemble_register, disassemble_with, ByteKind,
+};
+use breadboard_8bit::isa::{self, Encoding, Operand, INSTRUCTION_SET};
+
+#[test]
+fn every_instruction_disassembles_to_what_assembles_it() {
+    for instruction in INSTRUCTION_SET.iter() {
+        let byte = instruction.encode(5);
+        let text = disassemble_instruction(byte);
+        let expected = match instruction.operand {
+            Operand::None => instruction.mnemonic.to_string(),
+            _ => format!("{} 5", instruction.mnemonic),
+        };
+        assert_eq!(text, expected);
+        let operand = if instruction.operand == Operand::None {
+            0
+        } else {
+            5
+        };
+        assert_eq!(
+            assemble(&text).unwrap()[0],
+            instruction.encode(operand),
+            "{}",
+            text
+        );
+    }
+}
+
+#[test]
+fn unknown_opcodes_are_marked() {
+    for opcode in (0..16).filter(|&opcode| isa::by_opcode(opcode).is_none()) {
+        let byte = opcode << 4 | 0x3;
+        assert_eq!(
+            disassemble_instruction(byte),
+            format!("??? ({:#04x})", byte)
+        );
+    }
+    // The control flow cannot be followed past an unknown opcode
+    let bytes = disassemble(&[0x90, 0xe0, 0xf0]);
+    assert_eq!(bytes[0].kind, ByteKind::Code);
+    assert_eq!(bytes[0].to_string(), "0x00: 90  ??? (0x90)");
+    assert_eq!(bytes[1].kind, ByteKind::Unreachable);
+    assert_eq!(bytes[1].to_string(), "0x01: e0  OUT      ; unreachable");
+}
+
+#[test]
+fn conditional_jumps_follow_both_branches() {
+    let ram = assemble("JC end\nOUT\nJMP 0\n.org 10\nend: HLT\nOUT\n").unwrap();
+    let kinds: Vec<ByteKind> = disassemble(&ram).iter().map(|b| b.kind).collect();
+    assert_eq!(kinds[..3], [ByteKind::Code; 3]);
+    assert_eq!(kinds[3], ByteKind::Unreachable);
+    assert_eq!(kinds[10], ByteKind::Code);
+    // Nothing runs after HLT
+    assert_eq!(kinds[11], ByteKind::Unreachable);
+}
+
+#[test]
+fn data_is_shown_as_bytes() {
+    let ram = assemble("STA 15\nHLT\n.org 15\n.byte 200\n").unwrap();
+    let bytes = disassemble(&ram);
+    assert_eq!(bytes[15].kind, ByteKind::Data);
+    assert_eq!(bytes[15].to_string(), "0x0f: c8  .byte 200 ; data");
+}
+
+#[test]
+fn extended_instructions_take_the_next_byte() {
+    let source = "LDA x\nOUT\nJZ end\nLDI 7\nend: HLT\n.org 200\nx: .byte 42\n";
+    let ram = assemble_with(source, Encoding::Extended).unwrap();
+    let bytes = disassemble_with(&ram, Encoding::Extended);
+    let kinds: Vec<ByteKind> = bytes[..9].iter().map(|b| b.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            ByteKind::Code,
+            ByteKind::Operand,
+            ByteKind::Code,
+            ByteKind::Code,
+            ByteKind::Operand,
+            ByteKind::Code,
+            ByteKind::Operand,
+            ByteKind::Code,
+            ByteKind::Unreachable,
+        ]
+    );
+    assert_eq!(bytes[0].operand, Some(200));
+    assert_eq!(bytes[0].to_string(), "0x00: 10  LDA 200");
+    assert_eq!(bytes[1].to_string(), "0x01: c8           ; operand");
+    assert_eq!(bytes[3].to_string(), "0x03: 80  JZ 7");
+    assert_eq!(bytes[5].to_string(), "0x05: 50  LDI 7");
+    assert_eq!(bytes[200].kind, ByteKind::Data);
+    // Read as compact instructions, the operands are lost
+    assert_eq!(disassemble(&ram)[0].to_string(), "0x00: 10  LDA 0");
+}
+
+#[test]
+fn extended_registers_only_show_the_mnemonic() {
+    assert_eq!(disassemble_register(0x10, Encoding::Extended), "LDA");
+    assert_eq!(disassemble_register(0x1e, Encoding::Compact), "LDA 14");
+    assert_eq!(disassemble_register(0x90, Encoding::Extended), "??? (0x90)");
+}
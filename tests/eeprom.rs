@@ -0,0 +1,161 @@
+use breadboard_8bit::eeprom::{parse_active_low, ChipLayout};
+use breadboard_8bit::modules::{AddressLayout, ControlFlag, MICROCODE_SIZE};
+
+// A different word at every address, using all four bytes
+fn microcode() -> Vec<u32> {
+    (0..MICROCODE_SIZE as u32)
+        .map(|address| address.wrapping_mul(0x9e37_79b9) ^ address)
+        .collect()
+}
+
+fn chips(chips: usize) -> ChipLayout {
+    ChipLayout {
+        chips,
+        ..ChipLayout::default()
+    }
+}
+
+#[test]
+fn parses_active_low_lines() {
+    assert_eq!(parse_active_low("none"), Ok(0));
+    assert_eq!(
+        parse_active_low("HLT, MI"),
+        Ok(ControlFlag::Hlt as u32 | ControlFlag::MemoryAddressIn as u32)
+    );
+    let all = ControlFlag::ALL
+        .iter()
+        .fold(0, |mask, &flag| mask | flag as u32);
+    assert_eq!(parse_active_low("all"), Ok(all));
+    assert!(parse_active_low("HLT,XX").is_err());
+}
+
+#[test]
+fn each_chip_holds_one_byte() {
+    let microcode = microcode();
+    let images = chips(4).export(&microcode).unwrap();
+    assert_eq!(images.len(), 4);
+    for (chip, image) in images.iter().enumerate() {
+        assert_eq!(image.len(), MICROCODE_SIZE);
+        for (address, &byte) in image.iter().enumerate() {
+            assert_eq!(byte, (microcode[address] >> (8 * chip)) as u8);
+        }
+    }
+}
+
+#[test]
+fn round_trips_through_chips() {
+    let microcode = microcode();
+    let layout = chips(4);
+    let imported = layout.import(&layout.export(&microcode).unwrap()).unwrap();
+    assert_eq!(&imported[..], &microcode[..]);
+}
+
+#[test]
+fn fewer_chips_drop_the_high_bytes() {
+    let microcode = microcode();
+    let layout = chips(3);
+    let imported = layout.import(&layout.export(&microcode).unwrap()).unwrap();
+    let expected: Vec<u32> = microcode.iter().map(|word| word & 0xff_ffff).collect();
+    assert_eq!(&imported[..], &expected[..]);
+}
+
+#[test]
+fn active_low_lines_are_inverted_on_the_chips() {
+    let microcode = microcode();
+    let active_low = ControlFlag::Hlt as u32 | ControlFlag::Jump as u32;
+    let layout = ChipLayout {
+        chips: 4,
+        active_low,
+        ..ChipLayout::default()
+    };
+    let plain = chips(4).export(&microcode).unwrap();
+    let inverted = layout.export(&microcode).unwrap();
+    for (chip, (plain, inverted)) in plain.iter().zip(&inverted).enumerate() {
+        let mask = (active_low >> (8 * chip)) as u8;
+        for (&plain, &inverted) in plain.iter().zip(inverted) {
+            assert_eq!(inverted, plain ^ mask);
+        }
+    }
+    let imported = layout.import(&inverted).unwrap();
+    assert_eq!(&imported[..], &microcode[..]);
+}
+
+#[test]
+fn narrow_layouts_lose_what_does_not_fit() {
+    let microcode = microcode();
+    let layout = ChipLayout {
+        chips: 4,
+        layout: "flags:2,instruction:4,step:3".parse().unwrap(),
+        active_low: 0,
+    };
+    let images = layout.export(&microcode).unwrap();
+    assert!(images.iter().all(|image| image.len() == 512));
+    let imported = layout.import(&images).unwrap();
+    let default = AddressLayout::default();
+    for (address, (&word, &expected)) in imported.iter().zip(&microcode).enumerate() {
+        let fields = default.unpack(address);
+        if fields.flags < 4 && fields.step < 8 {
+            assert_eq!(word, expected, "address {:#x}", address);
+        } else {
+            assert_eq!(word, 0, "address {:#x}", address);
+        }
+    }
+}
+
+#[test]
+fn combined_images_stack_the_chips() {
+    let microcode = microcode();
+    let layout = chips(3);
+    let images = layout.export(&microcode).unwrap();
+    let combined = layout.export_combined(&microcode).unwrap();
+    assert_eq!(combined, images.concat());
+    assert_eq!(layout.split_combined(&combined).unwrap(), images);
+}
+
+#[test]
+fn combined_images_use_the_byte_select_lines() {
+    let microcode = microcode();
+    let layout = ChipLayout {
+        chips: 4,
+        layout: "flags:2,select:2,instruction:4,step:4".parse().unwrap(),
+        active_low: 0,
+    };
+    let combined = layout.export_combined(&microcode).unwrap();
+    assert_eq!(combined.len(), layout.chip_size());
+    let images = layout.split_combined(&combined).unwrap();
+    let imported = layout.import(&images).unwrap();
+    for (address, (&word, &expected)) in imported.iter().zip(&microcode).enumerate() {
+        if address >> 8 < 4 {
+            assert_eq!(word, expected, "address {:#x}", address);
+        }
+    }
+}
+
+#[test]
+fn rejects_mismatched_images() {
+    let layout = chips(3);
+    let images = layout.export(&microcode()).unwrap();
+    assert!(layout.import(&images[..2]).is_err());
+    let mut short = images.clone();
+    short[1].pop();
+    assert!(layout.import(&short).is_err());
+    assert!(layout.split_combined(&[0; 16]).is_err());
+    let narrow = ChipLayout {
+        chips: 4,
+        layout: "flags:4,select:1,instruction:4,step:4".parse().unwrap(),
+        active_low: 0,
+    };
+    assert!(narrow.export_combined(&microcode()).is_err());
+}
+
+#[test]
+fn takes_one_to_four_chips() {
+    let microcode = microcode();
+    for &count in [0, 5].iter() {
+        let layout = chips(count);
+        assert!(layout.export(&microcode).is_err());
+        assert!(layout.export_combined(&microcode).is_err());
+        let images = vec![vec![0; layout.chip_size()]; count];
+        assert!(layout.import(&images).is_err());
+    }
+}
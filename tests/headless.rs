@@ -0,0 +1,142 @@
+use breadboard_8bit::eeprom::ChipLayout;
+use breadboard_8bit::microcode_compiler::compile;
+use breadboard_8bit::modules::{
+    AddressLayout, ControlFlag, ControlNames, MicrocodeFile, MICROCODE_SIZE,
+};
+use std::process::{Command, Output};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_breadboard_8bit"))
+        .args(["--headless", "--trace", "none"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn halted_programs_exit_with_0() {
+    let output = run(&["--program", "assets/programs/add.asm"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Halted after 15 cycles\n"), "{}", stdout);
+    assert!(stdout.contains("    Output: 42\n"), "{}", stdout);
+    assert!(
+        stdout.contains("  0x00: 1e 2f e0 f0 00 00 00 00 00 00 00 00 00 00 0e 1c\n"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn the_cycle_limit_exits_with_2() {
+    let output = run(&["--program", "assets/programs/add.asm", "--max-cycles", "5"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("Cycle limit of 5 reached\n"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn errors_exit_with_1() {
+    let output = run(&["--program", "assets/programs/missing.asm"]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&["--max-cycles", "many"]);
+    assert_eq!(output.status.code(), Some(1));
+    // EEPROM options without dumps
+    for option in ["--layout", "--active-low", "--steps"].iter() {
+        let output = run(&[option, "all"]);
+        assert_eq!(output.status.code(), Some(1), "{}", option);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("--eeprom"), "{}: {}", option, stderr);
+    }
+}
+
+#[test]
+fn bus_contention_exits_with_4_with_the_halt_policy() {
+    // Every instruction has A and the program counter drive the bus at once
+    let word = ControlFlag::ARegisterOut as u32 | ControlFlag::CounterOut as u32;
+    let file = MicrocodeFile {
+        layout: AddressLayout::default(),
+        microcode: vec![word; MICROCODE_SIZE],
+        names: ControlNames::default(),
+        steps: 5,
+    };
+    let path = std::env::temp_dir().join(format!("contention-{}.mc", std::process::id()));
+    std::fs::write(&path, file.to_bytes().unwrap()).unwrap();
+    let microcode = path.to_str().unwrap();
+
+    let output = run(&["--microcode", microcode, "--bus-contention", "halt"]);
+    assert_eq!(output.status.code(), Some(4));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("Bus contention after 0 cycles: "),
+        "{}",
+        stdout
+    );
+    // Other policies carry on until the cycle limit
+    let output = run(&["--microcode", microcode, "--max-cycles", "10"]);
+    assert_eq!(output.status.code(), Some(2));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rom_contents_are_printed_from_their_start() {
+    // The extended machine with the constants of the program in a ROM
+    let program = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/programs/rom.asm");
+    let description = std::fs::read_to_string("assets/machines/extended.toml").unwrap()
+        + &format!(
+            "\n[[module]]\nkind = \"rom\"\naddress = \"Memory Address\"\nstart = 0x70\n\
+             size = 2\nprogram = {:?}\n",
+            program
+        );
+    let path = std::env::temp_dir().join(format!("rom-{}.toml", std::process::id()));
+    std::fs::write(&path, description).unwrap();
+    let output = run(&[
+        "--machine",
+        path.to_str().unwrap(),
+        "--program",
+        "assets/programs/rom.asm",
+    ]);
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("    Output: 42\n"), "{}", stdout);
+    assert!(stdout.ends_with("ROM@0x70:\n  0x70: 07 01\n"), "{}", stdout);
+}
+
+#[test]
+fn combined_eeprom_dumps_are_split_per_chip() {
+    let source = std::fs::read_to_string("assets/microcode/default.mc").unwrap();
+    let microcode = compile(&source).ok().unwrap().microcode;
+    let layout = ChipLayout {
+        chips: 3,
+        layout: "flags:2,select:2,instruction:4,step:4".parse().unwrap(),
+        active_low: 0,
+    };
+    let path = std::env::temp_dir().join(format!("combined-{}.bin", std::process::id()));
+    std::fs::write(&path, layout.export_combined(&microcode[..]).unwrap()).unwrap();
+    let dump = path.to_str().unwrap();
+    let layout = "flags:2,select:2,instruction:4,step:4";
+
+    let output = run(&[
+        "--program",
+        "assets/programs/add.asm",
+        "--eeprom",
+        dump,
+        "--layout",
+        layout,
+        "--combined",
+        "--chips",
+        "3",
+    ]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains("    Output: 42\n"), "{}", stdout);
+    // The chip count is needed to split the dump
+    let output = run(&["--eeprom", dump, "--layout", layout, "--combined"]);
+    assert_eq!(output.status.code(), Some(1));
+    std::fs::remove_file(path).unwrap();
+}
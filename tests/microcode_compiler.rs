@@ -0,0 +1,173 @@
+use breadboard_8bit::isa;
+use breadboard_8bit::microcode_compiler::{compile, CompileError, Compiled, ErrorKind};
+use breadboard_8bit::modules::{
+    AddressLayout, BranchingInstructionDecoder, ControlFlag, ControlWord, InstructionDecoder,
+    MicrocodeDecoder, MicrocodeFile,
+};
+use breadboard_8bit::shareable::{Share, Shareable};
+
+const FETCH: &str = "fetch:\n    CO MI\n    RO II CE\n";
+
+fn compiled(source: &str) -> Compiled {
+    compile(source)
+        .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+        .unwrap()
+}
+
+fn errors(source: &str) -> Vec<CompileError> {
+    compile(source).err().unwrap()
+}
+
+fn word(compiled: &Compiled, flags: u8, instruction: u8, step: u8) -> u32 {
+    compiled.microcode[(flags as usize) << 8 | (instruction as usize) << 4 | step as usize]
+}
+
+#[test]
+fn default_description_matches_the_branching_decoder() {
+    let source = std::fs::read_to_string("assets/microcode/default.mc").unwrap();
+    let compiled = compiled(&source);
+    assert_eq!(compiled.steps, 5);
+    let mut instruction = Shareable::new(0);
+    let mut flags = Shareable::new(0);
+    let mut decoder = BranchingInstructionDecoder::new(instruction.share(), flags.share());
+    for f in 0..4 {
+        for opcode in 0..16 {
+            for step in 0..5 {
+                instruction.set(opcode << 4);
+                flags.set(f);
+                decoder.set_counter(step as usize);
+                assert_eq!(
+                    word(&compiled, f, opcode, step),
+                    decoder.decode().0,
+                    "flags {:04b}, opcode {:#x}, step {}",
+                    f,
+                    opcode,
+                    step
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn flags_are_given_by_label_or_name() {
+    let compiled = compiled("OUT:\n    AO | OutputRegisterIn, NI\n");
+    let expected = ControlFlag::ARegisterOut as u32
+        | ControlFlag::OutputRegisterIn as u32
+        | ControlFlag::NextInstruction as u32;
+    assert_eq!(word(&compiled, 0, isa::OUT, 0), expected);
+}
+
+#[test]
+fn every_instruction_starts_with_fetch() {
+    let compiled = compiled(&format!("{}OUT:\n    AO OI NI\n", FETCH));
+    let fetch = ControlFlag::CounterOut as u32 | ControlFlag::MemoryAddressIn as u32;
+    for opcode in 0..16 {
+        assert_eq!(word(&compiled, 0, opcode, 0), fetch);
+    }
+    assert_eq!(word(&compiled, 0, isa::OUT, 3), 0);
+    assert_ne!(word(&compiled, 0, isa::OUT, 2), 0);
+    assert_eq!(word(&compiled, 0, isa::HLT, 2), 0);
+}
+
+#[test]
+fn conditions_pick_the_first_matching_step() {
+    let source = "JC:\n    if C & !Z: J\n    if Z: HLT\n    else: NI\n    -\n";
+    let compiled = compiled(source);
+    assert_eq!(word(&compiled, 0b10, isa::JC, 0), ControlFlag::Jump as u32);
+    assert_eq!(word(&compiled, 0b11, isa::JC, 0), ControlFlag::Hlt as u32);
+    assert_eq!(word(&compiled, 0b01, isa::JC, 0), ControlFlag::Hlt as u32);
+    assert_eq!(
+        word(&compiled, 0b00, isa::JC, 0),
+        ControlFlag::NextInstruction as u32
+    );
+    assert_eq!(word(&compiled, 0b00, isa::JC, 1), 0);
+}
+
+#[test]
+fn new_instructions_give_their_opcode() {
+    let compiled = compiled("flag 17 ShiftLeft SL\nSHL 0x9:\n    SL NI\n");
+    assert_eq!(compiled.names.name(17), "ShiftLeft");
+    assert_eq!(compiled.names.label(17), "SL");
+    assert_eq!(
+        word(&compiled, 0, 9, 0),
+        1 << 17 | ControlFlag::NextInstruction as u32
+    );
+}
+
+#[test]
+fn custom_names_survive_the_microcode_file() {
+    let compiled = compiled("flag 17 ShiftLeft SL\nflag 20 Beep\nSHL 0x9:\n    SL NI\n    Beep\n");
+    let file = MicrocodeFile {
+        layout: AddressLayout::default(),
+        microcode: compiled.microcode.to_vec(),
+        names: compiled.names,
+        steps: compiled.steps as u8,
+    };
+    let file = MicrocodeFile::from_bytes(&file.to_bytes().unwrap()).unwrap();
+    let instruction = Shareable::new(0x90);
+    let flags = Shareable::new(0);
+    let mut decoder =
+        MicrocodeDecoder::with_file(instruction.share(), flags.share(), file).unwrap();
+    let names = decoder.control_names();
+    assert_eq!(names.label(17), "SL");
+    assert_eq!(names.label(20), "Beep");
+
+    let cw = decoder.decode();
+    assert_eq!(cw.named(&names).to_string(), "NextInstruction | ShiftLeft");
+    decoder.set_counter(1);
+    assert_eq!(decoder.decode().named(&names).to_string(), "Beep");
+    // Without the names, the bits are only numbered
+    assert_eq!(cw.to_string(), "NextInstruction | bit 17");
+    assert_eq!(ControlWord(1 << 20).to_string(), "bit 20");
+}
+
+#[test]
+fn step_count_can_be_raised() {
+    let long = "LDA:\n    -\n    -\n    -\n    -\n    -\n    NI\n";
+    assert_eq!(
+        errors(long)[0].kind,
+        ErrorKind::TooManySteps {
+            name: "LDA".to_string(),
+            steps: 6,
+            max: 5
+        }
+    );
+    let compiled = compiled(&format!("steps 6\n{}", long));
+    assert_eq!(compiled.steps, 6);
+    assert_eq!(
+        word(&compiled, 0, isa::LDA, 5),
+        ControlFlag::NextInstruction as u32
+    );
+}
+
+#[test]
+fn every_error_is_reported_with_its_line() {
+    let source = "\
+        OUT\n\
+        LDA:\n    FOO\n\
+        LDA:\n\
+        JC:\n    else: NI\n    if Q: J\n\
+        BAR:\n\
+        X 16:\n\
+        flag 3 Mine\n\
+        steps 0\n";
+    let errors: Vec<(usize, ErrorKind)> = errors(source)
+        .into_iter()
+        .map(|e| (e.line, e.kind))
+        .collect();
+    assert_eq!(
+        errors,
+        [
+            (1, ErrorKind::StepOutsideSection),
+            (3, ErrorKind::UnknownFlag("FOO".to_string())),
+            (4, ErrorKind::DuplicateOpcode(isa::LDA)),
+            (6, ErrorKind::ElseWithoutIf),
+            (7, ErrorKind::InvalidCondition("Q".to_string())),
+            (8, ErrorKind::UnknownMnemonic("BAR".to_string())),
+            (9, ErrorKind::InvalidOpcode("16".to_string())),
+            (10, ErrorKind::FlagBitTaken(3)),
+            (11, ErrorKind::InvalidSteps("0".to_string())),
+        ]
+    );
+}
@@ -0,0 +1,152 @@
+use breadboard_8bit::modules::{
+    AddressLayout, ControlFlag, ControlNames, MicrocodeFile, DEFAULT_STEPS, MICROCODE_SIZE,
+};
+
+fn words(size: usize) -> Vec<u32> {
+    (0..size as u32)
+        .map(|address| (address * 7) & (ControlFlag::NextInstruction as u32 * 2 - 1))
+        .collect()
+}
+
+fn default_file() -> MicrocodeFile {
+    MicrocodeFile {
+        layout: AddressLayout::default(),
+        microcode: words(MICROCODE_SIZE),
+        names: ControlNames::default(),
+        steps: DEFAULT_STEPS as u8,
+    }
+}
+
+fn error(bytes: &[u8]) -> String {
+    MicrocodeFile::from_bytes(bytes).err().unwrap()
+}
+
+#[test]
+fn round_trips_the_container() {
+    let file = default_file();
+    let bytes = file.to_bytes().unwrap();
+    assert!(bytes.starts_with(b"BB8MCODE"));
+    assert_eq!(MicrocodeFile::from_bytes(&bytes), Ok(file));
+}
+
+#[test]
+fn keeps_the_layout_names_and_steps() {
+    let layout: AddressLayout = "flags:2,instruction:4,step:3".parse().unwrap();
+    let mut names = ControlNames::default();
+    names.set(20, "Beep", "BP");
+    let file = MicrocodeFile {
+        layout,
+        microcode: words(layout.size()),
+        names,
+        steps: 8,
+    };
+    let read = MicrocodeFile::from_bytes(&file.to_bytes().unwrap()).unwrap();
+    assert_eq!(read.layout, layout);
+    assert_eq!(read.steps, 8);
+    assert_eq!(read.names.name(20), "Beep");
+    assert_eq!(read.names.label(20), "BP");
+    assert_eq!(read, file);
+}
+
+#[test]
+fn reads_raw_images() {
+    let file = default_file();
+    let raw = file.to_raw_bytes().unwrap();
+    assert_eq!(raw.len(), MICROCODE_SIZE * 4);
+    assert_eq!(&raw[4..8], &file.microcode[1].to_le_bytes());
+    assert_eq!(MicrocodeFile::from_bytes(&raw), Ok(file));
+}
+
+#[test]
+fn raw_images_only_hold_the_default_layout() {
+    let mut file = default_file();
+    file.steps = 6;
+    assert!(file.to_raw_bytes().is_err());
+    let layout: AddressLayout = "flags:2,instruction:4,step:3".parse().unwrap();
+    let file = MicrocodeFile {
+        layout,
+        microcode: words(layout.size()),
+        names: ControlNames::default(),
+        steps: DEFAULT_STEPS as u8,
+    };
+    assert!(file.to_raw_bytes().is_err());
+}
+
+#[test]
+fn rejects_truncated_raw_images() {
+    let raw = default_file().to_raw_bytes().unwrap();
+    let message = error(&raw[..raw.len() - 4]);
+    assert!(message.contains("truncated"), "{}", message);
+}
+
+#[test]
+fn rejects_big_endian_raw_images() {
+    let raw: Vec<u8> = default_file()
+        .microcode
+        .iter()
+        .flat_map(|word| word.to_be_bytes().to_vec())
+        .collect();
+    let message = error(&raw);
+    assert!(message.contains("big-endian"), "{}", message);
+}
+
+#[test]
+fn rejects_truncated_containers() {
+    let bytes = default_file().to_bytes().unwrap();
+    for &len in [9, 12, 40, bytes.len() - 1].iter() {
+        let message = error(&bytes[..len]);
+        assert!(message.contains("truncated"), "{} bytes: {}", len, message);
+    }
+}
+
+#[test]
+fn rejects_corrupted_containers() {
+    let mut bytes = default_file().to_bytes().unwrap();
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 1;
+    let message = error(&bytes);
+    assert!(message.contains("checksum mismatch"), "{}", message);
+
+    let mut bytes = default_file().to_bytes().unwrap();
+    bytes.push(0);
+    assert!(error(&bytes).contains("unexpected bytes"));
+}
+
+#[test]
+fn rejects_other_versions() {
+    for version in [0, 2].iter() {
+        let mut bytes = default_file().to_bytes().unwrap();
+        bytes[8] = *version;
+        assert!(error(&bytes).contains("unsupported microcode file version"));
+    }
+}
+
+#[test]
+fn refuses_to_write_names_that_do_not_fit() {
+    let mut names = ControlNames::default();
+    names.set(20, &"x".repeat(256), "X");
+    let file = MicrocodeFile {
+        names,
+        ..default_file()
+    };
+    let message = file.to_bytes().unwrap_err();
+    assert!(
+        message.contains("name of bit 20 (256 bytes)"),
+        "{}",
+        message
+    );
+    let mut bytes = Vec::new();
+    assert!(file.write(&mut bytes).is_err());
+}
+
+#[test]
+fn rejects_renamed_built_in_flags() {
+    let mut names = ControlNames::default();
+    names.set(ControlFlag::Hlt.bit(), "Halt", "HLT");
+    let file = MicrocodeFile {
+        names,
+        ..default_file()
+    };
+    let message = error(&file.to_bytes().unwrap());
+    assert!(message.contains("control bit 0 is Halt"), "{}", message);
+}
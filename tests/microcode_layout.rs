@@ -0,0 +1,162 @@
+use breadboard_8bit::modules::{
+    AddressLayout, BitField, ControlNames, InstructionDecoder, MicrocodeAddress, MicrocodeDecoder,
+    MicrocodeFile,
+};
+use breadboard_8bit::shareable::{Share, Shareable};
+
+fn layout(s: &str) -> AddressLayout {
+    s.parse().unwrap()
+}
+
+#[test]
+fn fields_are_given_most_significant_first() {
+    let layout = layout("flags:2,select:1,instruction:4,step:3");
+    assert_eq!(layout.step, BitField { shift: 0, width: 3 });
+    assert_eq!(layout.instruction, BitField { shift: 3, width: 4 });
+    assert_eq!(layout.byte_select, Some(BitField { shift: 7, width: 1 }));
+    assert_eq!(layout.flags, BitField { shift: 8, width: 2 });
+    assert_eq!(
+        (layout.bits(), layout.size(), layout.max_steps()),
+        (10, 1024, 8)
+    );
+    assert_eq!(layout.to_string(), "flags:2,select:1,instruction:4,step:3");
+    assert_eq!(layout.to_string().parse(), Ok(layout));
+}
+
+#[test]
+fn the_default_is_the_breadboard_wiring() {
+    let default = AddressLayout::default();
+    assert_eq!(layout("flags:4,instruction:4,step:4"), default);
+    assert_eq!(layout(" f:4, i:4, s:4 "), default);
+    assert_eq!(default.byte_select, None);
+    assert_eq!(default.size(), 4096);
+}
+
+#[test]
+fn invalid_layouts_are_refused() {
+    let cases = [
+        ("flags:4,instruction:4", "no step field"),
+        ("flags:4,instruction:4,step:5", "invalid width 5 for step"),
+        ("flags:0,instruction:4,step:4", "invalid width 0 for flags"),
+        ("flags:4,select:3,instruction:4,step:4", "expected 1 to 2"),
+        ("flags:x,instruction:4,step:4", "invalid width x"),
+        (
+            "flags:4,instruction:4,step:4,flags:1",
+            "flags is given twice",
+        ),
+        ("flags:4,opcode:4,step:4", "unknown layout field opcode"),
+        ("flags,instruction:4,step:4", "expected NAME:WIDTH"),
+        ("", "expected NAME:WIDTH"),
+    ];
+    for (text, message) in cases.iter() {
+        let error = text.parse::<AddressLayout>().unwrap_err();
+        assert!(error.contains(message), "{}: {}", text, error);
+    }
+}
+
+#[test]
+fn addresses_are_packed_into_their_fields() {
+    let layout = layout("instruction:4,flags:2,step:3");
+    let address = MicrocodeAddress {
+        flags: 0b10,
+        instruction: 0xa,
+        step: 5,
+    };
+    assert_eq!(layout.pack(address), 0xa << 5 | 0b10 << 3 | 5);
+    assert_eq!(layout.unpack(layout.pack(address)), address);
+    // Values wider than their field are truncated
+    let wide = MicrocodeAddress {
+        flags: 0b111,
+        instruction: 0x1f,
+        step: 9,
+    };
+    assert_eq!(
+        layout.unpack(layout.pack(wide)),
+        MicrocodeAddress {
+            flags: 0b11,
+            instruction: 0xf,
+            step: 1
+        }
+    );
+}
+
+#[test]
+fn bytes_are_selected_past_the_address_or_by_their_lines() {
+    let shared = layout("select:2,flags:2,instruction:4,step:4");
+    assert_eq!(shared.select_byte(0x123, 3), 3 << 10 | 0x123);
+    assert_eq!(
+        AddressLayout::default().select_byte(0x123, 2),
+        2 << 12 | 0x123
+    );
+}
+
+#[test]
+fn microcode_round_trips_through_another_layout() {
+    let from = AddressLayout::default();
+    let to = layout("step:4,instruction:4,flags:4");
+    let microcode: Vec<u32> = (0..from.size() as u32).collect();
+    let converted = from.convert(&microcode, &to);
+    assert_eq!(converted.len(), to.size());
+    let address = MicrocodeAddress {
+        flags: 1,
+        instruction: 2,
+        step: 3,
+    };
+    assert_eq!(converted[to.pack(address)], microcode[from.pack(address)]);
+    assert_eq!(to.convert(&converted, &from), microcode);
+
+    // Addresses wider than a layout are truncated like its address lines
+    let narrow = layout("flags:2,instruction:4,step:3");
+    let back = narrow.convert(&from.convert(&microcode, &narrow), &from);
+    assert_eq!(back[from.pack(address)], microcode[from.pack(address)]);
+    let truncated = MicrocodeAddress { step: 9, ..address };
+    let step_1 = MicrocodeAddress { step: 1, ..address };
+    assert_eq!(back[from.pack(truncated)], microcode[from.pack(step_1)]);
+}
+
+#[test]
+fn decoders_leave_the_byte_select_lines_out() {
+    let shared = layout("flags:2,select:1,instruction:4,step:3");
+    let decoder = shared.without_byte_select();
+    assert_eq!(decoder, layout("flags:2,instruction:4,step:3"));
+    assert_eq!((shared.size(), decoder.size()), (1024, 512));
+    assert_eq!(
+        layout("select:2,flags:4,instruction:4,step:4").without_byte_select(),
+        AddressLayout::default()
+    );
+
+    // A file written for chips sharing an image runs on a decoder
+    let microcode: Vec<u32> = (0..shared.size() as u32)
+        .map(|address| address & 0x7f)
+        .collect();
+    let file = MicrocodeFile {
+        layout: shared,
+        microcode,
+        names: ControlNames::default(),
+        steps: 8,
+    };
+    let instruction = Shareable::new(0x30);
+    let flags = Shareable::new(0b10);
+    let mut decoder =
+        MicrocodeDecoder::with_file(instruction.share(), flags.share(), file).unwrap();
+    assert_eq!(decoder.microcode().map(|m| m.len()), Some(512));
+    decoder.set_counter(5);
+    assert_eq!(decoder.decode().0, 3 << 3 | 5);
+}
+
+#[test]
+fn decoders_refuse_microcode_their_layout_cannot_address() {
+    let layout = layout("flags:2,instruction:4,step:3");
+    let decoder = |words: usize| {
+        MicrocodeDecoder::with_layout(
+            Shareable::new(0).share(),
+            Shareable::new(0).share(),
+            layout,
+            vec![0; words],
+        )
+    };
+    assert!(decoder(256).is_err());
+    assert!(decoder(512).unwrap().with_steps(0).is_err());
+    assert!(decoder(512).unwrap().with_steps(9).is_err());
+    assert!(decoder(512).unwrap().with_steps(8).is_ok());
+}
@@ -0,0 +1,151 @@
+use breadboard_8bit::isa;
+use breadboard_8bit::microcode_compiler::compile;
+use breadboard_8bit::microcode_lint::{lint, lint_with_expected_flags, LintKind, Severity};
+use breadboard_8bit::modules::ControlFlag;
+
+fn default_microcode() -> Vec<u32> {
+    let source = std::fs::read_to_string("assets/microcode/default.mc").unwrap();
+    compile(&source)
+        .map_err(|_| "invalid microcode")
+        .unwrap()
+        .microcode
+        .to_vec()
+}
+
+fn address(flags: u8, instruction: u8, step: u8) -> usize {
+    (flags as usize) << 8 | (instruction as usize) << 4 | step as usize
+}
+
+/// Applies `f` to the control word of a step for every flags value
+fn edit(microcode: &mut [u32], instruction: u8, step: u8, f: impl Fn(u32) -> u32) {
+    for flags in 0..16 {
+        let word = &mut microcode[address(flags, instruction, step)];
+        *word = f(*word);
+    }
+}
+
+fn kinds(microcode: &[u32], instruction: u8) -> Vec<LintKind> {
+    lint(microcode)
+        .into_iter()
+        .filter(|d| d.instruction == instruction)
+        .map(|d| d.kind)
+        .collect()
+}
+
+#[test]
+fn default_microcode_has_no_errors() {
+    let diagnostics = lint(&default_microcode());
+    let errors: Vec<String> = diagnostics
+        .iter()
+        .filter(|d| d.severity() == Severity::Error)
+        .map(|d| d.to_string())
+        .collect();
+    assert!(errors.is_empty(), "{:?}", errors);
+    // Only the opcodes without an instruction never end
+    for diagnostic in diagnostics.iter() {
+        assert_eq!(diagnostic.kind, LintKind::NoEnd(5));
+        let opcode = diagnostic.instruction;
+        assert!(
+            opcode == isa::NOP || isa::by_opcode(opcode).is_none(),
+            "{}",
+            diagnostic
+        );
+    }
+}
+
+#[test]
+fn multiple_bus_writers_are_errors() {
+    let mut microcode = default_microcode();
+    edit(&mut microcode, isa::OUT, 2, |w| {
+        w | ControlFlag::RamOut as u32
+    });
+    let diagnostics = lint(&microcode);
+    let diagnostic = diagnostics
+        .iter()
+        .find(|d| d.instruction == isa::OUT)
+        .unwrap();
+    assert_eq!(diagnostic.step, Some(2));
+    assert_eq!(diagnostic.flags.len(), 16);
+    assert_eq!(diagnostic.severity(), Severity::Error);
+    assert_eq!(
+        diagnostic.kind,
+        LintKind::MultipleWriters(vec![ControlFlag::RamOut, ControlFlag::ARegisterOut])
+    );
+    assert_eq!(
+        diagnostic.to_string(),
+        "error: OUT step 2: RamOut, ARegisterOut all write to the bus"
+    );
+}
+
+#[test]
+fn readers_without_writers_are_errors() {
+    let mut microcode = default_microcode();
+    edit(&mut microcode, isa::LDA, 3, |w| {
+        w & !(ControlFlag::RamOut as u32)
+    });
+    assert_eq!(
+        kinds(&microcode, isa::LDA),
+        [LintKind::ReaderWithoutWriter(vec![
+            ControlFlag::ARegisterIn
+        ])]
+    );
+}
+
+#[test]
+fn unnamed_bits_are_errors() {
+    let mut microcode = default_microcode();
+    edit(&mut microcode, isa::HLT, 2, |w| w | 1 << 20);
+    assert_eq!(kinds(&microcode, isa::HLT), [LintKind::UnusedBits(1 << 20)]);
+}
+
+#[test]
+fn instructions_that_never_end_are_warnings() {
+    let mut microcode = default_microcode();
+    edit(&mut microcode, isa::OUT, 2, |w| {
+        w & !(ControlFlag::NextInstruction as u32)
+    });
+    let diagnostics = lint(&microcode);
+    let diagnostic = diagnostics
+        .iter()
+        .find(|d| d.instruction == isa::OUT)
+        .unwrap();
+    assert_eq!(diagnostic.kind, LintKind::NoEnd(5));
+    assert_eq!(diagnostic.severity(), Severity::Warning);
+}
+
+#[test]
+fn differing_fetch_cycles_are_errors() {
+    let mut microcode = default_microcode();
+    let usual = microcode[address(0, isa::ADD, 1)];
+    edit(&mut microcode, isa::ADD, 1, |w| {
+        w & !(ControlFlag::CounterEnable as u32)
+    });
+    assert_eq!(
+        kinds(&microcode, isa::ADD),
+        [LintKind::FetchDiffers {
+            expected: usual,
+            found: usual & !(ControlFlag::CounterEnable as u32)
+        }]
+    );
+}
+
+#[test]
+fn unexpected_flag_dependence_is_a_warning() {
+    let mut microcode = default_microcode();
+    // JMP that only jumps when the carry is set
+    for flags in (0..16).filter(|flags| flags & 0b10 == 0) {
+        microcode[address(flags, isa::JMP, 2)] = ControlFlag::NextInstruction as u32;
+    }
+    let diagnostics = lint(&microcode);
+    let diagnostic = diagnostics
+        .iter()
+        .find(|d| d.instruction == isa::JMP)
+        .unwrap();
+    assert_eq!(diagnostic.kind, LintKind::FlagDependence(0b10));
+    assert_eq!(diagnostic.severity(), Severity::Warning);
+    let allowed = lint_with_expected_flags(
+        &microcode,
+        &[(isa::JMP, 0b10), (isa::JC, 0b10), (isa::JZ, 0b01)],
+    );
+    assert!(allowed.iter().all(|d| d.instruction != isa::JMP));
+}
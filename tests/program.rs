@@ -0,0 +1,147 @@
+use breadboard_8bit::isa::Encoding;
+use breadboard_8bit::program::{parse_intel_hex, parse_program, ProgramFormat};
+use std::path::Path;
+
+const ADD_HEX: &str = ":040000001E2FE0F0DF\n:02000E000E1CC6\n:00000001FF\n";
+
+#[test]
+fn hex_data_records_are_placed_at_their_address() {
+    let image = parse_intel_hex(ADD_HEX, 16).unwrap();
+    assert_eq!(
+        image,
+        [0x1e, 0x2f, 0xe0, 0xf0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0e, 0x1c]
+    );
+}
+
+#[test]
+fn hex_image_ends_at_the_highest_address_written() {
+    assert_eq!(
+        parse_intel_hex(":0100020042BB\n", 16).unwrap(),
+        [0, 0, 0x42]
+    );
+    assert_eq!(parse_intel_hex("", 16).unwrap(), []);
+}
+
+#[test]
+fn hex_stops_at_end_of_file_record() {
+    let image = parse_intel_hex(":00000001FF\n:0100020042BB\n", 16).unwrap();
+    assert!(image.is_empty());
+}
+
+#[test]
+fn hex_checksums_are_verified() {
+    let error = parse_intel_hex(":0100020042BC\n", 16).unwrap_err();
+    assert!(
+        error.contains("line 1") && error.contains("checksum"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn malformed_hex_records_are_refused() {
+    for (record, message) in [
+        ("0100020042BB", "':'"),
+        (":01000200", "malformed"),
+        (":0100020042B", "malformed"),
+        (":01000200GGBB", "hex digit"),
+        (":0200020042BA", "length"),
+        (":0100020642B5", "record type"),
+    ] {
+        let error = parse_intel_hex(record, 16).unwrap_err();
+        assert!(error.contains(message), "{}: {}", record, error);
+    }
+}
+
+#[test]
+fn hex_records_past_the_end_of_ram_are_refused() {
+    let error = parse_intel_hex(":02000E000102ED\n:0100100042AD\n", 16).unwrap_err();
+    assert!(error.contains("line 2"), "{}", error);
+    assert_eq!(parse_intel_hex(":0100100042AD\n", 256).unwrap()[0x10], 0x42);
+    // An extended linear address would otherwise grow the image to 4 GiB
+    let error = parse_intel_hex(":02000004FFFFFC\n:0100000042BD\n", 256).unwrap_err();
+    assert!(error.contains("line 2"), "{}", error);
+    let error = parse_intel_hex(":020000021000EC\n:0100000042BD\n", 256).unwrap_err();
+    assert!(error.contains("line 2"), "{}", error);
+}
+
+#[test]
+fn formats_are_detected_from_the_extension_then_the_contents() {
+    let detect = |name: &str, contents: &[u8]| ProgramFormat::detect(Path::new(name), contents);
+    assert_eq!(detect("add.bin", b"LDA 14"), ProgramFormat::Binary);
+    assert_eq!(detect("add.hex", b""), ProgramFormat::IntelHex);
+    assert_eq!(detect("add.asm", b""), ProgramFormat::Assembly);
+    assert_eq!(
+        detect("add", &[0x1e, 0x2f, 0xe0, 0xf0]),
+        ProgramFormat::Binary
+    );
+    assert_eq!(detect("add", ADD_HEX.as_bytes()), ProgramFormat::IntelHex);
+    assert_eq!(detect("add", b"LDA 14\nOUT\n"), ProgramFormat::Assembly);
+    assert_eq!("ihex".parse(), Ok(ProgramFormat::IntelHex));
+    assert!("elf".parse::<ProgramFormat>().is_err());
+}
+
+#[test]
+fn programs_must_fit_in_ram() {
+    let binary = [0xe0; 17];
+    let error = parse_program(
+        &binary,
+        ProgramFormat::Binary,
+        Encoding::Compact,
+        Encoding::Compact.ram_size(),
+    )
+    .unwrap_err();
+    assert!(error.contains("17 bytes"), "{}", error);
+    let image = parse_program(
+        &binary,
+        ProgramFormat::Binary,
+        Encoding::Extended,
+        Encoding::Extended.ram_size(),
+    )
+    .unwrap();
+    assert_eq!(image.len(), 17);
+}
+
+#[test]
+fn assembly_is_assembled_for_the_encoding() {
+    let source = b"LDA 14\nOUT\nHLT\n";
+    let compact = parse_program(
+        source,
+        ProgramFormat::Assembly,
+        Encoding::Compact,
+        Encoding::Compact.ram_size(),
+    )
+    .unwrap();
+    assert_eq!(compact.len(), 16);
+    assert_eq!(compact[..3], [0x1e, 0xe0, 0xf0]);
+    let extended = parse_program(
+        source,
+        ProgramFormat::Assembly,
+        Encoding::Extended,
+        Encoding::Extended.ram_size(),
+    )
+    .unwrap();
+    assert_eq!(extended.len(), 256);
+    assert_eq!(extended[..4], [0x10, 0x0e, 0xe0, 0xf0]);
+}
+
+#[test]
+fn programs_are_sized_for_the_ram_built() {
+    let source = b"LDA 14\nOUT\nHLT\n";
+    let small = parse_program(source, ProgramFormat::Assembly, Encoding::Extended, 16).unwrap();
+    assert_eq!(small.len(), 16);
+    let source = b"LDA 0x80\nHLT\n.org 0x80\n.byte 42\n";
+    let error = parse_program(source, ProgramFormat::Assembly, Encoding::Extended, 16).unwrap_err();
+    assert!(
+        error.contains("129 bytes long but RAM only holds 16 bytes"),
+        "{}",
+        error
+    );
+    // Explicit zeros past the end of RAM are part of the program
+    let source = b"HLT\n.org 20\n.byte 0\n";
+    let error = parse_program(source, ProgramFormat::Assembly, Encoding::Extended, 16).unwrap_err();
+    assert!(error.contains("21 bytes"), "{}", error);
+    let error =
+        parse_program(&[0xe0; 17], ProgramFormat::Binary, Encoding::Extended, 16).unwrap_err();
+    assert!(error.contains("17 bytes"), "{}", error);
+}
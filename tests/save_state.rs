@@ -0,0 +1,94 @@
+use breadboard_8bit::breadboard_builder::BreadboardBuilder;
+use breadboard_8bit::modules::{ControlNames, MicrocodeDecoder, MICROCODE_SIZE};
+use breadboard_8bit::save_state::{SaveState, VERSION};
+use breadboard_8bit::state::BreadboardState;
+use breadboard_8bit::trace::Tracer;
+
+fn saved() -> SaveState {
+    let mut state = BreadboardState::default().with_tracer(Tracer::none());
+    state.run_until_halt(7);
+    state.save_state()
+}
+
+#[test]
+fn round_trips_through_bytes() {
+    let saved = saved();
+    let read = SaveState::from_bytes(&saved.to_bytes().unwrap()).unwrap();
+    assert_eq!(read.decoder, saved.decoder);
+    assert_eq!(
+        (read.cycles, read.step, read.bus),
+        (saved.cycles, saved.step, saved.bus)
+    );
+    assert_eq!(read.cw.0, saved.cw.0);
+    assert_eq!(read.modules, saved.modules);
+}
+
+#[test]
+fn refuses_what_does_not_fit() {
+    let mut saved = saved();
+    saved.modules[0].0 = "x".repeat(256);
+    let error = saved.to_bytes().unwrap_err();
+    assert!(error.contains("name of"), "{}", error);
+
+    let mut saved = self::saved();
+    saved.modules[0].1 = vec![0; 1 << 16];
+    let error = saved.to_bytes().unwrap_err();
+    assert!(error.contains("snapshot of"), "{}", error);
+
+    let mut saved = self::saved();
+    let module = saved.modules[0].clone();
+    saved.modules = vec![module; 256];
+    let error = saved.to_bytes().unwrap_err();
+    assert!(error.contains("module count (256)"), "{}", error);
+}
+
+fn named_machine(names: ControlNames) -> BreadboardState<MicrocodeDecoder> {
+    BreadboardBuilder::sap1()
+        .build(
+            |_| (),
+            |instruction, flags| {
+                Ok(MicrocodeDecoder::with_microcode(
+                    instruction,
+                    flags,
+                    Box::new([1 << 20; MICROCODE_SIZE]),
+                )
+                .with_control_names(names))
+            },
+        )
+        .unwrap()
+        .with_tracer(Tracer::none())
+}
+
+#[test]
+fn keeps_custom_control_names() {
+    let mut names = ControlNames::default();
+    names.set(20, "Beep", "BP");
+    let mut state = named_machine(names.clone());
+    state.run_until_halt(3);
+    let bytes = state.save_state().to_bytes().unwrap();
+    let read = SaveState::from_bytes(&bytes).unwrap();
+    assert_eq!(read.names, names);
+
+    let mut resumed = named_machine(read.names.clone());
+    resumed.load_state(&read).unwrap();
+    assert_eq!(
+        resumed.cw().named(resumed.control_names()).to_string(),
+        "Beep"
+    );
+    let error = named_machine(ControlNames::default())
+        .load_state(&read)
+        .unwrap_err();
+    assert!(error.contains("control bit names"), "{}", error);
+}
+
+#[test]
+fn refuses_other_versions() {
+    let mut bytes = saved().to_bytes().unwrap();
+    bytes[8] = VERSION + 1;
+    let error = SaveState::from_bytes(&bytes).err().unwrap();
+    assert!(
+        error.contains("unsupported saved state version"),
+        "{}",
+        error
+    );
+}
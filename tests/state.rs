@@ -0,0 +1,370 @@
+use breadboard_8bit::breadboard_builder::{BreadboardBuilder, MachineDescription};
+use breadboard_8bit::breakpoints::{Breakpoint, Breakpoints};
+use breadboard_8bit::bus::{Contention, ContentionPolicy, FloatingPolicy};
+use breadboard_8bit::isa::Encoding;
+use breadboard_8bit::modules::{
+    BranchingInstructionDecoder, ControlFlag, ExtendedInstructionDecoder, InstructionDecoder,
+    MicrocodeDecoder, MICROCODE_SIZE,
+};
+use breadboard_8bit::program::load_program;
+use breadboard_8bit::shareable::{Share, Shareable};
+use breadboard_8bit::state::{BreadboardState, StepSummary, Warning};
+use breadboard_8bit::trace::Tracer;
+
+// Every step of every instruction asserts the same control word
+fn constant(word: u32) -> BreadboardState<MicrocodeDecoder> {
+    BreadboardBuilder::sap1()
+        .build(
+            |_| (),
+            |instruction, flags| {
+                let microcode = Box::new([word; MICROCODE_SIZE]);
+                Ok(MicrocodeDecoder::with_microcode(
+                    instruction,
+                    flags,
+                    microcode,
+                ))
+            },
+        )
+        .unwrap()
+        .with_tracer(Tracer::none())
+}
+
+#[test]
+fn floating_reads_are_reported() {
+    let mut state = constant(ControlFlag::OutputRegisterIn as u32);
+    state.settle();
+    state.step_cycle();
+    assert_eq!(
+        state.take_warnings(),
+        [
+            (0, Warning::FloatingRead("Output".to_string())),
+            (1, Warning::FloatingRead("Output".to_string())),
+        ]
+    );
+    assert!(state.take_warnings().is_empty());
+}
+
+#[test]
+fn contention_is_reported_with_the_warn_policy() {
+    let word = ControlFlag::ARegisterOut as u32 | ControlFlag::CounterOut as u32;
+    let mut state = constant(word).with_contention_policy(ContentionPolicy::Warn);
+    state.settle();
+    let contention = Contention {
+        drivers: vec![
+            ("Program Counter".to_string(), 0),
+            ("A Register".to_string(), 0),
+        ],
+    };
+    assert_eq!(state.bus_contention(), Some(&contention));
+    assert_eq!(
+        state.take_warnings(),
+        [(0, Warning::Contention(contention))]
+    );
+
+    let mut state = constant(word).with_contention_policy(ContentionPolicy::WiredOr);
+    state.settle();
+    assert!(state.bus_contention().is_some());
+    assert!(state.take_warnings().is_empty());
+}
+
+/// Loads 5 into A, counts to 3, then has A and the program counter drive
+/// the bus into the output register before halting
+fn contending(policy: ContentionPolicy) -> BreadboardState<MicrocodeDecoder> {
+    let steps = [
+        ControlFlag::RamOut as u32 | ControlFlag::ARegisterIn as u32,
+        ControlFlag::CounterEnable as u32,
+        ControlFlag::CounterEnable as u32,
+        ControlFlag::CounterEnable as u32,
+        ControlFlag::ARegisterOut as u32
+            | ControlFlag::CounterOut as u32
+            | ControlFlag::OutputRegisterIn as u32,
+        ControlFlag::Hlt as u32,
+    ];
+    BreadboardBuilder::sap1()
+        .build(
+            |ram| ram[0] = 0b0101,
+            |instruction, flags| {
+                let mut microcode = Box::new([0; MICROCODE_SIZE]);
+                for (address, word) in microcode.iter_mut().enumerate() {
+                    *word = steps[(address & 0xf) % steps.len()];
+                }
+                MicrocodeDecoder::with_microcode(instruction, flags, microcode).with_steps(6)
+            },
+        )
+        .unwrap()
+        .with_tracer(Tracer::none())
+        .with_contention_policy(policy)
+}
+
+#[test]
+fn contention_policies_decide_the_bus_value() {
+    let cases = [
+        (ContentionPolicy::Warn, 0b0011),
+        (ContentionPolicy::WiredAnd, 0b0001),
+        (ContentionPolicy::WiredOr, 0b0111),
+    ];
+    for (policy, bus) in cases.iter() {
+        let mut state = contending(*policy);
+        state.run_until_halt(4);
+        assert_eq!(state.bus(), *bus, "{:?}", policy);
+        let summary = state.run_until_halt(100);
+        assert!(summary.halted && !summary.breakpoint, "{:?}", policy);
+        assert_eq!(value(&state, "Output"), Some(*bus), "{:?}", policy);
+    }
+}
+
+#[test]
+fn contention_halts_the_run_with_the_halt_policy() {
+    let mut state = contending(ContentionPolicy::Halt);
+    let summary = state.run_until_halt(100);
+    assert!(summary.halted);
+    assert!(!summary.breakpoint);
+    assert_eq!(state.cycles(), 4);
+    assert_eq!(
+        state.bus_contention(),
+        Some(&Contention {
+            drivers: vec![
+                ("Program Counter".to_string(), 3),
+                ("A Register".to_string(), 5),
+            ],
+        })
+    );
+    // The output register never latched the bus
+    assert_eq!(value(&state, "Output"), Some(0));
+}
+
+#[test]
+fn only_the_latest_warnings_are_kept() {
+    let mut state = constant(ControlFlag::OutputRegisterIn as u32);
+    state.run_until_halt(2000);
+    let warnings = state.take_warnings();
+    assert_eq!(warnings.len(), breadboard_8bit::state::MAX_WARNINGS);
+    assert_eq!(warnings.last().map(|(cycle, _)| *cycle), Some(2000));
+}
+
+#[test]
+fn described_machines_resume_from_their_description() {
+    let description = MachineDescription::load("assets/machines/rom.toml").unwrap();
+    let mut state = description
+        .build(|_| ())
+        .unwrap()
+        .with_tracer(Tracer::none());
+    state.run_until_halt(10);
+    let saved = state.save_state();
+
+    let mut resumed = description
+        .build(|_| ())
+        .unwrap()
+        .with_tracer(Tracer::none());
+    resumed.load_state(&saved).unwrap();
+    assert_eq!(resumed.cycles(), 10);
+    assert!(resumed.run_until_halt(1000).halted);
+    assert_eq!(resumed.module("Output").and_then(|m| m.value()), Some(42));
+
+    let mut extended = BreadboardBuilder::extended()
+        .build(
+            |_| (),
+            |instruction, flags| Ok(ExtendedInstructionDecoder::new(instruction, flags)),
+        )
+        .unwrap();
+    let error = extended.load_state(&saved).unwrap_err();
+    assert!(error.contains("ROM@0x00"), "{}", error);
+}
+
+#[test]
+fn saved_steps_past_the_decoder_are_refused() {
+    let machine = || {
+        BreadboardBuilder::extended()
+            .build(
+                |_| (),
+                |instruction, flags| Ok(ExtendedInstructionDecoder::new(instruction, flags)),
+            )
+            .unwrap()
+            .with_tracer(Tracer::none())
+    };
+    let mut saved = machine().save_state();
+    saved.step = saved.steps;
+    let error = machine().load_state(&saved).unwrap_err();
+    assert!(error.contains("only has 6 steps"), "{}", error);
+    saved.step = saved.steps - 1;
+    let mut state = machine();
+    state.load_state(&saved).unwrap();
+    assert_eq!(state.step(), 5);
+}
+
+#[test]
+fn decoder_counters_are_clamped_to_the_last_step() {
+    let register = Shareable::new(0);
+    let mut decoder = ExtendedInstructionDecoder::new(register.share(), register.share());
+    decoder.set_counter(200);
+    assert_eq!(decoder.get_counter(), 5);
+}
+
+fn add_program() -> BreadboardState {
+    let image = load_program("assets/programs/add.asm", None, Encoding::Compact, 16).unwrap();
+    BreadboardBuilder::sap1()
+        .build(
+            |ram| ram[..image.len()].copy_from_slice(&image),
+            |instruction, flags| Ok(BranchingInstructionDecoder::new(instruction, flags)),
+        )
+        .unwrap()
+        .with_tracer(Tracer::none())
+}
+
+fn value(state: &BreadboardState<impl InstructionDecoder>, name: &str) -> Option<u8> {
+    state.module(name).and_then(|m| m.value())
+}
+
+#[test]
+fn programs_run_until_they_halt() {
+    let mut state = add_program();
+    let summary = state.run_until_halt(10000);
+    assert_eq!(
+        summary,
+        StepSummary {
+            cycles: 15,
+            halted: true,
+            breakpoint: false,
+        }
+    );
+    assert_eq!(state.cycles(), summary.cycles);
+    assert_eq!(value(&state, "Output"), Some(42));
+    // A halted computer stays halted
+    assert_eq!(state.run_until_halt(10000).cycles, 0);
+    assert_eq!(state.step_cycle().cycles, 0);
+}
+
+#[test]
+fn runs_stop_at_the_cycle_limit() {
+    let mut state = add_program();
+    let summary = state.run_until_halt(3);
+    assert_eq!(summary.cycles, 3);
+    assert!(!summary.halted);
+    assert_eq!(state.cycles(), 3);
+    assert!(state.run_until_halt(10000).halted);
+    assert_eq!(value(&state, "Output"), Some(42));
+}
+
+#[test]
+fn instructions_are_stepped_one_at_a_time() {
+    let mut state = add_program();
+    // LDA 14
+    assert_eq!(state.step_instruction().cycles, 4);
+    assert_eq!(state.step(), 0);
+    assert_eq!(value(&state, "A Register"), Some(14));
+    // ADD 15
+    assert_eq!(state.step_instruction().cycles, 5);
+    assert_eq!(value(&state, "A Register"), Some(42));
+    assert_eq!(value(&state, "Output"), Some(0));
+    let summary = state.step_cycle();
+    assert_eq!(summary.cycles, 1);
+    assert_eq!(state.step(), 1);
+}
+
+/// Everything a saved state holds, which is the whole machine
+fn saved(state: &BreadboardState<impl InstructionDecoder>) -> Vec<u8> {
+    state.save_state().to_bytes().unwrap()
+}
+
+#[test]
+fn stepping_back_then_forward_gives_the_same_machine() {
+    let mut state = add_program();
+    state.run_until_halt(7);
+    let before = saved(&state);
+    state.run_until_halt(3);
+    let after = saved(&state);
+    assert_eq!(state.history_len(), 10);
+    for _ in 0..3 {
+        assert!(state.step_back_cycle());
+    }
+    assert_eq!(state.cycles(), 7);
+    assert_eq!(saved(&state), before);
+    state.run_until_halt(3);
+    assert_eq!(saved(&state), after);
+    assert!(state.run_until_halt(100).halted);
+    assert_eq!(value(&state, "Output"), Some(42));
+}
+
+#[test]
+fn stepping_back_an_instruction_returns_to_its_first_step() {
+    let mut state = add_program().with_history_length(5);
+    // Halfway through ADD
+    state.run_until_halt(6);
+    assert_eq!(state.step_back_instruction(), 2);
+    assert_eq!((state.cycles(), state.step()), (4, 0));
+    // Only 3 cycles of LDA are left in the history
+    assert_eq!(state.step_back_instruction(), 3);
+    assert_eq!((state.cycles(), state.step()), (1, 1));
+    assert_eq!(state.step_back_instruction(), 0);
+    assert!(!state.step_back_cycle());
+}
+
+#[test]
+fn stepping_back_restores_the_bus_contention() {
+    // Only the third step makes two modules write to the bus
+    let state = BreadboardBuilder::sap1()
+        .build(
+            |_| (),
+            |instruction, flags| {
+                let mut microcode = Box::new([0; MICROCODE_SIZE]);
+                for (address, word) in microcode.iter_mut().enumerate() {
+                    if address & 0xf == 2 {
+                        *word = ControlFlag::ARegisterOut as u32 | ControlFlag::CounterOut as u32;
+                    }
+                }
+                Ok(MicrocodeDecoder::with_microcode(
+                    instruction,
+                    flags,
+                    microcode,
+                ))
+            },
+        )
+        .unwrap();
+    let mut state = state.with_tracer(Tracer::none());
+    state.run_until_halt(2);
+    assert!(state.bus_contention().is_some());
+    state.step_back_cycle();
+    assert_eq!(state.bus_contention(), None);
+    state.step_cycle();
+    assert!(state.bus_contention().is_some());
+}
+
+#[test]
+fn stepping_back_restores_the_floating_bus_noise() {
+    let mut state =
+        constant(ControlFlag::OutputRegisterIn as u32).with_floating_policy(FloatingPolicy::Random);
+    state.settle();
+    let mut bus = Vec::new();
+    for _ in 0..4 {
+        state.step_cycle();
+        bus.push(state.bus());
+    }
+    for _ in 0..3 {
+        state.step_back_cycle();
+    }
+    assert_eq!(state.bus(), bus[0]);
+    let mut replayed = vec![state.bus()];
+    for _ in 0..3 {
+        state.step_cycle();
+        replayed.push(state.bus());
+    }
+    assert_eq!(replayed, bus);
+}
+
+#[test]
+fn stepping_back_restores_the_watched_values() {
+    let mut state = add_program().with_breakpoints(Breakpoints::new(vec![Breakpoint::Watch(
+        "Output".to_string(),
+    )]));
+    assert!(state.run_until_halt(100).breakpoint);
+    assert_eq!(state.cycles(), 12);
+    // The output changes again when the cycle is replayed
+    state.step_back_cycle();
+    assert!(state.step_cycle().breakpoint);
+    // and does not change when going back further
+    for _ in 0..2 {
+        state.step_back_cycle();
+    }
+    assert!(!state.step_cycle().breakpoint);
+    assert!(state.step_cycle().breakpoint);
+}
@@ -0,0 +1,98 @@
+use breadboard_8bit::modules::BranchingInstructionDecoder;
+use breadboard_8bit::trace::{MemorySink, TraceFormat, Tracer};
+
+mod common;
+
+use common::{machine, ADD};
+
+/// Runs a program to completion and returns its trace
+fn trace(source: &str, format: TraceFormat) -> Vec<String> {
+    let sink = MemorySink::new();
+    let mut state = machine(source, BranchingInstructionDecoder::new)
+        .with_tracer(Tracer::new(format, sink.clone()));
+    assert!(state.run_until_halt(100).halted);
+    sink.lines()
+}
+
+#[test]
+fn compact_traces_take_a_line_per_cycle() {
+    let lines = trace(ADD, TraceFormat::Compact);
+    assert_eq!(lines.len(), 15);
+    assert_eq!(
+        lines[0],
+        "     0 T0 NOP      bus 00000000  MemoryAddressIn | CounterOut"
+    );
+    assert_eq!(
+        lines[2],
+        "     2 T2 LDA 14   bus 00001110  MemoryAddressIn | InstructionRegisterOut"
+    );
+    assert_eq!(lines[14], "    14 T2 HLT      bus 00000000  Hlt");
+}
+
+#[test]
+fn full_traces_list_every_module() {
+    let lines = trace(ADD, TraceFormat::Full);
+    let first: Vec<&str> = lines
+        .iter()
+        .take_while(|line| !line.is_empty())
+        .map(String::as_str)
+        .collect();
+    assert_eq!(
+        first,
+        [
+            "     Program Counter 0000",
+            "      Memory Address 00000000",
+            "     Memory Contents 00011110",
+            "Instruction Register 0000 0000",
+            "        Decoder Step 000",
+            "          A Register 00000000",
+            "        Sum Register 00000000",
+            "               Flags ",
+            "          B Register 00000000",
+            "              Output 0",
+            "         Instruction NOP",
+            "        Control word MemoryAddressIn | CounterOut",
+            "                 Bus 00000000",
+        ]
+    );
+}
+
+#[test]
+fn json_traces_take_an_object_per_cycle() {
+    let lines = trace(ADD, TraceFormat::Json);
+    assert_eq!(lines.len(), 15);
+    assert_eq!(
+        lines[3],
+        concat!(
+            r#"{"cycle":3,"step":3,"instruction":30,"#,
+            r#""control_word":{"raw":65608,"flags":["RamOut","ARegisterIn","NextInstruction"]},"#,
+            r#""bus":14,"modules":{"Program Counter":1,"Memory Address":14,"#,
+            r#""Memory Contents":14,"Instruction Register":30,"Decoder Step":3,"#,
+            r#""A Register":0,"Sum Register":0,"Flags":0,"B Register":0,"Output":0},"#,
+            r#""memory_write":null}"#
+        )
+    );
+}
+
+#[test]
+fn json_traces_show_memory_writes() {
+    let lines = trace("LDI 5\nSTA 15\nHLT\n", TraceFormat::Json);
+    let writes: Vec<(usize, &str)> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.ends_with(r#""memory_write":null}"#))
+        .map(|(cycle, line)| (cycle, &line[line.find("\"memory_write\"").unwrap()..]))
+        .collect();
+    assert_eq!(
+        writes,
+        [(
+            6,
+            r#""memory_write":{"module":"Memory Contents","address":15,"value":5}}"#
+        )]
+    );
+}
+
+#[test]
+fn nothing_is_traced_with_the_none_format() {
+    assert!(trace(ADD, TraceFormat::None).is_empty());
+}